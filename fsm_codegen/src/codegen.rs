@@ -7,10 +7,134 @@ use quote::*;
 
 use itertools::Itertools;
 
+/// Access the region's member of a current state value, e.g. `current_state.1` for the second region.
+pub fn region_state(fsm: &FsmDescription, region: &FsmRegion, current_state: quote::Tokens) -> quote::Tokens {
+    if fsm.has_multiple_regions() {
+        let mut q = current_state;
+        q.append(".");
+        q.append(region.id.to_string());
+        q
+    } else {
+        current_state
+    }
+}
+
+/// Start the timeouts of a state that was just entered.
+fn start_state_timeouts(fsm: &FsmDescription, state: &syn::Ty) -> quote::Tokens {
+    if !fsm.state_timeouts.iter().any(|x| &x.state_ty == state) {
+        return quote! {};
+    }
+
+    let states_path = fsm.get_states_path();
+    let variant = FsmDescription::to_state_variant_name(state);
+    let f = FsmDescription::to_state_field_name(state);
+
+    quote! {
+        self.start_state_timeouts(&#states_path::#variant(std::sync::Arc::clone(&self.states.#f)));
+    }
+}
+
+/// Cancel the pending timeouts of a state that is being exited.
+fn cancel_state_timeouts(fsm: &FsmDescription, state: &syn::Ty) -> quote::Tokens {
+    if !fsm.state_timeouts.iter().any(|x| &x.state_ty == state) {
+        return quote! {};
+    }
+
+    let states_path = fsm.get_states_path();
+    let variant = FsmDescription::to_state_variant_name(state);
+    let f = FsmDescription::to_state_field_name(state);
+
+    quote! {
+        self.cancel_state_timeouts(&#states_path::#variant(std::sync::Arc::clone(&self.states.#f)));
+    }
+}
+
+pub fn build_state_timeouts(fsm: &FsmDescription) -> quote::Tokens {
+    if fsm.state_timeouts.is_empty() {
+        return quote! {};
+    }
+
+    let events_ty = fsm.get_events_ty();
+    let events_path = fsm.get_events_path();
+    let states_ty = fsm.get_states_ty();
+    let states_path = fsm.get_states_path();
+
+    let mut start = quote::Tokens::new();
+    let mut cancel = quote::Tokens::new();
+    let mut cancel_all = quote::Tokens::new();
+    let mut expired = quote::Tokens::new();
+
+    let states: Vec<_> = fsm.state_timeouts.iter().map(|x| &x.state_ty).unique_by(|x| *x).collect();
+    for state in states {
+        let mut s = quote::Tokens::new();
+        let mut c = quote::Tokens::new();
+
+        for (i, timeout) in fsm.state_timeouts.iter().enumerate().filter(|&(_, x)| &x.state_ty == state) {
+            let field = FsmDescription::to_state_timeout_field_name(i);
+            let duration = &timeout.duration_ty;
+            let event = &timeout.event_ty;
+            let event_variant = FsmDescription::to_event_variant_name(event);
+
+            s.append(quote! {
+                {
+                    let queue = std::sync::Arc::clone(&self.queue);
+                    self.#field.lock().unwrap().start(move |entry| async move {
+                        tokio::time::sleep(<#duration as FsmDuration>::duration()).await;
+                        let _ = fsm_enqueue_event(&queue, #events_path::FsmTimeoutEvent(FsmTimeoutEvent { timeout: #i, entry: entry }), 0).await;
+                    });
+                }
+            }.as_str());
+
+            c.append(quote! {
+                self.#field.lock().unwrap().cancel();
+            }.as_str());
+
+            expired.append(quote! {
+                #i if self.#field.lock().unwrap().is_current(timeout.entry) => Some(#events_path::#event_variant(<#event as Default>::default())),
+            }.as_str());
+        }
+
+        let variant = FsmDescription::to_state_variant_name(state);
+        start.append(quote! { #states_path::#variant(_) => { #s }, }.as_str());
+        cancel.append(quote! { #states_path::#variant(_) => { #c }, }.as_str());
+        cancel_all.append(c.as_str());
+    }
+
+    quote! {
+        fn start_state_timeouts(&self, state: &#states_ty) {
+            match state {
+                #start
+                _ => ()
+            }
+        }
+
+        fn cancel_state_timeouts(&self, state: &#states_ty) {
+            match state {
+                #cancel
+                _ => ()
+            }
+        }
+
+        fn cancel_all_state_timeouts(&self) {
+            #cancel_all
+        }
+
+        /// The event of an expired timeout, `None` if its state was exited or entered again since.
+        fn state_timeout_event(&self, timeout: FsmTimeoutEvent) -> Option<#events_ty> {
+            match timeout.timeout {
+                #expired
+                _ => None
+            }
+        }
+    }
+}
+
 pub fn build_state_store(fsm: &FsmDescription) -> quote::Tokens {
     let fsm_name = fsm.get_fsm_ty();
     let impl_suffix = fsm.get_impl_suffix();
+    let where_clause = fsm.get_where_clause();
     let states_store_ty = fsm.get_states_store_ty();
+    let states_store_declaration = fsm.get_states_store_declaration();
     let context_ty = &fsm.context_ty;
 
     let mut retr = quote::Tokens::new();
@@ -20,16 +144,16 @@ pub fn build_state_store(fsm: &FsmDescription) -> quote::Tokens {
     for state in &fsm.get_all_states() {
         let field_name = FsmDescription::to_state_field_name(&state);
         f.append(quote! { #field_name: FsmArc<#state>,  }.as_str());
-        n.append(quote! { #field_name: std::sync::Arc::new(tokio::sync::RwLock::new(#state::new_state(context))), }.as_str());
+        n.append(quote! { #field_name: std::sync::Arc::new(tokio::sync::RwLock::new(<#state>::new_state(context))), }.as_str());
 
         retr.append(quote! {
-            impl #impl_suffix FsmRetrieveState<#state> for #fsm_name {
+            impl #impl_suffix FsmRetrieveState<#state> for #fsm_name #where_clause {
                 fn get_state(&self) -> FsmArc<#state> {
                     std::sync::Arc::clone(&self.states.#field_name)
                 }
             }
 
-            impl #impl_suffix FsmRetrieveState<#state> for #states_store_ty {
+            impl #impl_suffix FsmRetrieveState<#state> for #states_store_ty #where_clause {
                 fn get_state(&self) -> FsmArc<#state> {
                     std::sync::Arc::clone(&self.#field_name)
                 }
@@ -39,19 +163,25 @@ pub fn build_state_store(fsm: &FsmDescription) -> quote::Tokens {
 
     for sub in fsm.get_submachine_types() {
         let field_name = FsmDescription::to_state_sub_started_field_name(sub);
-        f.append(quote! { #field_name: bool, }.as_str());
-        n.append(quote! { #field_name: false, }.as_str());
+        f.append(quote! { #field_name: std::sync::atomic::AtomicBool, }.as_str());
+        n.append(quote! { #field_name: std::sync::atomic::AtomicBool::new(false), }.as_str());
+    }
+
+    if fsm.is_generic() {
+        let phantom_ty = fsm.get_phantom_ty();
+        f.append(quote! { _fsm_ty: #phantom_ty, }.as_str());
+        n.append(quote! { _fsm_ty: std::marker::PhantomData, }.as_str());
     }
 
     let q = quote! {
         #[derive(Debug)]
-        pub struct #states_store_ty {
+        pub struct #states_store_declaration {
             #f
         }
 
-        impl #states_store_ty {
-            pub fn new<'a>(context: &FsmArc<#context_ty>) -> #states_store_ty {
-                #states_store_ty {
+        impl #impl_suffix #states_store_ty #where_clause {
+            pub fn new(context: &FsmArc<#context_ty>) -> Self {
+                Self {
                     #n
                 }
             }
@@ -68,100 +198,319 @@ pub fn build_state_store(fsm: &FsmDescription) -> quote::Tokens {
 pub fn build_enums(fsm: &FsmDescription) -> quote::Tokens {
     let fsm_name = fsm.get_fsm_ty();
     let impl_suffix = fsm.get_impl_suffix();
+    let where_clause = fsm.get_where_clause();
     let events_ty = fsm.get_events_ty();
+    let events_path = fsm.get_events_path();
+    let events_declaration = fsm.get_events_declaration();
     let states_ty = fsm.get_states_ty();
+    let states_path = fsm.get_states_path();
+    let states_declaration = fsm.get_states_declaration();
 
     // events
-    let all_transitions = fsm.get_all_transitions();
-    let events = all_transitions.iter().map(|ref x| &x.event).unique_by(|x| *x);
+    let events = fsm.get_all_events();
 
     let mut events_types = quote::Tokens::new();
     let mut event_traits = quote::Tokens::new();
-    for event in events {
+    let mut event_clone = quote::Tokens::new();
+    let mut event_into_any = quote::Tokens::new();
+    let mut event_from_any = quote::Tokens::new();
+    let mut event_clone_any = quote::Tokens::new();
+    let mut event_field_tys = Vec::new();
+    for event in &events {
         let mut t = quote::Tokens::new();
         event.to_tokens(&mut t);
         if t.as_str() == "NoEvent" || t.as_str() == "FsmErrorEvent" { continue; }
 
-        events_types.append(quote! { #event(#event), }.as_str());
+        let event_variant = FsmDescription::to_event_variant_name(event);
+        events_types.append(quote! { #event_variant(#event), }.as_str());
+        event_clone.append(quote! { #events_path::#event_variant(e) => #events_path::#event_variant(e.clone()), }.as_str());
+        event_into_any.append(quote! { #events_path::#event_variant(e) => Ok(Box::new(e)), }.as_str());
+        event_clone_any.append(quote! { #events_path::#event_variant(e) => Some(Box::new(e.clone())), }.as_str());
+        event_from_any.append(quote! {
+            let event = match event.downcast::<#event>() {
+                Ok(e) => return Ok(#events_path::#event_variant(*e)),
+                Err(event) => event
+            };
+        }.as_str());
+        event_field_tys.push(t);
         event_traits.append(quote! {
-            impl From<#event> for #events_ty {
+            impl #impl_suffix From<#event> for #events_ty #where_clause {
                 fn from(ev: #event) -> Self {
-                    #events_ty::#event(ev)
+                    #events_path::#event_variant(ev)
                 }
             }
         }.as_str());
     }
+
+    // the events of the submachines, for `HierarchicalDispatch`
+    let mut sub_events_from_any = quote::Tokens::new();
+    if fsm.dispatch.is_some() {
+        for sub in fsm.get_submachine_types() {
+            let variant = FsmDescription::to_state_variant_name(sub);
+
+            if fsm.snapshot {
+                events_types.append("#[serde(skip)]");
+            }
+            events_types.append(quote! { #variant(<#sub as Fsm>::E), }.as_str());
+            event_clone.append(quote! { #events_path::#variant(e) => #events_path::#variant(e.clone()), }.as_str());
+            event_into_any.append(quote! { #events_path::#variant(e) => e.into_any().map_err(#events_path::#variant), }.as_str());
+            sub_events_from_any.append(quote! {
+                let event = match <<#sub as Fsm>::E as FsmEventsAny>::from_any(event) {
+                    Ok(e) => return Ok(#events_path::#variant(e)),
+                    Err(event) => event
+                };
+            }.as_str());
+            event_traits.append(quote! {
+                impl #impl_suffix From<<#sub as Fsm>::E> for #events_ty #where_clause {
+                    fn from(ev: <#sub as Fsm>::E) -> Self {
+                        #events_path::#variant(ev)
+                    }
+                }
+            }.as_str());
+        }
+    }
+
+    // the expired timeouts, see `FsmStateTimer`
+    if !fsm.state_timeouts.is_empty() {
+        events_types.append(quote! { FsmTimeoutEvent(FsmTimeoutEvent), }.as_str());
+        event_clone.append(quote! { #events_path::FsmTimeoutEvent(e) => #events_path::FsmTimeoutEvent(e.clone()), }.as_str());
+    }
+
+    if fsm.is_generic() && fsm.snapshot {
+        events_types.append("#[serde(skip)]");
+    }
+    events_types.append(fsm.get_phantom_variant().as_str());
     events_types.append(quote! { FsmErrorEvent(FsmErrorEvent), }.as_str());
     events_types.append(quote! { NoEvent(NoEvent) }.as_str());
 
+    let mut event_names = quote::Tokens::new();
+    let mut event_variants = quote::Tokens::new();
+    for event in &events {
+        let event_variant = FsmDescription::to_event_variant_name(event);
+        let mut t = quote::Tokens::new();
+        event.to_tokens(&mut t);
+        if t.as_str() == "NoEvent" || t.as_str() == "FsmErrorEvent" { continue; }
+
+        let event_name = ty_to_name(event);
+        event_names.append(quote! { #events_path::#event_variant(_) => #event_name, }.as_str());
+        event_variants.append(quote! { #event_name, }.as_str());
+    }
+    event_names.append(quote! { #events_path::FsmErrorEvent(_) => "FsmErrorEvent", }.as_str());
+    event_names.append(quote! { #events_path::NoEvent(_) => "NoEvent", }.as_str());
+    if !fsm.state_timeouts.is_empty() {
+        event_names.append(quote! { #events_path::FsmTimeoutEvent(_) => "FsmTimeoutEvent", }.as_str());
+    }
+    if fsm.dispatch.is_some() {
+        for sub in fsm.get_submachine_types() {
+            let variant = FsmDescription::to_state_variant_name(sub);
+            event_names.append(quote! { #events_path::#variant(e) => e.name(), }.as_str());
+        }
+    }
+    event_names.append(fsm.get_phantom_arm(&events_path).as_str());
+
+    // derives on a generic enum would bound the generic parameters, these impls bound the events instead
     let mut derive_events = quote::Tokens::new();
-    if fsm.copyable_events {
+    let mut events_impls = quote::Tokens::new();
+    // the events are cloned for the submachines of a machine with `HierarchicalDispatch`
+    let is_clone = fsm.copyable_events || fsm.snapshot || fsm.dispatch.is_some();
+    if fsm.copyable_events && !fsm.is_generic() {
         derive_events.append("#[derive(Copy, Clone)]");
+    } else if is_clone && !fsm.is_generic() {
+        derive_events.append("#[derive(Clone)]");
+    } else {
+        // the other machines' events are `Clone` when their event types are, e.g. as the submachine of a machine
+        // with `HierarchicalDispatch`. The `for<'e>` defers the bounds on concrete types to the uses of the impl.
+        let clone_where_clause = if is_clone {
+            where_clause.clone()
+        } else {
+            let mut predicates = quote::Tokens::new();
+            for ty in &event_field_tys {
+                predicates.append(format!("for<'e> {}: Clone,", ty.as_str()));
+            }
+            fsm.get_where_clause_with(predicates)
+        };
+
+        let phantom_arm = fsm.get_phantom_arm(&events_path);
+        events_impls.append(quote! {
+            impl #impl_suffix Clone for #events_ty #clone_where_clause {
+                fn clone(&self) -> Self {
+                    match self {
+                        #event_clone
+                        #events_path::FsmErrorEvent(e) => #events_path::FsmErrorEvent(e.clone()),
+                        #events_path::NoEvent(e) => #events_path::NoEvent(e.clone()),
+                        #phantom_arm
+                    }
+                }
+            }
+        }.as_str());
+
+        if fsm.copyable_events {
+            events_impls.append(quote! { impl #impl_suffix Copy for #events_ty #where_clause {} }.as_str());
+        }
+    }
+    if fsm.snapshot {
+        derive_events.append("#[derive(serde::Serialize, serde::Deserialize)]");
+        derive_events.append(fsm.get_serde_bound(&event_field_tys).as_str());
     }
 
+    let events_clone_any = if fsm.dispatch.is_some() {
+        quote! {
+            /// A copy of the event without its enum, for the submachines.
+            fn clone_into_any(&self) -> Option<Box<dyn std::any::Any + Send>> {
+                match self {
+                    #event_clone_any
+                    _ => None
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // the conversions through `Any` only exist for the generic parameters that make the events `'static`
+    let events_any_where_clause = if fsm.is_generic() {
+        let mut predicates = quote::Tokens::new();
+        for ty in &event_field_tys {
+            predicates.append(format!("{}: 'static,", ty.as_str()));
+        }
+        fsm.get_where_clause_with(predicates)
+    } else {
+        quote! {}
+    };
+
     // states
     let mut state_types = quote::Tokens::new();
+    let mut state_traits = quote::Tokens::new();
+    let mut state_clone = quote::Tokens::new();
     let mut state_display = quote::Tokens::new();
     let mut state_name = quote::Tokens::new();
     let mut state_is_submachine = quote::Tokens::new();
 
     for state in &fsm.get_all_states() {
-        state_types.append(quote! { #state(FsmArc<#state>), }.as_str());
-        state_display.append(quote! { #states_ty::#state(_) => { write!(f, stringify!(#state)) } }.as_str());
+        let variant = FsmDescription::to_state_variant_name(state);
+        let name = ty_to_name(state);
+
+        state_types.append(quote! { #variant(FsmArc<#state>), }.as_str());
+        state_traits.append(quote! {
+            impl #impl_suffix From<FsmArc<#state>> for #states_ty #where_clause {
+                fn from(state: FsmArc<#state>) -> Self {
+                    #states_path::#variant(state)
+                }
+            }
+        }.as_str());
+        state_clone.append(quote! { #states_path::#variant(s) => #states_path::#variant(std::sync::Arc::clone(s)), }.as_str());
+        state_display.append(quote! { #states_path::#variant(_) => { f.write_str(#name) } }.as_str());
 
         if fsm.is_submachine(&state) {
-            state_name.append(quote! { #states_ty::#state(s) => {
+            state_name.append(quote! { #states_path::#variant(s) => {
                 let sub = s.read().await;
                 let sub = sub.get_current_state().await;
 
-                format!("{}::{}", stringify!(#state), sub.name().await)
+                format!("{}::{}", #name, sub.name().await)
              } }.as_str());
-            state_is_submachine.append(quote! { #states_ty::#state(_) => { true } }.as_str());
+            state_is_submachine.append(quote! { #states_path::#variant(_) => { true } }.as_str());
         } else {
-            state_name.append(quote! { #states_ty::#state(_) => { format!(stringify!(#state)) } }.as_str());
-            state_is_submachine.append(quote! { #states_ty::#state(_) => { false } }.as_str());
+            state_name.append(quote! { #states_path::#variant(_) => { #name.to_string() } }.as_str());
+            state_is_submachine.append(quote! { #states_path::#variant(_) => { false } }.as_str());
         }
     }
 
+    state_types.append(fsm.get_phantom_variant().as_str());
+    let phantom_arm = fsm.get_phantom_arm(&states_path);
+
     quote! {
         // Events
         #[derive(Debug)]
         #derive_events
-        pub enum #events_ty {
+        pub enum #events_declaration {
             #events_types
         }
 
-        impl #impl_suffix FsmEvents<#fsm_name> for #events_ty {
+        #events_impls
+
+        impl #impl_suffix FsmEventsAny for #events_ty #events_any_where_clause {
+            fn into_any(self) -> Result<Box<dyn std::any::Any + Send>, Self> {
+                match self {
+                    #event_into_any
+                    event => Err(event)
+                }
+            }
+
+            fn from_any(event: Box<dyn std::any::Any + Send>) -> Result<Self, Box<dyn std::any::Any + Send>> {
+                let event = match Self::from_own_any(event) {
+                    Ok(event) => return Ok(event),
+                    Err(event) => event
+                };
+                #sub_events_from_any
+                Err(event)
+            }
+        }
+
+        impl #impl_suffix #events_ty #events_any_where_clause {
+            /// The event if the machine accepts it itself, without the events of its submachines.
+            fn from_own_any(event: Box<dyn std::any::Any + Send>) -> Result<Self, Box<dyn std::any::Any + Send>> {
+                #event_from_any
+                Err(event)
+            }
+
+            #events_clone_any
+        }
+
+        impl #impl_suffix FsmEvents<#fsm_name> for #events_ty #where_clause {
             fn new_no_event() -> Self {
-                #events_ty::NoEvent(NoEvent)
+                #events_path::NoEvent(NoEvent)
             }
 
             fn new_error_event(error: FsmTransitionError) -> Self {
-                #events_ty::FsmErrorEvent(FsmErrorEvent(error))
+                #events_path::FsmErrorEvent(FsmErrorEvent(error))
+            }
+
+            fn name(&self) -> &'static str {
+                match self {
+                    #event_names
+                }
+            }
+
+            fn variants() -> &'static [&'static str] {
+                &[#event_variants]
             }
         }
 
         #event_traits
 
         // States
-        #[derive(Clone, Debug)]
-        pub enum #states_ty {
+        #[derive(Debug)]
+        pub enum #states_declaration {
             #state_types
         }
 
-        impl std::fmt::Display for #states_ty {
+        #state_traits
+
+        // not derived, the generic parameters don't have to be `Clone`
+        impl #impl_suffix Clone for #states_ty #where_clause {
+            fn clone(&self) -> Self {
+                match self {
+                    #state_clone
+                    #phantom_arm
+                }
+            }
+        }
+
+        impl #impl_suffix std::fmt::Display for #states_ty #where_clause {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 match self {
                     #state_display
+                    #phantom_arm
                 }
             }
         }
 
         #[async_trait]
-        impl FsmRetrieveStateName for #states_ty {
+        impl #impl_suffix FsmRetrieveStateName for #states_ty #where_clause {
             async fn name(&self) -> String {
                 match self {
                     #state_name
+                    #phantom_arm
                 }
             }
         }
@@ -170,8 +519,10 @@ pub fn build_enums(fsm: &FsmDescription) -> quote::Tokens {
 
 pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
     let fsm_ty = fsm.get_fsm_ty();
+    let machine_name = &fsm.name;
     let events_ty = fsm.get_events_ty();
-    let states_ty = fsm.get_states_ty();
+    let events_path = fsm.get_events_path();
+    let states_path = fsm.get_states_path();
     let error_state_ty = fsm.get_error_state_ty();
 
     // states
@@ -197,28 +548,71 @@ pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
         let mut q = quote::Tokens::new();
 
         for state in &region.get_all_states() {
-            let t: Vec<_> = region.transitions.iter().filter(|&x| &x.source_state == state).collect();
+            let mut t: Vec<_> = region.transitions.iter().filter(|&x| &x.source_state == state).collect();
 
             if t.len() == 0 { continue; }
 
+            // `AnyEvent` transitions are matched last
+            t.sort_by_key(|x| x.is_any_event());
+
+            let state_variant = FsmDescription::to_state_variant_name(state);
             let mut tq = quote::Tokens::new();
 
+            // the events that `AnyEvent` doesn't match, unless an unguarded transition already matched them. The
+            // events of guarded transitions fall back to `AnyEvent` when every guard failed.
+            let mut any_event_fallback = quote::Tokens::new();
+            if t.iter().any(|x| x.is_any_event()) {
+                let mut excluded = vec![syn::parse_type("NoEvent").unwrap(), syn::parse_type("FsmErrorEvent").unwrap()];
+                excluded.extend(region.deferred_events.iter().filter(|x| &x.state_ty == state).map(|x| x.event_ty.clone()));
+
+                for event in excluded.iter().unique() {
+                    if t.iter().any(|x| &x.event == event && !x.is_guarded()) { continue; }
+
+                    let event_variant = FsmDescription::to_event_variant_name(event);
+                    any_event_fallback.append(quote! {
+                        match (&current_state, &event) {
+                            (&#states_path::#state_variant(_), &#events_path::#event_variant(_)) => break Err(FsmError::NoTransition),
+                            (_, _) => ()
+                        }
+                    }.as_str());
+                }
+            }
+
             for transition in t {
 
                 let event = &transition.event;
+                let event_pattern = if transition.is_any_event() {
+                    tq.append(any_event_fallback.as_str());
+                    any_event_fallback = quote::Tokens::new();
+
+                    quote! { _ }
+                } else {
+                    let event_variant = FsmDescription::to_event_variant_name(event);
+                    quote! { &#events_path::#event_variant(_) }
+                };
                 let target_state = &transition.target_state;
+                let target_state_variant = FsmDescription::to_state_variant_name(target_state);
                 let action = &transition.action;
 
 
                 let source_state_field = FsmDescription::to_state_field_name(&state);
                 let target_state_field = FsmDescription::to_state_field_name(&target_state);
 
-                let action_call = if transition.has_same_states() {
+                let action_call = if let Some(ref entry_point) = transition.entry_point {
+                    let entry_point_ty = &entry_point.ty;
+                    quote! {
+                        <#action as FsmAction<#fsm_ty, #state, #entry_point_ty>>::action(
+                            &event_ctx,
+                            &*self.states.#source_state_field.read().await,
+                            &<#entry_point_ty>::default()
+                        ).await
+                    }
+                } else if transition.has_same_states() {
                     quote! {
                         <#action as FsmActionSelf<#fsm_ty, #state>>::action(
                             &event_ctx,
                             &*self.states.#source_state_field.read().await
-                        ).await;
+                        ).await
                     }
                 } else {
                     quote! {
@@ -226,24 +620,10 @@ pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
                             &event_ctx,
                             &*self.states.#source_state_field.read().await,
                             &*self.states.#target_state_field.read().await
-                        ).await;
+                        ).await
                     }
                 };
 
-                let mut sub_init = quote! { };
-                if fsm.is_submachine(&target_state) {
-                    let is_shallow = fsm.shallow_history_events.iter().find(|ref x| &x.event_ty == event && &x.target_state_ty == target_state).is_some();
-
-                    if is_shallow == false {
-                        sub_init = quote! {
-                            {
-                                self.states.#target_state_field.read().await.start().await;
-                                just_called_start = true;
-                            }
-                        };
-                    }
-                }
-
                 let mut sub_state_exit = quote! {};
                 let mut sub_state_entry = quote! {};
 
@@ -251,32 +631,73 @@ pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
                     sub_state_exit = quote! {
                         {
                             let state = self.states.#source_state_field.read().await;
-                            let s = state.get_current_state().await;
 
-                            if let Err(error) = state.call_on_exit(s).await {
+                            if let Err(error) = state.exit_current_states().await {
                                 break Err(error);
                             }
                         }
                     };
                 }
 
-
-                if fsm.is_submachine(&target_state) {
+                if let Some(ref entry_point) = transition.entry_point {
+                    let entry_state = &entry_point.state_ty;
                     sub_state_entry = quote! {
                         {
                             let state = self.states.#target_state_field.read().await;
-                            let s = state.get_current_state().await;
+                            let entry: FsmArc<#entry_state> = state.get_state();
+                            state.start_at(vec![entry.into()]).await;
+                        }
+                    };
+                } else if !transition.fork_targets.is_empty() {
+                    let mut entries = quote::Tokens::new();
+                    for fork_target in &transition.fork_targets {
+                        entries.append(quote! {
+                            {
+                                let entry: FsmArc<#fork_target> = state.get_state();
+                                entry.into()
+                            },
+                        }.as_str());
+                    }
 
-                            if let Err(error) = state.call_on_entry(s).await {
-                                break Err(error);
+                    sub_state_entry = quote! {
+                        {
+                            let state = self.states.#target_state_field.read().await;
+                            state.start_at(vec![#entries]).await;
+                        }
+                    };
+                } else if fsm.is_submachine(target_state) {
+                    sub_state_entry = match fsm.get_history_type(event, target_state) {
+                        None => quote! {
+                            {
+                                self.states.#target_state_field.read().await.start().await;
+                            }
+                        },
+                        Some(history_type) => {
+                            let history = match history_type {
+                                HistoryType::Shallow => quote! { FsmHistory::Shallow },
+                                HistoryType::Deep => quote! { FsmHistory::Deep }
+                            };
+
+                            quote! {
+                                {
+                                    let state = self.states.#target_state_field.read().await;
+
+                                    if let Err(error) = state.resume(#history).await {
+                                        break Err(error);
+                                    }
+                                }
                             }
                         }
                     };
                 }
 
 
+                let source_timeouts = cancel_state_timeouts(fsm, state);
+                let target_timeouts = start_state_timeouts(fsm, target_state);
+
                 let mut state_exit = quote! {
                     self.inspection.on_state_exit(&current_state, &event_ctx).await;
+                    #source_timeouts
 
                     {
                         let state = self.states.#source_state_field.read().await;
@@ -288,7 +709,7 @@ pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
 
                 let mut state_entry = quote! {
                     self.inspection.on_state_entry(
-                        &#states_ty::#target_state(std::sync::Arc::clone(&self.states.#target_state_field)),
+                        &#states_path::#target_state_variant(std::sync::Arc::clone(&self.states.#target_state_field)),
                         &event_ctx
                     ).await;
 
@@ -298,6 +719,8 @@ pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
                             break Err(error);
                         }
                     }
+
+                    #target_timeouts
                 };
 
                 if transition.transition_type == TransitionType::Internal {
@@ -305,29 +728,63 @@ pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
                     state_entry = quote! {};
                 }
 
+                // a guard is only awaited when its state and event match and no earlier transition fired
                 let guard = if let Some(ref guard_ty) = transition.guard {
                     quote! {
-                        if #guard_ty::guard(&event_ctx, &self.states)
+                        if <#guard_ty as FsmGuardAsync<#fsm_ty, #state>>::guard(
+                            &event_ctx,
+                            &*self.states.#source_state_field.read().await,
+                            &self.states
+                        ).await
+                    }
+                } else if !transition.join_sources.is_empty() {
+                    let mut active = quote::Tokens::new();
+                    for (i, join_source) in transition.join_sources.iter().enumerate() {
+                        if i > 0 {
+                            active.append("&&");
+                        }
+                        active.append(quote! {
+                            ({
+                                let state: FsmArc<#join_source> = sub.get_state();
+                                sub.is_state_active(&state.into()).await
+                            })
+                        }.as_str());
+                    }
+
+                    quote! {
+                        if {
+                            let sub = self.states.#source_state_field.read().await;
+                            #active
+                        }
                     }
                 } else {
                     quote! {}
                 };
 
-                let state_set = if fsm.has_multiple_regions() {
-                    let mut q = quote! { self.state. };
-                    q.append(&region.id.to_string());
-                    q
+                let state_set = region_state(fsm, region, quote! { (*state_) });
+
+                let region_id = region.id;
+                let target_state_name = ty_to_name(target_state);
+                let notify = if transition.transition_type == TransitionType::Internal {
+                    quote! {}
                 } else {
-                    quote! { self.state }
+                    quote! {
+                        self.notifications.notify(FsmTransitionNotification {
+                            machine: #machine_name,
+                            region: #region_id,
+                            source_state: current_state.to_string(),
+                            target_state: #target_state_name.to_string(),
+                            event: event.name()
+                        });
+                    }
                 };
 
 
-                let s = quote! {
-                    (&#states_ty::#state(_), &#events_ty::#event(_)) #guard => {
+                let transition_body = quote! {
                         let result: FsmTransitionResult<()> = loop {
                             self.inspection.on_transition(
                                 &current_state,
-                                &#states_ty::#target_state(std::sync::Arc::clone(&self.states.#target_state_field)),
+                                &#states_path::#target_state_variant(std::sync::Arc::clone(&self.states.#target_state_field)),
                                 &event_ctx
                             ).await;
 
@@ -336,32 +793,38 @@ pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
 
                             self.inspection.on_action(&current_state, &event_ctx).await;
 
-                            {
-                                #action_call
+                            if let Err(error) = #action_call {
+                                break Err(error);
                             }
 
                             {
-                                let mut state_ = #state_set.write().await;
-                                *state_ = #states_ty::#target_state(std::sync::Arc::clone(&self.states.#target_state_field));
+                                let mut state_ = self.state.write().await;
+                                #state_set = #states_path::#target_state_variant(std::sync::Arc::clone(&self.states.#target_state_field));
                             }
 
                             event_ctx.current_state = self.get_current_state().await;
 
                             #state_entry
-
-                            let mut just_called_start = false;
-                            #sub_init
-                            if just_called_start == false {
-                                #sub_state_entry
-                            }
+                            #sub_state_entry
+                            #notify
 
                             break Ok(())
                         };
 
                         #error_handling
 
-                        Ok(())
-                    },
+                        break Ok(());
+                };
+
+                let s = quote! {
+                    match (&current_state, &event) {
+                        (&#states_path::#state_variant(_), #event_pattern) => {
+                            #guard {
+                                #transition_body
+                            }
+                        },
+                        (_, _) => ()
+                    }
                 };
 
                 tq.append(s.as_str());
@@ -370,44 +833,45 @@ pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
             q.append(tq.as_str());
         }
 
-        let (region_state_field, result) = if fsm.has_multiple_regions() {
-            let mut q = quote! { self.state. };
-            q.append(&region.id.to_string());
-
+        let region_current_state = region_state(fsm, region, quote! { fsm_read_state(&self.state).await });
+        let region_id = region.id;
+        let result = if fsm.has_multiple_regions() {
             let mut r = quote::Tokens::new();
             r.append(&format!("r{}", region.id));
-            (q, r)
+            r
         } else {
-            (quote! { self.state }, quote! { res })
+            quote! { res }
         };
 
         event_dispatch.append(quote! {
 
-            let current_state = fsm_read_state(&#region_state_field).await;
-            let #result = match (&current_state, &event) {
+            event_ctx.region = #region_id;
+            let current_state = #region_current_state;
+            let #result = loop {
                 #q
-                (_, _) => Err(FsmError::NoTransition)
+                break Err(FsmError::NoTransition);
             };
 
         }.as_str());
 
 
         for interrupted_state in &region.interrupt_states {
-            let s_ty = &interrupted_state.interrupt_state_ty;
+            let s_ty = FsmDescription::to_state_variant_name(&interrupted_state.interrupt_state_ty);
 
             let mut m = quote::Tokens::new();
             for e_ty in &interrupted_state.resume_event_ty {
+                let e_ty = FsmDescription::to_event_variant_name(e_ty);
                 m.append(quote! {
-                    (#states_ty::#s_ty(_), &#events_ty::#e_ty(_)) => {
+                    (#states_path::#s_ty(_), &#events_path::#e_ty(_)) => {
                         whitelisted_event = true;
                     },
                 }.as_str());
             }
 
             interrupted_states.append(quote! {
-                match (fsm_read_state(&#region_state_field).await, &event) {
+                match (#region_current_state, &event) {
                     #m
-                    (#states_ty::#s_ty(_), _) => {
+                    (#states_path::#s_ty(_), _) => {
                         is_interrupted = true;
                     },
                     (_, _) => ()
@@ -426,15 +890,16 @@ pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
         let mut r = quote::Tokens::new();
 
         for region in &fsm.regions {
-            let mut q = quote! { self.state. };
-            q.append(&region.id.to_string());
+            let q = region_state(fsm, region, quote! { fsm_read_state(&self.state).await });
+            let region_id = region.id;
 
             r = quote::Tokens::new();
             r.append(&format!("r{}", region.id));
 
             return_result.append(quote! {
-                if #r == Err(FsmError::NoTransition) {
-                    self.inspection.on_no_transition(&fsm_read_state(&#q).await, &event_ctx).await;
+                event_ctx.region = #region_id;
+                if #r == Err(FsmError::NoTransition) && !is_deferred {
+                    self.inspection.on_no_transition(&#q, &event_ctx).await;
                 }
                 if res.is_none() && #r.is_ok() {
                     res = Some(#r);
@@ -450,41 +915,195 @@ pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
         }.as_str());
     } else {
         return_result = quote! {
-            if res == Err(FsmError::NoTransition) {
+            if res == Err(FsmError::NoTransition) && !is_deferred {
                 self.inspection.on_no_transition(&fsm_read_state(&self.state).await, &event_ctx).await;
             }
         }
     }
 
-    let f = quote! {
-        async fn process_event(&self, event: #events_ty) -> Result<(), FsmError> {
-            if self.execute_queue_pre {
-                self.execute_queued_events().await;
-            }
+    let (is_deferred, deferred_dispatch) = if fsm.has_deferred_events() {
+        (quote! { self.is_event_deferred(&event).await }, quote! {
+            let res = match res {
+                Err(FsmError::NoTransition) if is_deferred => {
+                    let _ = self.deferred_queue.write().await.enqueue_event(event);
+                    Ok(())
+                },
+                Ok(()) => self.process_deferred_events().await,
+                res => res
+            };
+        })
+    } else {
+        (quote! { false }, quote! {})
+    };
 
-            let res = {
-                let mut event_ctx = EventContext {
-                    event: &event,
-                    queue: std::sync::Arc::clone(&self.queue),
-                    context: std::sync::Arc::clone(&self.context),
-                    current_state: self.get_current_state().await
-                };
+    let mut deferred_events = quote::Tokens::new();
+    for region in &fsm.regions {
+        let current_state = region_state(fsm, region, quote! { current_state });
 
-                {
-                    let mut is_interrupted = false;
-                    let mut whitelisted_event = false;
-                    #interrupted_states
-                    if is_interrupted && whitelisted_event == false {
-                        return Err(FsmError::Interrupted);
+        let mut m = quote::Tokens::new();
+        for deferred_event in &region.deferred_events {
+            let s_ty = FsmDescription::to_state_variant_name(&deferred_event.state_ty);
+            let e_ty = FsmDescription::to_event_variant_name(&deferred_event.event_ty);
+
+            m.append(quote! {
+                (&#states_path::#s_ty(_), &#events_path::#e_ty(_)) => { return true; },
+            }.as_str());
+        }
+
+        if !region.deferred_events.is_empty() {
+            deferred_events.append(quote! {
+                match (&#current_state, event) {
+                    #m
+                    (_, _) => ()
+                }
+            }.as_str());
+        }
+    }
+
+    let completion = if fsm.is_completable() {
+        quote! {
+            // only the event that moves the machine into its final states completes it
+            if res.is_ok() {
+                let completed = self.is_completed().await;
+                if completed && !self.completed.swap(completed, std::sync::atomic::Ordering::SeqCst) {
+                    let hook = self.completion_hook.lock().unwrap().clone();
+                    if let Some(hook) = hook {
+                        hook.call().await;
                     }
+                } else if !completed {
+                    self.completed.store(false, std::sync::atomic::Ordering::SeqCst);
                 }
+            }
+        }
+    } else {
+        quote! {}
+    };
 
-                #event_dispatch
+    let is_event_deferred = if fsm.has_deferred_events() {
+        quote! {
+            async fn is_event_deferred(&self, event: &#events_ty) -> bool {
+                let current_state = self.get_current_state().await;
+                #deferred_events
+
+                false
+            }
+        }
+    } else {
+        quote! {
+            async fn is_event_deferred(&self, _event: &#events_ty) -> bool {
+                false
+            }
+        }
+    };
 
-                #return_result
+    let own_dispatch = match fsm.dispatch {
+        None => quote! {
+            #event_dispatch
+            #return_result
+        },
+        Some(DispatchPriority::InnerFirst) => quote! {
+            let res = if is_deferred {
+                Err(FsmError::NoTransition)
+            } else {
+                self.dispatch_to_submachines(&event).await
+            };
 
-                res
+            let res = match res {
+                Err(FsmError::NoTransition) => {
+                    #event_dispatch
+                    #return_result
+                    res
+                },
+                res => res
+            };
+        },
+        Some(DispatchPriority::OuterFirst) => quote! {
+            #event_dispatch
+            #return_result
+
+            let res = match res {
+                Err(FsmError::NoTransition) if !is_deferred => self.dispatch_to_submachines(&event).await,
+                res => res
             };
+        }
+    };
+
+    let submachine_event = if fsm.dispatch.is_some() {
+        quote! {
+            let event = Self::lift_submachine_event(event);
+        }
+    } else {
+        quote! {}
+    };
+
+    let process = quote! {
+        #submachine_event
+
+        let is_deferred = #is_deferred;
+
+        let res = {
+            let mut event_ctx = EventContext {
+                event: &event,
+                queue: std::sync::Arc::clone(&self.queue),
+                context: std::sync::Arc::clone(&self.context),
+                current_state: self.get_current_state().await,
+                region: 0,
+                inspection: Default::default()
+            };
+
+            self.inspection.on_event(&event_ctx).await;
+
+            {
+                let mut is_interrupted = false;
+                let mut whitelisted_event = false;
+                #interrupted_states
+                if is_interrupted && whitelisted_event == false {
+                    self.inspection.on_event_processed(&event_ctx, &Err(FsmError::Interrupted)).await;
+                    return Err(FsmError::Interrupted);
+                }
+            }
+
+            #own_dispatch
+
+            self.inspection.on_event_processed(&event_ctx, &res).await;
+
+            res
+        };
+
+        #deferred_dispatch
+        #completion
+
+        res
+    };
+
+    // a timeout is dropped when its state was exited before it was dequeued
+    let process = if !fsm.state_timeouts.is_empty() {
+        quote! {
+            let event = match event {
+                #events_path::FsmTimeoutEvent(timeout) => self.state_timeout_event(timeout),
+                event => Some(event)
+            };
+
+            let res = match event {
+                Some(event) => { #process },
+                None => Ok(())
+            };
+        }
+    } else {
+        quote! {
+            let res = { #process };
+        }
+    };
+
+    let f = quote! {
+        #is_event_deferred
+
+        async fn process_event(&self, event: #events_ty) -> Result<(), FsmError> {
+            if self.execute_queue_pre {
+                self.execute_queued_events().await;
+            }
+
+            #process
 
             if self.execute_queue_post {
                 self.execute_queued_events().await;
@@ -499,19 +1118,116 @@ pub fn build_state_transitions(fsm: &FsmDescription) -> quote::Tokens {
 }
 
 
+/// Forwards the events to the active submachines of a machine with `HierarchicalDispatch`.
+fn build_dispatch_to_submachines(fsm: &FsmDescription) -> quote::Tokens {
+    if fsm.dispatch.is_none() {
+        return quote! {};
+    }
+
+    let events_ty = fsm.get_events_ty();
+    let states_path = fsm.get_states_path();
+
+    let events_path = fsm.get_events_path();
+
+    // the wrapped events of a submachine become the machine's own events when it accepts them itself
+    let mut sub_events = quote::Tokens::new();
+    for sub in fsm.get_submachine_types() {
+        let variant = FsmDescription::to_state_variant_name(sub);
+        sub_events.append(quote! {
+            #events_path::#variant(e) => match e.into_any() {
+                Ok(any) => match #events_path::from_own_any(any) {
+                    Ok(event) => event,
+                    Err(any) => #events_path::#variant(<<#sub as Fsm>::E as FsmEventsAny>::from_any(any).expect("The event converts back into its submachine's events"))
+                },
+                Err(e) => #events_path::#variant(e)
+            },
+        }.as_str());
+    }
+
+    // every region with an active submachine gets the event, the first error is returned, then `Ok` over `NoTransition`
+    let mut regions = quote::Tokens::new();
+    for region in &fsm.regions {
+        if region.submachines.is_empty() {
+            continue;
+        }
+
+        let mut m = quote::Tokens::new();
+        for sub in &region.submachines {
+            let variant = FsmDescription::to_state_variant_name(sub);
+            m.append(quote! {
+                #states_path::#variant(s) => match event {
+                    #events_path::#variant(e) => s.read().await.process_event(e.clone()).await,
+                    event => match event.clone_into_any().map(<<#sub as Fsm>::E as FsmEventsAny>::from_any) {
+                        Some(Ok(event)) => s.read().await.process_event(event).await,
+                        _ => Err(FsmError::NoTransition)
+                    }
+                },
+            }.as_str());
+        }
+
+        let current_state = region_state(fsm, region, quote! { current_state });
+        regions.append(quote! {
+            let r = match &#current_state {
+                #m
+                _ => Err(FsmError::NoTransition)
+            };
+            res = match (res, r) {
+                (Err(FsmError::NoTransition), r) => r,
+                (Ok(()), Err(FsmError::NoTransition)) => Ok(()),
+                (Ok(()), r) => r,
+                (res, _) => res
+            };
+        }.as_str());
+    }
+
+    quote! {
+        /// A wrapped event of a submachine that the machine accepts itself is dispatched as its own event.
+        fn lift_submachine_event(event: #events_ty) -> #events_ty {
+            match event {
+                #sub_events
+                event => event
+            }
+        }
+
+        async fn dispatch_to_submachines(&self, event: &#events_ty) -> Result<(), FsmError> {
+            let current_state = self.get_current_state().await;
+            let mut res = Err(FsmError::NoTransition);
+            #regions
+
+            res
+        }
+    }
+}
+
 pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
     let fsm_ty = fsm.get_fsm_ty();
-    let fsm_ty_inline = fsm.get_fsm_ty_inline();
+    let fsm_declaration = fsm.get_fsm_declaration();
     let impl_suffix = fsm.get_impl_suffix();
+    let where_clause = fsm.get_where_clause();
     let events_ty = fsm.get_events_ty();
+    let events_path = fsm.get_events_path();
     let states_ty = fsm.get_states_ty();
+    let states_path = fsm.get_states_path();
     let current_state_ty = fsm.get_current_state_ty();
     let states_store_ty = fsm.get_states_store_ty();
     let error_state_ty = fsm.get_error_state_ty();
     let inspection_ty = fsm.get_inspection_ty();
+    let queue_ty = fsm.get_queue_ty();
     let ctx = &fsm.context_ty;
 
     let transitions = build_state_transitions(fsm);
+    let dispatch_to_submachines = build_dispatch_to_submachines(fsm);
+
+    let mut state_active = quote::Tokens::new();
+    for region in &fsm.regions {
+        let current_state = region_state(fsm, region, quote! { current_state });
+        if region.id > 0 {
+            state_active.append("||");
+        }
+        state_active.append(quote! {
+            std::mem::discriminant(&#current_state) == std::mem::discriminant(state)
+        }.as_str());
+    }
 
     let mut start = quote! {
         {
@@ -519,12 +1235,12 @@ pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
             *state = Self::new_initial_state(&self.states);
         }
 
-        let mut event = <Self::E as FsmEvents<Self>>::new_no_event();
+        let mut event = <#events_ty as FsmEvents<Self>>::new_no_event();
     };
 
     let start_error_handling = if error_state_ty.is_some() {
         quote! {
-            event = <Self::E as FsmEvents<Self>>::new_error_event(error);
+            event = <#events_ty as FsmEvents<Self>>::new_error_event(error);
          }
     } else {
         quote! { panic!("The on_entry action of the initial state returned an error: {:?}", error); }
@@ -532,6 +1248,7 @@ pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
 
     for region in &fsm.regions {
         let initial_state = &region.initial_state_ty;
+        let initial_state_variant = FsmDescription::to_state_variant_name(initial_state);
         let initial_state_field = FsmDescription::to_state_field_name(initial_state);
 
         let sub_start = if fsm.is_submachine(&initial_state) {
@@ -539,18 +1256,64 @@ pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
         } else {
             quote! { }
         };
+        let initial_timeouts = start_state_timeouts(fsm, initial_state);
+        let region_id = region.id;
+
+        // an entry state of the region replaces its initial state
+        let mut entry_states = quote::Tokens::new();
+        let mut entry_sub_start = quote::Tokens::new();
+        let mut region_states = vec![initial_state.clone()];
+        region_states.extend(region.get_all_states().into_iter().filter(|x| x != initial_state));
+        for state in &region_states {
+            let variant = FsmDescription::to_state_variant_name(state);
+            entry_states.append(quote! { s @ #states_path::#variant(_) => Some(s.clone()), }.as_str());
+
+            if fsm.is_submachine(state) {
+                let f = FsmDescription::to_state_field_name(state);
+                entry_sub_start.append(quote! {
+                    #states_path::#variant(_) => self.states.#f.read().await.start().await,
+                }.as_str());
+            }
+        }
+        let entry_state_set = region_state(fsm, region, quote! { (*state_) });
+        if !region.submachines.is_empty() {
+            entry_sub_start = quote! {
+                match entry_state {
+                    #entry_sub_start
+                    _ => ()
+                }
+            };
+        }
 
         start.append(quote! {
-            {
+            let entry_state: Option<#states_ty> = entries.iter().find_map(|s| match s {
+                #entry_states
+                _ => None
+            });
+
+            if let Some(entry_state) = entry_state {
+                {
+                    let mut state_ = self.state.write().await;
+                    #entry_state_set = entry_state.clone();
+                }
+
+                if let Err(error) = self.call_on_entry(entry_state.clone()).await {
+                    #start_error_handling
+                }
+
+                #entry_sub_start
+            } else {
                 let event_ctx = EventContext {
                     event: &event,
                     queue: std::sync::Arc::clone(&self.queue),
                     context: std::sync::Arc::clone(&self.context),
-                    current_state: self.get_current_state().await
+                    current_state: self.get_current_state().await,
+                    region: #region_id,
+                    inspection: Default::default()
                 };
 
                 self.inspection.on_state_entry(
-                    &#states_ty::#initial_state(std::sync::Arc::clone(&self.states.#initial_state_field)),
+                    &#states_path::#initial_state_variant(std::sync::Arc::clone(&self.states.#initial_state_field)),
                     &event_ctx
                 ).await;
 
@@ -561,6 +1324,7 @@ pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
                     }
                 }
 
+                #initial_timeouts
                 #sub_start
             }
         }.as_str());
@@ -602,14 +1366,19 @@ pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
         };
     }
 
+    // an exit that failed didn't get to cancel the timers of its state
+    if !fsm.state_timeouts.is_empty() {
+        stop.append(quote! {
+            self.cancel_all_state_timeouts();
+        }.as_str());
+    }
+
     let sub_on_handlers = build_on_handlers(fsm);
 
     let initial_state = {
         let st: Vec<_> = fsm.regions.iter().map(|x| {
-            let mut t = quote! { #states_ty:: };
-            x.initial_state_ty.to_tokens(&mut t);
-            t.append(quote! { (states.get_state()) });
-            t
+            let variant = FsmDescription::to_state_variant_name(&x.initial_state_ty);
+            quote! { #states_path::#variant(states.get_state()) }
         }).collect();
 
         quote! {
@@ -633,6 +1402,7 @@ pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
         #[cfg(feature = "viz_docs")]
         () =>
         {
+            let fsm_ty_inline = fsm.get_fsm_ty_inline();
             let js_file = &format!("fsm_viz_{}.js", ty_to_string(&fsm_ty_inline));
             let viz_html_file = &format!("fsm_viz_{}.html", ty_to_string(&fsm_ty_inline));
             let js_file_js = {
@@ -688,8 +1458,51 @@ pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
         }
     };
 
-    let error_state = if let Some(error_state_name) = &error_state_ty {
-        quote! { Some(#states_ty::#error_state_name(states.get_state())) }
+    let mut timeout_fields = quote::Tokens::new();
+    let mut timeout_fields_new = quote::Tokens::new();
+    for (i, _) in fsm.state_timeouts.iter().enumerate() {
+        let field = FsmDescription::to_state_timeout_field_name(i);
+        timeout_fields.append(quote! { #field: std::sync::Mutex<FsmStateTimer>, }.as_str());
+        timeout_fields_new.append(quote! { #field: std::sync::Mutex::new(FsmStateTimer::default()), }.as_str());
+    }
+    let state_timeouts = build_state_timeouts(fsm);
+
+    // submachines queue their completion event in this machine
+    let mut completion_hooks = quote::Tokens::new();
+    for sub in &fsm.get_completed_submachines() {
+        let f = FsmDescription::to_state_field_name(sub);
+        let event_variant = FsmDescription::to_event_variant_name(&FsmDescription::to_completed_event(sub));
+
+        completion_hooks.append(quote! {
+            {
+                let queue = std::sync::Arc::clone(&queue);
+                states.#f.try_read().expect("The submachine is locked during construction").set_completion_hook(FsmCompletionHook::new(move || {
+                    let queue = std::sync::Arc::clone(&queue);
+                    async move {
+                        let _ = fsm_enqueue_event(&queue, #events_path::#event_variant(Completed::new()), 0).await;
+                    }
+                }));
+            }
+        }.as_str());
+    }
+
+    // submachines forward their transitions to the subscribers of this machine
+    let mut forward_transitions = quote::Tokens::new();
+    let mut forward_sub_transitions = quote::Tokens::new();
+    for sub in fsm.get_submachine_types() {
+        let f = FsmDescription::to_state_field_name(sub);
+
+        forward_transitions.append(quote! {
+            states.#f.try_read().expect("The submachine is locked during construction").forward_transitions(notifications.nested_sender());
+        }.as_str());
+        forward_sub_transitions.append(quote! {
+            self.states.#f.try_read().expect("The submachine is locked while forwarding its transitions").forward_transitions(sender.clone());
+        }.as_str());
+    }
+
+    let error_state = if let Some(error_state) = &error_state_ty {
+        let error_state_variant = FsmDescription::to_state_variant_name(error_state);
+        quote! { Some(#states_path::#error_state_variant(states.get_state())) }
     } else {
         quote! { None }
     };
@@ -697,20 +1510,25 @@ pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
     quote! {
         #main_struct_docs
         #[derive(Debug)]
-        pub struct #fsm_ty {
+        pub struct #fsm_declaration {
 	        state: FsmArc<#current_state_ty>,
             states: #states_store_ty,
 	        context: FsmArc<#ctx>,
             queue: FsmArc<dyn FsmEventQueue<#fsm_ty>>,
+            deferred_queue: FsmArc<dyn FsmEventQueue<#fsm_ty>>,
             inspection: #inspection_ty,
             error_state: Option<#states_ty>,
+            completion_hook: std::sync::Mutex<Option<FsmCompletionHook>>,
+            completed: std::sync::atomic::AtomicBool,
+            notifications: FsmNotifications,
+            #timeout_fields
 
             pub execute_queue_pre: bool,
             pub execute_queue_post: bool
         }
 
         #[async_trait]
-        impl #impl_suffix Fsm for #fsm_ty {
+        impl #impl_suffix Fsm for #fsm_ty #where_clause {
             type E = #events_ty;
             type S = #states_ty;
             type SS = #states_store_ty;
@@ -718,17 +1536,31 @@ pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
             type CS = #current_state_ty;
 
             fn new(context: &FsmArc<Self::C>) -> Self {
-                let states = #states_store_ty::new(&context);
+                Self::new_with_queue(context, std::sync::Arc::new(tokio::sync::RwLock::new(<#queue_ty>::new())))
+            }
+
+            fn new_with_queue(context: &FsmArc<Self::C>, queue: FsmArc<dyn FsmEventQueue<Self>>) -> Self {
+                let states = <#states_store_ty>::new(&context);
                 let state = Self::new_initial_state(&states);
                 let error_state = #error_state;
 
-                #fsm_ty_inline {
+                #completion_hooks
+
+                let notifications = FsmNotifications::default();
+                #forward_transitions
+
+                Self {
                     state: std::sync::Arc::new(tokio::sync::RwLock::new(state)),
                     states,
                     inspection: <#inspection_ty>::new_from_context(&context),
                     error_state,
+                    completion_hook: std::sync::Mutex::new(None),
+                    completed: std::sync::atomic::AtomicBool::new(false),
+                    notifications,
+                    #timeout_fields_new
                     context: std::sync::Arc::clone(context),
-                    queue: std::sync::Arc::new(tokio::sync::RwLock::new(FsmEventQueueVec::new())),
+                    queue,
+                    deferred_queue: std::sync::Arc::new(tokio::sync::RwLock::new(FsmEventQueueVec::new())),
 
                     execute_queue_pre: true,
                     execute_queue_post: true
@@ -736,7 +1568,11 @@ pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
             }
 
             async fn start(&self) {
-                #start
+                self.start_with_entries(Vec::new()).await;
+            }
+
+            async fn start_at(&self, states: Vec<#states_ty>) {
+                self.start_with_entries(states).await;
             }
 
 	        async fn stop(&self) {
@@ -747,93 +1583,261 @@ pub fn build_main_struct(fsm: &FsmDescription) -> quote::Tokens {
                 &self.queue
             }
 
+            fn get_deferred_queue(&self) -> &FsmArc<dyn FsmEventQueue<Self>> {
+                &self.deferred_queue
+            }
+
             async fn get_current_state(&self) -> #current_state_ty {
                 fsm_read_state(&self.state).await
             }
 
+            async fn is_state_active(&self, state: &#states_ty) -> bool {
+                let current_state = self.get_current_state().await;
+                #state_active
+            }
+
             fn get_states(&self) -> &#states_store_ty {
                 &self.states
             }
 
+            fn get_context(&self) -> &FsmArc<#ctx> {
+                &self.context
+            }
+
+            fn subscribe(&self) -> tokio::sync::broadcast::Receiver<FsmTransitionNotification> {
+                self.notifications.subscribe()
+            }
+
+            fn subscribe_with_submachines(&self) -> tokio::sync::broadcast::Receiver<FsmTransitionNotification> {
+                self.notifications.subscribe_with_submachines()
+            }
+
+            fn forward_transitions(&self, sender: tokio::sync::broadcast::Sender<FsmTransitionNotification>) {
+                #forward_sub_transitions
+                self.notifications.forward_to(sender);
+            }
+
             #sub_on_handlers
             #transitions
         }
 
-        impl #impl_suffix #fsm_ty {
+        impl #impl_suffix #fsm_ty #where_clause {
+            async fn start_with_entries(&self, entries: Vec<#states_ty>) {
+                self.completed.store(false, std::sync::atomic::Ordering::SeqCst);
+                #start
+            }
+
             fn new_initial_state(states: &#states_store_ty) -> #current_state_ty {
                #initial_state
             }
 
-            pub fn get_context(&self) -> &FsmArc<#ctx> {
-                &self.context
+            pub fn get_inspection(&self) -> &#inspection_ty {
+                &self.inspection
             }
 
             pub fn module_path() -> &'static str {
                  module_path!()
             }
 
+            #state_timeouts
+
+            #dispatch_to_submachines
+
             #viz
         }
     }
 }
 
 pub fn build_on_handlers(fsm: &FsmDescription) -> quote::Tokens {
-    let events_ty = fsm.get_events_ty();
+    let events_path = fsm.get_events_path();
     let states_ty = fsm.get_states_ty();
+    let states_path = fsm.get_states_path();
 
     let mut on_entry = quote::Tokens::new();
     let mut on_exit = quote::Tokens::new();
 
     for state in &fsm.get_all_states() {
+        let variant = FsmDescription::to_state_variant_name(state);
+        let f = FsmDescription::to_state_field_name(&state);
 
-        if fsm.is_submachine(&state) { continue; }
+        let entry_timeouts = start_state_timeouts(fsm, state);
+        let exit_timeouts = cancel_state_timeouts(fsm, state);
 
-        let f = FsmDescription::to_state_field_name(&state);
+        // the region that the state belongs to, for the inspection
+        let region_id = fsm.regions.iter()
+            .find(|r| &r.initial_state_ty == state || r.get_all_states().contains(state))
+            .map_or(0, |r| r.id);
 
         on_entry.append(quote!{
-            #states_ty::#state(_) => {
+            #states_path::#variant(_) => {
+                event_ctx.region = #region_id;
                 self.inspection.on_state_entry(&state, &event_ctx).await;
-                self.states.#f.read().await.on_entry(&event_ctx).await
+                self.states.#f.read().await.on_entry(&event_ctx).await?;
+                #entry_timeouts
+
+                Ok(())
             },
         }.as_str());
 
+        // submachines exit their own active states before the submachine state itself
+        let sub_exit = if fsm.is_submachine(state) {
+            quote! {
+                self.states.#f.read().await.exit_current_states().await?;
+            }
+        } else {
+            quote! {}
+        };
+
         on_exit.append(quote!{
-            #states_ty::#state(_) => {
+            #states_path::#variant(_) => {
+                #sub_exit
+                event_ctx.region = #region_id;
                 self.inspection.on_state_exit(&state, &event_ctx).await;
+                #exit_timeouts
                 self.states.#f.read().await.on_exit(&event_ctx).await
             },
         }.as_str());
     }
 
+    let mut exit_current = quote::Tokens::new();
+    let mut resume = quote::Tokens::new();
+
+    for region in &fsm.regions {
+        let current_state = region_state(fsm, region, quote! { current_state });
+
+        exit_current.append(quote! {
+            self.call_on_exit(#current_state.clone()).await?;
+        }.as_str());
+
+        let mut sub_resume = quote::Tokens::new();
+        for sub in &region.submachines {
+            let variant = FsmDescription::to_state_variant_name(sub);
+            let f = FsmDescription::to_state_field_name(sub);
+
+            sub_resume.append(quote! {
+                #states_path::#variant(_) => {
+                    let sub = self.states.#f.read().await;
+                    match history {
+                        FsmHistory::Shallow => sub.start().await,
+                        FsmHistory::Deep => sub.resume(FsmHistory::Deep).await?
+                    }
+                },
+            }.as_str());
+        }
+
+        resume.append(quote! {
+            self.call_on_entry(#current_state.clone()).await?;
+        }.as_str());
+
+        if !region.submachines.is_empty() {
+            resume.append(quote! {
+                match #current_state {
+                    #sub_resume
+                    _ => ()
+                }
+            }.as_str());
+        }
+    }
+
+    let is_completed = if fsm.is_completable() {
+        let mut regions = quote::Tokens::new();
+
+        for region in &fsm.regions {
+            let current_state = region_state(fsm, region, quote! { current_state });
+
+            let mut m = quote::Tokens::new();
+            for final_state in &region.final_states {
+                let variant = FsmDescription::to_state_variant_name(final_state);
+                m.append(quote! {
+                    #states_path::#variant(_) => (),
+                }.as_str());
+            }
+
+            regions.append(quote! {
+                match #current_state {
+                    #m
+                    _ => { return false; }
+                }
+            }.as_str());
+        }
+
+        quote! {
+            async fn is_completed(&self) -> bool {
+                let current_state = self.get_current_state().await;
+                #regions
+
+                true
+            }
+        }
+    } else {
+        quote! {
+            async fn is_completed(&self) -> bool {
+                false
+            }
+        }
+    };
+
+    let phantom_arm = fsm.get_phantom_arm(&states_path);
+
+    let history_arg = if !fsm.get_submachine_types().is_empty() {
+        quote! { history }
+    } else {
+        quote! { _history }
+    };
+
     quote! {
         async fn call_on_entry(&self, state: #states_ty) -> FsmTransitionResult<()> {
-            let no = #events_ty::new_no_event();
-            let event_ctx = EventContext {
+            let no = #events_path::new_no_event();
+            let mut event_ctx = EventContext {
                 event: &no,
                 queue: std::sync::Arc::clone(&self.queue),
                 context: std::sync::Arc::clone(&self.context),
-                current_state: self.get_current_state().await
+                current_state: self.get_current_state().await,
+                region: 0,
+                inspection: Default::default()
             };
 
             match state {
                 #on_entry
-                _ => Ok(())
+                #phantom_arm
             }
         }
 
         async fn call_on_exit(&self, state: #states_ty) -> FsmTransitionResult<()> {
-            let no = #events_ty::new_no_event();
-            let event_ctx = EventContext {
+            let no = #events_path::new_no_event();
+            let mut event_ctx = EventContext {
                 event: &no,
                 queue: std::sync::Arc::clone(&self.queue),
                 context: std::sync::Arc::clone(&self.context),
-                current_state: self.get_current_state().await
+                current_state: self.get_current_state().await,
+                region: 0,
+                inspection: Default::default()
             };
 
             match state {
                 #on_exit
-                _ => Ok(())
+                #phantom_arm
             }
         }
+
+        #is_completed
+
+        fn set_completion_hook(&self, hook: FsmCompletionHook) {
+            *self.completion_hook.lock().unwrap() = Some(hook);
+        }
+
+        async fn exit_current_states(&self) -> FsmTransitionResult<()> {
+            let current_state = self.get_current_state().await;
+            #exit_current
+
+            Ok(())
+        }
+
+        async fn resume(&self, #history_arg: FsmHistory) -> FsmTransitionResult<()> {
+            let current_state = self.get_current_state().await;
+            #resume
+
+            Ok(())
+        }
     }
 }
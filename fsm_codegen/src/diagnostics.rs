@@ -0,0 +1,85 @@
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// A problem with the definition of a machine, reported on the offending field of the definition tuple.
+#[derive(Debug)]
+pub struct FsmDefinitionError {
+    /// The index of the field in the definition tuple, `None` for the definition as a whole.
+    pub field: Option<usize>,
+    pub message: String
+}
+
+impl FsmDefinitionError {
+    pub fn new(field: Option<usize>, message: String) -> Self {
+        FsmDefinitionError { field, message }
+    }
+
+    pub fn to_compile_error(&self, input: TokenStream) -> TokenStream {
+        let (start, end) = definition_spans(input, self.field);
+        compile_error(start, end, &self.message)
+    }
+}
+
+/// A `compile_error!` that spans from the `start` to the `end` token.
+pub fn compile_error(start: Span, end: Span, message: &str) -> TokenStream {
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(start);
+
+    let mut message = Literal::string(message);
+    message.set_span(end);
+
+    let mut group = Group::new(Delimiter::Brace, TokenTree::Literal(message).into());
+    group.set_span(end);
+
+    vec![
+        TokenTree::Ident(Ident::new("compile_error", start)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(group)
+    ].into_iter().collect()
+}
+
+/// The first and the last span of a field of the definition tuple, or the name of the definition.
+fn definition_spans(input: TokenStream, field: Option<usize>) -> (Span, Span) {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+
+    let name = tokens.iter()
+        .skip_while(|t| !matches!(t, TokenTree::Ident(i) if i.to_string() == "struct"))
+        .nth(1)
+        .map(|t| t.span())
+        .unwrap_or_else(Span::call_site);
+
+    let body = tokens.iter().find_map(|t| match t {
+        TokenTree::Group(g) if g.delimiter() == Delimiter::Parenthesis => Some(g.stream()),
+        _ => None
+    });
+
+    let (field, body) = match (field, body) {
+        (Some(field), Some(body)) => (field, body),
+        _ => return (name, name)
+    };
+
+    // split the fields on the commas outside of the generic parameters
+    let mut fields: Vec<Vec<Span>> = vec![Vec::new()];
+    let mut depth = 0;
+    for token in body {
+        if let TokenTree::Punct(ref p) = token {
+            match p.as_char() {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                ',' if depth == 0 => {
+                    fields.push(Vec::new());
+                    continue;
+                },
+                _ => ()
+            }
+        }
+
+        fields.last_mut().unwrap().push(token.span());
+    }
+
+    match fields.get(field) {
+        Some(spans) if !spans.is_empty() => (spans[0], spans[spans.len() - 1]),
+        _ => (name, name)
+    }
+}
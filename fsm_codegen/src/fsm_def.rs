@@ -9,18 +9,24 @@ use itertools::Itertools;
 pub struct FsmDescription {
     pub name: String,
     pub name_ident: syn::Ident,
-    pub lifetimes: Vec<syn::Lifetime>,
+    /// The generic parameters of the definition, shared by all the generated types.
+    pub generics: syn::Generics,
 
     pub error_state_ty: Option<syn::Ty>,
 
     pub submachines: Vec<syn::Ty>,
-    pub shallow_history_events: Vec<ShallowHistoryEvent>,
+    pub history_events: Vec<HistoryEvent>,
+    pub state_timeouts: Vec<FsmStateTimeout>,
 
     pub regions: Vec<FsmRegion>,
     pub context_ty: syn::Ty,
     pub inspect_ty: Option<syn::Ty>,
+    pub queue_ty: Option<syn::Ty>,
 
-    pub copyable_events: bool
+    pub copyable_events: bool,
+    pub snapshot: bool,
+    /// Forward the events to the active submachines, see `HierarchicalDispatch`.
+    pub dispatch: Option<DispatchPriority>
 }
 
 #[derive(Debug, Clone)]
@@ -29,13 +35,38 @@ pub struct FsmInterruptState {
     pub resume_event_ty: Vec<syn::Ty>
 }
 
+#[derive(Debug, Clone)]
+pub struct FsmStateTimeout {
+    pub state_ty: syn::Ty,
+    pub duration_ty: syn::Ty,
+    pub event_ty: syn::Ty,
+    /// The field of the definition tuple that declares the timeout.
+    pub field: usize
+}
+
+/// A transition target that enters a submachine in one of its states.
+#[derive(Debug, Clone)]
+pub struct FsmEntryPoint {
+    /// The `EntryPoint<Machine, Sub, State>` type as written in the transition.
+    pub ty: syn::Ty,
+    pub state_ty: syn::Ty
+}
+
+#[derive(Debug, Clone)]
+pub struct FsmDeferredEvent {
+    pub state_ty: syn::Ty,
+    pub event_ty: syn::Ty
+}
+
 #[derive(Debug)]
 pub struct FsmRegion {
     pub submachines: Vec<syn::Ty>,
     pub id: usize,
     pub transitions: Vec<TransitionEntry>,
     pub initial_state_ty: syn::Ty,
-    pub interrupt_states: Vec<FsmInterruptState>
+    pub interrupt_states: Vec<FsmInterruptState>,
+    pub deferred_events: Vec<FsmDeferredEvent>,
+    pub final_states: Vec<syn::Ty>
 }
 
 impl FsmRegion {
@@ -56,19 +87,29 @@ impl FsmRegion {
 }
 
 
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum DispatchPriority {
+    InnerFirst,
+    OuterFirst
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum HistoryType {
+    Shallow,
+    Deep
+}
+
 #[derive(Debug)]
-pub struct ShallowHistoryEvent {
+pub struct HistoryEvent {
     pub event_ty: syn::Ty,
-    pub target_state_ty: syn::Ty
+    pub target_state_ty: syn::Ty,
+    pub history_type: HistoryType
 }
 
-impl ShallowHistoryEvent {
+impl HistoryEvent {
     #[allow(dead_code)]
     pub fn get_field_name(&self) -> syn::Ty {
-        let mut t = quote::Tokens::new();
-        self.target_state_ty.to_tokens(&mut t);
-
-        syn::parse_type(&format!("history_{}", t.as_str())).unwrap()
+        syn::parse_type(&format!("history_{}", ty_to_variant_string(&self.target_state_ty))).unwrap()
     }
 
     #[allow(dead_code)]
@@ -82,32 +123,143 @@ impl ShallowHistoryEvent {
 
 impl FsmDescription {
     pub fn get_fsm_ty(&self) -> syn::Ty {
-        if self.lifetimes.len() > 0 {
-            syn::parse_type(&format!("{}<{}>", &self.name, self.lifetimes[0].ident.as_ref())).unwrap()
-        } else {
-            syn::parse_type(&self.name).unwrap()
-        }
+        self.to_generic_ty(&self.name)
     }
 
+    #[cfg(any(feature = "viz", feature = "viz_docs"))]
     pub fn get_fsm_ty_inline(&self) -> syn::Ty {
         syn::parse_type(&self.name).unwrap()
     }
 
+    pub fn is_generic(&self) -> bool {
+        !self.generics.lifetimes.is_empty() || !self.generics.ty_params.is_empty()
+    }
+
+    /// The generic parameters with their bounds, e.g. `<D: Driver>`.
     pub fn get_impl_suffix(&self) -> quote::Tokens {
-        if self.lifetimes.len() == 0 {
-            quote! {}
+        let (impl_generics, _, _) = self.generics.split_for_impl();
+        quote! { #impl_generics }
+    }
+
+    pub fn get_where_clause(&self) -> quote::Tokens {
+        let (_, _, where_clause) = self.generics.split_for_impl();
+        quote! { #where_clause }
+    }
+
+    /// The where-clause of the definition with an extra predicate.
+    pub fn get_where_clause_with(&self, predicate: quote::Tokens) -> quote::Tokens {
+        let mut t = quote::Tokens::new();
+        if self.generics.where_clause.predicates.is_empty() {
+            t.append("where");
         } else {
-            let l = &self.lifetimes[0];
-            quote! { <#l> }
+            t.append(self.get_where_clause().as_str());
+            t.append(",");
+        }
+        t.append(predicate.as_str());
+        t
+    }
+
+    /// `Name<D>`, a generated type with the generic parameters of the definition.
+    fn to_generic_ty(&self, name: &str) -> syn::Ty {
+        let (_, ty_generics, _) = self.generics.split_for_impl();
+        syn::parse_type(&format!("{}{}", name, quote! { #ty_generics }.as_str())).unwrap()
+    }
+
+    /// `Name::<D>`, a generated type in expressions and patterns.
+    fn to_generic_path(&self, name: &str) -> quote::Tokens {
+        let (_, ty_generics, _) = self.generics.split_for_impl();
+        let mut t = quote::Tokens::new();
+        t.append(name);
+        if self.is_generic() {
+            t.append("::");
+            ty_generics.to_tokens(&mut t);
+        }
+        t
+    }
+
+    /// `Name<D: Driver> where ...`, the declaration of a generated struct or enum.
+    fn to_generic_declaration(&self, name: &str) -> quote::Tokens {
+        let mut t = quote::Tokens::new();
+        t.append(name);
+        t.append(self.get_impl_suffix().as_str());
+        t.append(self.get_where_clause().as_str());
+        t
+    }
+
+    /// Marks the generic parameters as used by the generated types that might not use them otherwise.
+    pub fn get_phantom_ty(&self) -> quote::Tokens {
+        let lifetimes = self.generics.lifetimes.iter().map(|l| {
+            let l = &l.lifetime;
+            quote! { &#l () }
+        });
+        let ty_params = self.generics.ty_params.iter().map(|t| {
+            let t = &t.ident;
+            quote! { #t }
+        });
+        let params: Vec<_> = lifetimes.chain(ty_params).collect();
+
+        quote! { std::marker::PhantomData<fn() -> (#(#params,)*)> }
+    }
+
+    /// The hidden variant of the generated enums of a generic machine, it can't be constructed.
+    pub fn get_phantom_variant(&self) -> quote::Tokens {
+        if !self.is_generic() {
+            return quote! {};
+        }
+
+        let phantom_ty = self.get_phantom_ty();
+        quote! {
+            #[doc(hidden)]
+            __FsmPhantom(std::convert::Infallible, #phantom_ty),
         }
     }
 
+    /// The match arm of the hidden variant, for the exhaustive matches on the generated enums.
+    pub fn get_phantom_arm(&self, path: &quote::Tokens) -> quote::Tokens {
+        if !self.is_generic() {
+            return quote! {};
+        }
+
+        quote! { #path::__FsmPhantom(..) => unreachable!(), }
+    }
+
+    /// Bounds the serde impls of a generated type on its field types, the generic parameters don't have to be serializable.
+    pub fn get_serde_bound(&self, field_tys: &[quote::Tokens]) -> quote::Tokens {
+        if !self.is_generic() {
+            return quote! {};
+        }
+
+        let serialize = field_tys.iter().map(|t| format!("{}: serde::Serialize", t.as_str())).join(", ");
+        let deserialize = field_tys.iter().map(|t| format!("{}: serde::Deserialize<'de>", t.as_str())).join(", ");
+        quote! { #[serde(bound(serialize = #serialize, deserialize = #deserialize))] }
+    }
+
+    pub fn get_fsm_declaration(&self) -> quote::Tokens {
+        self.to_generic_declaration(&self.name)
+    }
+
     pub fn get_events_ty(&self) -> syn::Ty {
-        syn::parse_type(&format!("{}Events", self.name)).unwrap()
+        self.to_generic_ty(&format!("{}Events", self.name))
+    }
+
+    pub fn get_events_path(&self) -> quote::Tokens {
+        self.to_generic_path(&format!("{}Events", self.name))
+    }
+
+    pub fn get_events_declaration(&self) -> quote::Tokens {
+        self.to_generic_declaration(&format!("{}Events", self.name))
     }
 
     pub fn get_states_ty(&self) -> syn::Ty {
-        syn::parse_type(&format!("{}States", self.name)).unwrap()
+        self.to_generic_ty(&format!("{}States", self.name))
+    }
+
+    pub fn get_states_path(&self) -> quote::Tokens {
+        self.to_generic_path(&format!("{}States", self.name))
+    }
+
+    pub fn get_states_declaration(&self) -> quote::Tokens {
+        self.to_generic_declaration(&format!("{}States", self.name))
     }
 
     pub fn get_current_state_ty(&self) -> syn::Ty {
@@ -124,7 +276,35 @@ impl FsmDescription {
     }
 
     pub fn get_states_store_ty(&self) -> syn::Ty {
-        syn::parse_type(&format!("{}StatesStore", self.name)).unwrap()
+        self.to_generic_ty(&format!("{}StatesStore", self.name))
+    }
+
+    pub fn get_states_store_declaration(&self) -> quote::Tokens {
+        self.to_generic_declaration(&format!("{}StatesStore", self.name))
+    }
+
+    pub fn get_snapshot_ty(&self) -> syn::Ty {
+        self.to_generic_ty(&format!("{}Snapshot", self.name))
+    }
+
+    pub fn get_snapshot_path(&self) -> quote::Tokens {
+        self.to_generic_path(&format!("{}Snapshot", self.name))
+    }
+
+    pub fn get_snapshot_declaration(&self) -> quote::Tokens {
+        self.to_generic_declaration(&format!("{}Snapshot", self.name))
+    }
+
+    pub fn get_states_snapshot_ty(&self) -> syn::Ty {
+        self.to_generic_ty(&format!("{}StatesSnapshot", self.name))
+    }
+
+    pub fn get_states_snapshot_path(&self) -> quote::Tokens {
+        self.to_generic_path(&format!("{}StatesSnapshot", self.name))
+    }
+
+    pub fn get_states_snapshot_declaration(&self) -> quote::Tokens {
+        self.to_generic_declaration(&format!("{}StatesSnapshot", self.name))
     }
 
     #[allow(dead_code)]
@@ -134,7 +314,7 @@ impl FsmDescription {
 
     #[allow(dead_code)]
     pub fn get_history_ty(&self) -> syn::Ty {
-        if self.shallow_history_events.len() == 0 {
+        if self.history_events.is_empty() {
             syn::parse_type("()").unwrap()
         } else {
             syn::parse_type(&format!("{}History", self.name)).unwrap()
@@ -151,6 +331,16 @@ impl FsmDescription {
         }
     }
 
+    pub fn get_queue_ty(&self) -> syn::Ty {
+        if let Some(ref ty) = self.queue_ty {
+            ty.clone()
+        } else {
+            let mut t = quote! {};
+            self.get_fsm_ty().to_tokens(&mut t);
+            syn::parse_type(&format!("FsmEventQueueVec<{}>", t.as_str())).unwrap()
+        }
+    }
+
     pub fn get_error_state_ty(&self) -> Option<syn::Ty> {
         self.error_state_ty.clone()
     }
@@ -165,6 +355,13 @@ impl FsmDescription {
         syn::parse_type(&format!("build_viz_docs_{}", self.name)).unwrap()
     }
 
+    /// The history pseudo-state that applies when entering `target_state` on `event`, if any.
+    pub fn get_history_type(&self, event: &syn::Ty, target_state: &syn::Ty) -> Option<HistoryType> {
+        self.history_events.iter()
+            .find(|x| &x.event_ty == event && &x.target_state_ty == target_state)
+            .map(|x| x.history_type)
+    }
+
     pub fn get_submachine_types(&self) -> &[syn::Ty] {
         &self.submachines
     }
@@ -177,6 +374,40 @@ impl FsmDescription {
         self.regions.iter().flat_map(|x| &x.transitions).cloned().collect()
     }
 
+    /// All the events used by transitions, deferrals and timeouts, without duplicates.
+    pub fn get_all_events(&self) -> Vec<syn::Ty> {
+        let transitions = self.get_all_transitions();
+        let deferred_events: Vec<_> = self.regions.iter().flat_map(|x| &x.deferred_events).map(|x| x.event_ty.clone()).collect();
+        let timeout_events: Vec<_> = self.state_timeouts.iter().map(|x| x.event_ty.clone()).collect();
+
+        transitions.iter().filter(|x| !x.is_any_event()).map(|x| &x.event)
+            .chain(deferred_events.iter())
+            .chain(timeout_events.iter())
+            .unique_by(|x| *x).cloned().collect()
+    }
+
+    /// Can the machine complete? Every region needs at least one final state.
+    pub fn is_completable(&self) -> bool {
+        self.regions.iter().all(|x| !x.final_states.is_empty())
+    }
+
+    /// The submachines whose completion event is handled by this machine.
+    pub fn get_completed_submachines(&self) -> Vec<syn::Ty> {
+        let events = self.get_all_events();
+
+        self.submachines.iter()
+            .filter(|sub| events.contains(&Self::to_completed_event(sub)))
+            .cloned().collect()
+    }
+
+    pub fn to_completed_event(submachine: &syn::Ty) -> syn::Ty {
+        syn::parse_type(&format!("Completed<{}>", ty_to_string(submachine))).unwrap()
+    }
+
+    pub fn has_deferred_events(&self) -> bool {
+        self.regions.iter().any(|x| !x.deferred_events.is_empty())
+    }
+
     pub fn get_all_states(&self) -> Vec<syn::Ty> {
         self.get_all_transitions().iter().map(|ref x| &x.source_state)
             .chain(self.get_all_transitions().iter().map(|ref x| &x.target_state))
@@ -191,12 +422,27 @@ impl FsmDescription {
     }
 
     pub fn to_state_field_name(state: &syn::Ty) -> syn::Ty {
-        let t = ty_to_string(state).to_lowercase();
+        let t = ty_to_variant_string(state).to_lowercase();
         syn::parse_type(&t).unwrap()
     }
 
+    /// The variant of the states enum for a state type, `states::Idle` becomes `Idle`.
+    pub fn to_state_variant_name(state: &syn::Ty) -> syn::Ty {
+        syn::parse_type(&ty_to_variant_string(state)).unwrap()
+    }
+
+    /// The variant of the events enum for an event type, generic
+    /// arguments are folded into the name: `Completed<Sub>` becomes `CompletedSub`.
+    pub fn to_event_variant_name(event: &syn::Ty) -> syn::Ty {
+        syn::parse_type(&ty_to_variant_string(event)).unwrap()
+    }
+
+    pub fn to_state_timeout_field_name(index: usize) -> syn::Ty {
+        syn::parse_type(&format!("state_timeout_{}", index)).unwrap()
+    }
+
     pub fn to_state_sub_started_field_name(state: &syn::Ty) -> syn::Ty {
-        let t = &format!("{}_started", ty_to_string(state).to_lowercase());
+        let t = &format!("{}_started", ty_to_variant_string(state).to_lowercase());
         syn::parse_type(&t).unwrap()
     }
 
@@ -208,7 +454,36 @@ impl FsmDescription {
 pub fn ty_to_string(ty: &syn::Ty) -> String {
     let mut t = quote::Tokens::new();
     ty.to_tokens(&mut t);
-    t.as_str().into()
+    t.as_str().replace(" :: ", "::")
+}
+
+/// The name of a type without its module paths, `states::Idle` is `Idle` and
+/// `Completed<crate::Player>` is `Completed<Player>`.
+pub fn ty_to_name(ty: &syn::Ty) -> String {
+    match *ty {
+        syn::Ty::Path(None, ref path) if !path.segments.is_empty() => {
+            let segment = &path.segments[path.segments.len() - 1];
+            let mut name = segment.ident.as_ref().to_string();
+
+            if let syn::PathParameters::AngleBracketed(ref data) = segment.parameters {
+                let params: Vec<_> = data.lifetimes.iter().map(|l| l.ident.as_ref().to_string())
+                    .chain(data.types.iter().map(ty_to_name))
+                    .collect();
+
+                if !params.is_empty() {
+                    name.push_str(&format!("<{}>", params.join(", ")));
+                }
+            }
+
+            name
+        },
+        _ => ty_to_string(ty)
+    }
+}
+
+/// The name of a type as an identifier, the base of the enum variants and the store fields.
+pub fn ty_to_variant_string(ty: &syn::Ty) -> String {
+    ty_to_name(ty).chars().filter(|c| c.is_alphanumeric() || *c == '_').collect()
 }
 
 /// Deconstruct a potential tuple into a vector of types,
@@ -229,7 +504,15 @@ pub struct TransitionEntry {
     pub target_state: syn::Ty,
     pub action: syn::Ty,
     pub transition_type: TransitionType,
-    pub guard: Option<syn::Ty>
+    pub guard: Option<syn::Ty>,
+    /// Enters the submachine target in this state instead of its initial state.
+    pub entry_point: Option<FsmEntryPoint>,
+    /// The states of the submachine target that a `Fork` enters, one for each of its regions.
+    pub fork_targets: Vec<syn::Ty>,
+    /// The states of the submachine source that a `Join` leaves, it fires only when all of them are active.
+    pub join_sources: Vec<syn::Ty>,
+    /// The field of the definition tuple that declares the transition.
+    pub field: Option<usize>
 }
 
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -256,11 +539,47 @@ impl TransitionEntry {
         self.source_state == self.target_state
     }
 
+    /// Does a guard or the states of a `Join` decide whether the transition fires?
+    pub fn is_guarded(&self) -> bool {
+        self.guard.is_some() || !self.join_sources.is_empty()
+    }
+
+    /// Does the transition match every event through `AnyEvent`?
+    pub fn is_any_event(&self) -> bool {
+        ty_to_name(&self.event) == "AnyEvent"
+    }
+
     #[allow(dead_code)]
     pub fn is_anonymous_transition(&self) -> bool {
         self.event == syn::parse_type("NoEvent").unwrap()
     }
 }
 
+impl fmt::Display for TransitionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let source = ty_to_string(&self.source_state);
+        let event = ty_to_string(&self.event);
+        let action = ty_to_string(&self.action);
+
+        let states = |states: &[syn::Ty]| format!("({})", states.iter().map(ty_to_string).collect::<Vec<_>>().join(", "));
+
+        match self.transition_type {
+            TransitionType::Normal if !self.fork_targets.is_empty() =>
+                write!(f, "Fork<{}, {}, {}, {}>", source, event, ty_to_string(&self.target_state), states(&self.fork_targets))?,
+            TransitionType::Normal if !self.join_sources.is_empty() =>
+                write!(f, "Join<{}, {}, {}, {}>", source, states(&self.join_sources), event, ty_to_string(&self.target_state))?,
+            TransitionType::Normal => write!(f, "Transition<{}, {}, {}, {}>", source, event, ty_to_string(&self.target_state), action)?,
+            TransitionType::SelfTransition => write!(f, "TransitionSelf<{}, {}, {}>", source, event, action)?,
+            TransitionType::Internal => write!(f, "TransitionInternal<{}, {}, {}>", source, event, action)?
+        }
+
+        if let Some(ref guard) = self.guard {
+            write!(f, " guarded by {}", ty_to_string(guard))?;
+        }
+
+        Ok(())
+    }
+}
+
 
 
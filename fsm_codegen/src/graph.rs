@@ -1,11 +1,11 @@
 extern crate syn;
 
+use crate::diagnostics::FsmDefinitionError;
 use crate::fsm_def::*;
 
 use petgraph::*;
 use petgraph::visit::*;
 
-use core::panic;
 use std::collections::HashMap;
 
 
@@ -22,13 +22,56 @@ impl fmt::Display for NodeData {
     }
 }
 
-pub fn create_regions(
-    transitions: &Vec<TransitionEntry>,
-    initial_states: &Vec<syn::Ty>,
-    error_state: &Option<syn::Ty>,
-    submachines: &Vec<syn::Ty>,
-    interrupt_states: &Vec<FsmInterruptState>
-) -> Vec<FsmRegion> {
+/// Two unguarded transitions from the same state on the same event make the machine nondeterministic,
+/// only the first one would ever fire. The transitions are tried in the order of the definition, a guarded
+/// transition after an unguarded one would never fire either.
+fn check_conflicting_transitions(transitions: &[TransitionEntry]) -> Result<(), FsmDefinitionError> {
+    let mut unguarded: HashMap<(String, String), &TransitionEntry> = HashMap::new();
+
+    for transition in transitions {
+        let key = (ty_to_string(&transition.source_state), ty_to_string(&transition.event));
+
+        if let Some(first) = unguarded.get(&key) {
+            let message = if !transition.is_guarded() {
+                format!("Conflicting transitions from state {} on event {}: {} and {}. Add a guard to at least one of them.",
+                        key.0, key.1, first, transition)
+            } else {
+                format!("The guarded transition {} never fires, the unguarded transition {} from the same state on the same event \
+                        is tried first. Move the guarded transition before it.", transition, first)
+            };
+
+            return Err(FsmDefinitionError::new(transition.field, message));
+        }
+
+        if !transition.is_guarded() {
+            unguarded.insert(key, transition);
+        }
+    }
+
+    Ok(())
+}
+
+/// The parts of a machine definition that its regions are built from.
+pub struct FsmRegionsDefinition<'a> {
+    pub transitions: &'a [TransitionEntry],
+    pub initial_states: &'a [syn::Ty],
+    pub initial_state_field: Option<usize>,
+    pub error_state: Option<&'a syn::Ty>,
+    pub error_state_field: Option<usize>,
+    pub submachines: &'a [syn::Ty],
+    pub interrupt_states: &'a [FsmInterruptState],
+    pub deferred_events: &'a [FsmDeferredEvent],
+    pub final_states: &'a [syn::Ty]
+}
+
+pub fn create_regions(definition: &FsmRegionsDefinition) -> Result<Vec<FsmRegion>, FsmDefinitionError> {
+    let FsmRegionsDefinition {
+        transitions, initial_states, initial_state_field, error_state, error_state_field,
+        submachines, interrupt_states, deferred_events, final_states
+    } = *definition;
+
+    check_conflicting_transitions(transitions)?;
+
     let mut gr = Graph::new();
     let mut nodes = HashMap::new();
 
@@ -82,7 +125,9 @@ pub fn create_regions(
     for initial_state in initial_states {
         let s = ty_to_string(initial_state);
 
-        let node = nodes.get(&s).expect(&format!("Missing initial state {} in graph?", &s));
+        let node = nodes.get(&s).ok_or_else(|| FsmDefinitionError::new(initial_state_field, format!(
+            "The initial state {} is missing from the graph of states.", s
+        )))?;
 
         let mut dfs = Dfs::new(&gr, *node);
         while let Some(nx) = dfs.next(&gr) {
@@ -98,7 +143,11 @@ pub fn create_regions(
                 target_state: error_state.clone(),
                 action: syn::parse_type("NoAction").unwrap(),
                 transition_type: TransitionType::Normal,
-                guard: None
+                guard: None,
+                entry_point: None,
+                fork_targets: Vec::new(),
+                join_sources: Vec::new(),
+                field: error_state_field
             });
         }
 
@@ -107,41 +156,67 @@ pub fn create_regions(
             id: region_id,
             transitions,
             initial_state_ty: initial_state.clone(),
-            interrupt_states: Vec::new()
+            interrupt_states: Vec::new(),
+            deferred_events: Vec::new(),
+            final_states: Vec::new()
         });
 
         region_id += 1;
     }
 
     if initial_states.len() != region_id {
-        panic!("Mismatch between the length of the state tuple and number of detected regions: {} state tuple length, {} regions", initial_states.len(), region_id);
+        return Err(FsmDefinitionError::new(initial_state_field, format!(
+            "Mismatch between the length of the state tuple and number of detected regions: {} state tuple length, {} regions",
+            initial_states.len(), region_id
+        )));
     }
 
     for node in gr.raw_nodes() {
         if node.weight.region == orphan_region {
-            panic!("Unreachable state: {}", node.weight.state);
+            let state = &node.weight.state;
+            let field = transitions.iter()
+                .find(|t| &ty_to_string(&t.source_state) == state || &ty_to_string(&t.target_state) == state)
+                .and_then(|t| t.field);
+
+            return Err(FsmDefinitionError::new(field, format!(
+                "The state {} can't be reached from the initial state. Add a transition into it, \
+                or add it to the tuple of the InitialState to make it the initial state of its own region.", state
+            )));
         }
     }
 
     for transition in transitions {
         let s = ty_to_string(&transition.source_state);
-        let node = *nodes.get(&s).unwrap();
+        let node = *nodes.get(&s).ok_or_else(|| FsmDefinitionError::new(transition.field, format!(
+            "The source state {} of the transition {} is missing from the graph of states.", s, transition
+        )))?;
         let r_id = gr[node].region;
         let ref mut r = regions[r_id];
         r.transitions.push(transition.clone());
     }
 
     for region in &mut regions {
-        let states = region.get_all_states().clone();
+        // the initial state of a region without transitions isn't one of their states
+        let mut states = region.get_all_states();
+        if !states.contains(&region.initial_state_ty) {
+            states.push(region.initial_state_ty.clone());
+        }
 
         for s in &states {
             if submachines.contains(s) {
                 region.submachines.push(s.clone());
             }
+            if final_states.contains(s) {
+                region.final_states.push(s.clone());
+            }
             let region_interrupted_states = interrupt_states.iter().filter(|x| &x.interrupt_state_ty == s);
             for interrupted_state in region_interrupted_states {
                 region.interrupt_states.push(interrupted_state.clone());
             }
+            let region_deferred_events = deferred_events.iter().filter(|x| &x.state_ty == s);
+            for deferred_event in region_deferred_events {
+                region.deferred_events.push(deferred_event.clone());
+            }
 
             if let Some(error_state) = error_state {
                 if error_state == s || initial_states.contains(s) {
@@ -155,12 +230,16 @@ pub fn create_regions(
                     target_state: error_state.clone(),
                     action: syn::parse_type("NoAction").unwrap(),
                     transition_type: TransitionType::Normal,
-                    guard: None
+                    guard: None,
+                    entry_point: None,
+                    fork_targets: Vec::new(),
+                    join_sources: Vec::new(),
+                    field: error_state_field
                 })
             }
         }
 
     }
 
-    regions
+    Ok(regions)
 }
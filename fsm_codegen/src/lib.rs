@@ -5,7 +5,7 @@ extern crate fsm;
 
 extern crate petgraph;
 
-use proc_macro::TokenStream;
+use proc_macro::{Group, Ident, TokenStream, TokenTree};
 
 extern crate syn;
 
@@ -14,28 +14,90 @@ extern crate quote;
 
 
 mod codegen;
+mod diagnostics;
 mod fsm_def;
 mod parse;
 mod viz;
+mod snapshot;
 mod graph;
+mod table;
 
 use codegen::*;
+use diagnostics::FsmDefinitionError;
 use parse::*;
 use viz::*;
+use snapshot::*;
+use table::*;
 
 
 
 #[proc_macro_derive(Fsm)]
 pub fn derive_fsm(input: TokenStream) -> TokenStream {
-    let ast = syn::parse_macro_input(&input.to_string()).unwrap();
+    build_fsm(input)
+}
+
+/// The transition table syntax, every machine is translated into the definition struct of the derive.
+#[proc_macro]
+pub fn fsm(input: TokenStream) -> TokenStream {
+    let tables = match parse_tables(input) {
+        Ok(tables) => tables,
+        Err(error) => return error.to_compile_error()
+    };
+
+    let mut output = TokenStream::new();
+    for table in tables {
+        output.extend(table.definition.clone());
+        output.extend(build_fsm(table.definition));
+    }
+
+    output
+}
 
-    let desc = parse_description(&ast);
+/// syn 0.10 doesn't parse `crate::` paths, they are parsed under this name and restored in the output.
+const CRATE_PLACEHOLDER: &str = "__fsm_crate";
+
+fn replace_crate_paths(input: TokenStream) -> TokenStream {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+
+    tokens.iter().enumerate().map(|(i, token)| match token {
+        TokenTree::Ident(ident) if ident.to_string() == "crate" => match tokens.get(i + 1) {
+            Some(TokenTree::Punct(p)) if p.as_char() == ':' => TokenTree::Ident(Ident::new(CRATE_PLACEHOLDER, ident.span())),
+            _ => token.clone()
+        },
+        TokenTree::Group(group) => {
+            let mut replaced = Group::new(group.delimiter(), replace_crate_paths(group.stream()));
+            replaced.set_span(group.span());
+            TokenTree::Group(replaced)
+        },
+        _ => token.clone()
+    }).collect()
+}
+
+fn build_fsm(input: TokenStream) -> TokenStream {
+    let ast = match syn::parse_macro_input(&replace_crate_paths(input.clone()).to_string()) {
+        Ok(ast) => ast,
+        // syn 0.10 only reports the input it failed on
+        Err(_) => {
+            let message = "The definition can't be parsed. It has to be a tuple struct of the definition types, \
+                           with types that syn 0.10 understands.".to_string();
+            return FsmDefinitionError::new(None, message).to_compile_error(input);
+        }
+    };
+
+    let desc = match parse_description(&ast) {
+        Ok(desc) => desc,
+        Err(mut error) => {
+            error.message = error.message.replace(CRATE_PLACEHOLDER, "crate");
+            return error.to_compile_error(input);
+        }
+    };
 
     //panic!("fsm: {:?}", fsm);
 
     let enums = build_enums(&desc);
     let main = build_main_struct(&desc);
     let state_store = build_state_store(&desc);
+    let snapshot = build_snapshot(&desc);
 
     let viz_test = build_test_viz_build(&desc);
 
@@ -43,6 +105,7 @@ pub fn derive_fsm(input: TokenStream) -> TokenStream {
         #enums
         #state_store
         #main
+        #snapshot
 
         #viz_test
     };
@@ -52,8 +115,7 @@ pub fn derive_fsm(input: TokenStream) -> TokenStream {
     //let q = q.to_string().parse().unwrap();
     //q
 
-    q.to_string().parse().unwrap()
+    q.to_string().replace(CRATE_PLACEHOLDER, "crate").parse().unwrap()
 
     //quote!(#fsm).to_string().parse().unwrap()
 }
-
@@ -1,6 +1,7 @@
 extern crate quote;
 extern crate syn;
 
+use crate::diagnostics::FsmDefinitionError;
 use crate::fsm_def::*;
 use crate::graph::*;
 
@@ -36,7 +37,20 @@ fn match_type_grab_generics(path: &syn::Path, type_name: &str) -> Result<Vec<syn
     Err(())
 }
 
-fn transition_from_ty(g: &[syn::Ty], transition_type: TransitionType) -> Vec<TransitionEntry> {
+/// The submachine and the state of an `EntryPoint<Machine, Sub, State>` transition target.
+fn entry_point_from_ty(ty: &syn::Ty) -> Option<(syn::Ty, FsmEntryPoint)> {
+    if let syn::Ty::Path(None, ref p) = *ty {
+        if let Ok(g) = match_type_grab_generics(p, "EntryPoint") {
+            if g.len() == 3 {
+                return Some((g[1].clone(), FsmEntryPoint { ty: ty.clone(), state_ty: g[2].clone() }));
+            }
+        }
+    }
+
+    None
+}
+
+fn transition_from_ty(g: &[syn::Ty], transition_type: TransitionType, field: usize) -> Vec<TransitionEntry> {
     let mut ret = vec![];
 
     let src_states = ty_to_vec(&g[1]);
@@ -44,13 +58,22 @@ fn transition_from_ty(g: &[syn::Ty], transition_type: TransitionType) -> Vec<Tra
     for src_state in src_states {
         let t = match transition_type {
             TransitionType::Normal => {
+                let (target_state, entry_point) = match entry_point_from_ty(&g[3]) {
+                    Some((submachine, entry_point)) => (submachine, Some(entry_point)),
+                    None => (g[3].clone(), None)
+                };
+
                 TransitionEntry {
                     source_state: src_state.clone(),
                     event: g[2].clone(),
-                    target_state: g[3].clone(),
+                    target_state,
                     action: g[4].clone(),
                     transition_type: TransitionType::Normal,
-                    guard: g.get(5).cloned()
+                    guard: g.get(5).cloned(),
+                    entry_point,
+                    fork_targets: Vec::new(),
+                    join_sources: Vec::new(),
+                    field: Some(field)
                 }
             },
             TransitionType::SelfTransition => {
@@ -60,7 +83,11 @@ fn transition_from_ty(g: &[syn::Ty], transition_type: TransitionType) -> Vec<Tra
                     target_state: src_state.clone(),
                     action: g[3].clone(),
                     transition_type: TransitionType::SelfTransition,
-                    guard: g.get(4).cloned()
+                    guard: g.get(4).cloned(),
+                    entry_point: None,
+                    fork_targets: Vec::new(),
+                    join_sources: Vec::new(),
+                    field: Some(field)
                 }
             },
             TransitionType::Internal => {
@@ -70,7 +97,11 @@ fn transition_from_ty(g: &[syn::Ty], transition_type: TransitionType) -> Vec<Tra
                     target_state: src_state.clone(),
                     action: g[3].clone(),
                     transition_type: TransitionType::Internal,
-                    guard: g.get(4).cloned()
+                    guard: g.get(4).cloned(),
+                    entry_point: None,
+                    fork_targets: Vec::new(),
+                    join_sources: Vec::new(),
+                    field: Some(field)
                 }
             }
         };
@@ -80,7 +111,22 @@ fn transition_from_ty(g: &[syn::Ty], transition_type: TransitionType) -> Vec<Tra
     ret
 }
 
-pub fn parse_description(ast: &syn::MacroInput) -> FsmDescription {
+const DEFINITION_TYPES: &str = "InitialState, ErrorState, FinalState, ContextType, CopyableEvents, Snapshot, InspectionType, \
+QueueType, SubMachine, HierarchicalDispatch, InterruptState, DeferredEvent, StateTimeout, ShallowHistory, DeepHistory, Transition, TransitionGuard, \
+TransitionSelf, TransitionSelfGuard, TransitionInternal, TransitionInternalGuard, Fork or Join";
+
+/// The generic parameters of a definition type, with at least as many as its usage shows.
+fn expect_generics(g: Vec<syn::Ty>, count: usize, usage: &str, field: usize) -> Result<Vec<syn::Ty>, FsmDefinitionError> {
+    if g.len() < count {
+        return Err(FsmDefinitionError::new(Some(field), format!(
+            "Expected {} type parameters, found {}. Use it as `{}`.", count, g.len(), usage
+        )));
+    }
+
+    Ok(g)
+}
+
+pub fn parse_description(ast: &syn::MacroInput) -> Result<FsmDescription, FsmDefinitionError> {
 
     let fsm_name = ast.ident.as_ref().replace("Definition", "");
     let fsm_name_ident = syn::Ident::new(fsm_name.clone());
@@ -89,66 +135,87 @@ pub fn parse_description(ast: &syn::MacroInput) -> FsmDescription {
         syn::Body::Struct(syn::VariantData::Tuple(ref fields)) => {
             fields.iter().collect()
         },
-        _ => panic!("Tuples only!"),
+        _ => return Err(FsmDefinitionError::new(None, format!(
+            "The definition of a machine has to be a tuple struct, e.g. `struct {}Definition(InitialState<{}, Idle>, ...);`", fsm_name, fsm_name
+        ))),
     };
 
 
     let mut initial_state_ty = None;
+    let mut initial_state_field = None;
     let mut error_state_ty = None;
+    let mut error_state_field = None;
+    let mut final_states = Vec::new();
     let mut copyable_events = false;
+    let mut snapshot = false;
+    let mut dispatch = None;
+    let mut dispatch_field = None;
     let mut inspect_ty = None;
+    let mut queue_ty = None;
     let mut context_ty = syn::parse_type("()").unwrap();
     let mut transitions = Vec::new();
     let mut submachines = Vec::new();
-    let mut shallow_history_events = Vec::new();
+    let mut history_events = Vec::new();
     let mut interrupt_states: Vec<FsmInterruptState> = Vec::new();
-    let mut lifetimes = Vec::new();
+    let mut deferred_events = Vec::new();
+    let mut state_timeouts = Vec::new();
+    let mut generics = ast.generics.clone();
 
 
-    for field in fields {
+    for (i, field) in fields.into_iter().enumerate() {
 
         match field.ty {
             syn::Ty::Path(None, ref p @ syn::Path { .. }) => {
 
                 if let Ok(g) = match_type_grab_generics(&p, "InitialState") {
-                    if let Some(t) = g.get(1) {
-                        initial_state_ty = Some(t.clone());
-                        continue;
-                    }
+                    let g = expect_generics(g, 2, "InitialState<Machine, State>", i)?;
+                    initial_state_ty = Some(g[1].clone());
+                    initial_state_field = Some(i);
                 } else if let Ok(g) = match_type_grab_generics(&p, "ErrorState") {
-                    if let Some(t) = g.get(1) {
-                        error_state_ty = Some(t.clone());
-                        continue;
-                    }
-                }else if let Ok(g) = match_type_grab_generics(&p, "ContextType") {
-
-                    if let Some(t) = g.get(0) {
-                        context_ty = t.clone();
-
-                        if let &syn::Ty::Path(_, ref t) = t {
-                            if let Ok(pd) = match_type_grab_param_data(&t) {
-                                for lifetime in pd.lifetimes {
-                                    lifetimes.push(lifetime.clone());
+                    let g = expect_generics(g, 2, "ErrorState<Machine, State>", i)?;
+                    error_state_ty = Some(g[1].clone());
+                    error_state_field = Some(i);
+                } else if let Ok(g) = match_type_grab_generics(p, "FinalState") {
+                    let g = expect_generics(g, 2, "FinalState<Machine, State>", i)?;
+                    final_states.extend(ty_to_vec(&g[1]));
+                } else if let Ok(g) = match_type_grab_generics(p, "ContextType") {
+                    let g = expect_generics(g, 1, "ContextType<Context>", i)?;
+                    context_ty = g[0].clone();
+
+                    if let syn::Ty::Path(_, t) = &context_ty {
+                        if let Ok(pd) = match_type_grab_param_data(t) {
+                            for lifetime in pd.lifetimes {
+                                if !generics.lifetimes.iter().any(|l| l.lifetime == lifetime) {
+                                    generics.lifetimes.push(syn::LifetimeDef::new(lifetime.ident.clone()));
                                 }
                             }
                         }
-
-                        continue;
                     }
-
                 } else if let Ok(_) = match_type_grab_generics(&p, "CopyableEvents") {
                     copyable_events = true;
+                } else if match_type_grab_generics(p, "Snapshot").is_ok() {
+                    snapshot = true;
                 } else if let Ok(g) = match_type_grab_generics(&p, "InspectionType") {
-                    if let Some(t) = g.get(1) {
-                        inspect_ty = Some(t.clone());
-                        continue;
-                    }
+                    let g = expect_generics(g, 2, "InspectionType<Machine, Inspection>", i)?;
+                    inspect_ty = Some(g[1].clone());
+                } else if let Ok(g) = match_type_grab_generics(p, "QueueType") {
+                    let g = expect_generics(g, 2, "QueueType<Machine, Queue>", i)?;
+                    queue_ty = Some(g[1].clone());
+                } else if let Ok(g) = match_type_grab_generics(p, "HierarchicalDispatch") {
+                    let g = expect_generics(g, 2, "HierarchicalDispatch<Machine, InnerFirst>", i)?;
+                    dispatch = match ty_to_name(&g[1]).as_str() {
+                        "InnerFirst" => Some(DispatchPriority::InnerFirst),
+                        "OuterFirst" => Some(DispatchPriority::OuterFirst),
+                        _ => return Err(FsmDefinitionError::new(Some(i), format!(
+                            "Unknown dispatch priority `{}`, expected `InnerFirst` or `OuterFirst`.", ty_to_string(&g[1])
+                        )))
+                    };
+                    dispatch_field = Some(i);
                 } else if let Ok(g) = match_type_grab_generics(&p, "SubMachine") {
-                    if let Some(t) = g.get(0) {
-                        submachines.push(t.clone());
-                        continue;
-                    }
+                    let g = expect_generics(g, 1, "SubMachine<Machine>", i)?;
+                    submachines.push(g[0].clone());
                 } else if let Ok(g) = match_type_grab_generics(&p, "InterruptState") {
+                    let g = expect_generics(g, 3, "InterruptState<Machine, State, ResumeEvent>", i)?;
 
                     let st = g[1].clone();
                     let ev = g[2].clone();
@@ -166,65 +233,214 @@ pub fn parse_description(ast: &syn::MacroInput) -> FsmDescription {
                         });
                     }
 
-                } else if let Ok(g) = match_type_grab_generics(&p, "ShallowHistory") {
-                    match (g.get(1), g.get(2)) {
-                        (Some(event), Some(target_state)) => {
-                            shallow_history_events.push(ShallowHistoryEvent {
-                                event_ty: event.clone(),
-                                target_state_ty: target_state.clone()
-                            });
-                        },
-                        _ => ()
+                } else if let Ok(g) = match_type_grab_generics(p, "DeferredEvent") {
+                    let g = expect_generics(g, 3, "DeferredEvent<Machine, State, Event>", i)?;
+                    for st in ty_to_vec(&g[1]) {
+                        deferred_events.push(FsmDeferredEvent {
+                            state_ty: st,
+                            event_ty: g[2].clone()
+                        });
+                    }
+                } else if let Ok(g) = match_type_grab_generics(p, "StateTimeout") {
+                    let g = expect_generics(g, 4, "StateTimeout<Machine, State, Duration, Event>", i)?;
+                    for st in ty_to_vec(&g[1]) {
+                        state_timeouts.push(FsmStateTimeout {
+                            state_ty: st,
+                            duration_ty: g[2].clone(),
+                            event_ty: g[3].clone(),
+                            field: i
+                        });
                     }
+                } else if let Ok(g) = match_type_grab_generics(&p, "ShallowHistory") {
+                    let g = expect_generics(g, 3, "ShallowHistory<Machine, Event, TargetState>", i)?;
+                    history_events.push(HistoryEvent {
+                        event_ty: g[1].clone(),
+                        target_state_ty: g[2].clone(),
+                        history_type: HistoryType::Shallow
+                    });
+                } else if let Ok(g) = match_type_grab_generics(p, "DeepHistory") {
+                    let g = expect_generics(g, 3, "DeepHistory<Machine, Event, TargetState>", i)?;
+                    history_events.push(HistoryEvent {
+                        event_ty: g[1].clone(),
+                        target_state_ty: g[2].clone(),
+                        history_type: HistoryType::Deep
+                    });
                 } else if let Ok(g) = match_type_grab_generics(&p, "Transition") {
-                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::Normal));
+                    let g = expect_generics(g, 5, "Transition<Machine, Source, Event, Target, Action>", i)?;
+                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::Normal, i));
                 } else if let Ok(g) = match_type_grab_generics(&p, "TransitionGuard") {
-                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::Normal));
+                    let g = expect_generics(g, 6, "TransitionGuard<Machine, Source, Event, Target, Action, Guard>", i)?;
+                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::Normal, i));
 
                 } else if let Ok(g) = match_type_grab_generics(&p, "TransitionSelf") {
-                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::SelfTransition));
+                    let g = expect_generics(g, 4, "TransitionSelf<Machine, State, Event, Action>", i)?;
+                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::SelfTransition, i));
                 } else if let Ok(g) = match_type_grab_generics(&p, "TransitionSelfGuard") {
-                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::SelfTransition));
+                    let g = expect_generics(g, 5, "TransitionSelfGuard<Machine, State, Event, Action, Guard>", i)?;
+                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::SelfTransition, i));
 
                 } else if let Ok(g) = match_type_grab_generics(&p, "TransitionInternal") {
-                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::Internal));
+                    let g = expect_generics(g, 4, "TransitionInternal<Machine, State, Event, Action>", i)?;
+                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::Internal, i));
                 } else if let Ok(g) = match_type_grab_generics(&p, "TransitionInternalGuard") {
-                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::Internal));
+                    let g = expect_generics(g, 5, "TransitionInternalGuard<Machine, State, Event, Action, Guard>", i)?;
+                    transitions.extend_from_slice(&transition_from_ty(&g, TransitionType::Internal, i));
+
+                } else if let Ok(g) = match_type_grab_generics(p, "Fork") {
+                    let g = expect_generics(g, 5, "Fork<Machine, Source, Event, Sub, (TargetA, TargetB)>", i)?;
+                    transitions.push(TransitionEntry {
+                        source_state: g[1].clone(),
+                        event: g[2].clone(),
+                        target_state: g[3].clone(),
+                        action: syn::parse_type("NoAction").unwrap(),
+                        transition_type: TransitionType::Normal,
+                        guard: None,
+                        entry_point: None,
+                        fork_targets: ty_to_vec(&g[4]),
+                        join_sources: Vec::new(),
+                        field: Some(i)
+                    });
+                } else if let Ok(g) = match_type_grab_generics(p, "Join") {
+                    let g = expect_generics(g, 5, "Join<Machine, Sub, (SourceA, SourceB), Event, Target>", i)?;
+                    transitions.push(TransitionEntry {
+                        source_state: g[1].clone(),
+                        event: g[3].clone(),
+                        target_state: g[4].clone(),
+                        action: syn::parse_type("NoAction").unwrap(),
+                        transition_type: TransitionType::Normal,
+                        guard: None,
+                        entry_point: None,
+                        fork_targets: Vec::new(),
+                        join_sources: ty_to_vec(&g[2]),
+                        field: Some(i)
+                    });
 
                 } else {
-                    panic!("Unknown parameter type: {:?}", p);
+                    let name = p.segments.iter().map(|s| s.ident.as_ref()).collect::<Vec<_>>().join("::");
+                    return Err(FsmDefinitionError::new(Some(i), format!(
+                        "Unknown definition type `{}`, expected {}.", name, DEFINITION_TYPES
+                    )));
                 }
 
             },
             _ => {
-                panic!("nop!");
+                return Err(FsmDefinitionError::new(Some(i), format!(
+                    "Expected a definition type like `Transition<{}, Source, Event, Target, Action>`, found `{}`.", fsm_name, ty_to_string(&field.ty)
+                )));
             }
         }
 
     }
 
-    let regions = create_regions(&transitions,
-                                 &ty_to_vec(&initial_state_ty.expect("Missing Initial State")),
-                                 &error_state_ty,
-                                 &submachines,
-                                 &interrupt_states
-                                );
+    let initial_state_ty = initial_state_ty.ok_or_else(|| FsmDefinitionError::new(None, format!(
+        "The machine has no initial state, add `InitialState<{}, State>` to the definition.", fsm_name
+    )))?;
+
+    if dispatch.is_some() && submachines.is_empty() {
+        return Err(FsmDefinitionError::new(dispatch_field, format!(
+            "`HierarchicalDispatch` forwards the events to the active submachine, but `{}` has none. Add a `SubMachine` to the definition.", fsm_name
+        )));
+    }
+
+    if let Some(t) = transitions.iter().find(|t| t.entry_point.is_some() && !submachines.contains(&t.target_state)) {
+        return Err(FsmDefinitionError::new(t.field, format!(
+            "The entry point enters `{}`, which isn't a submachine of `{}`. Add `SubMachine<{}>` to the definition.",
+            ty_to_string(&t.target_state), fsm_name, ty_to_string(&t.target_state)
+        )));
+    }
+
+    if let Some(t) = transitions.iter().find(|t| !t.fork_targets.is_empty() && !submachines.contains(&t.target_state)) {
+        return Err(FsmDefinitionError::new(t.field, format!(
+            "The fork enters `{}`, which isn't a submachine of `{}`. Add `SubMachine<{}>` to the definition.",
+            ty_to_string(&t.target_state), fsm_name, ty_to_string(&t.target_state)
+        )));
+    }
+
+    if let Some(t) = transitions.iter().find(|t| !t.join_sources.is_empty() && !submachines.contains(&t.source_state)) {
+        return Err(FsmDefinitionError::new(t.field, format!(
+            "The join leaves `{}`, which isn't a submachine of `{}`. Add `SubMachine<{}>` to the definition.",
+            ty_to_string(&t.source_state), fsm_name, ty_to_string(&t.source_state)
+        )));
+    }
 
+    if let Some(t) = state_timeouts.iter().find(|t| !transitions.iter().any(|x| x.source_state == t.state_ty && (x.event == t.event_ty || x.is_any_event()))) {
+        return Err(FsmDefinitionError::new(Some(t.field), format!(
+            "The timeout of `{}` queues `{}`, which `{}` has no transition for. Add a transition from `{}` on `{}` to the definition.",
+            ty_to_string(&t.state_ty), ty_to_string(&t.event_ty), ty_to_string(&t.state_ty), ty_to_string(&t.state_ty), ty_to_string(&t.event_ty)
+        )));
+    }
 
-    FsmDescription {
+    let regions = create_regions(&FsmRegionsDefinition {
+        transitions: &transitions,
+        initial_states: &ty_to_vec(&initial_state_ty),
+        initial_state_field,
+        error_state: error_state_ty.as_ref(),
+        error_state_field,
+        submachines: &submachines,
+        interrupt_states: &interrupt_states,
+        deferred_events: &deferred_events,
+        final_states: &final_states
+    })?;
+
+    let desc = FsmDescription {
         name: fsm_name,
         name_ident: fsm_name_ident,
-        lifetimes: lifetimes,
+        generics,
 
         error_state_ty,
 
         submachines: submachines,
-        shallow_history_events: shallow_history_events,
+        history_events,
+        state_timeouts,
 
         context_ty: context_ty,
         inspect_ty: inspect_ty,
+        queue_ty,
         regions: regions,
 
-        copyable_events: copyable_events
+        copyable_events,
+        snapshot,
+        dispatch
+    };
+
+    check_name_collisions(&desc)?;
+
+    Ok(desc)
+}
+
+/// Types from different modules with the same name would share a variant of the generated enums.
+fn check_name_collisions(desc: &FsmDescription) -> Result<(), FsmDefinitionError> {
+    let transitions = desc.get_all_transitions();
+
+    let states = desc.get_all_states();
+    let states = states.iter().map(|s| (s, FsmDescription::to_state_field_name(s)));
+    check_collisions("states", &desc.get_states_ty(), states, &transitions)?;
+
+    let events = desc.get_all_events();
+    let events = events.iter().map(|e| (e, FsmDescription::to_event_variant_name(e)));
+    check_collisions("events", &desc.get_events_ty(), events, &transitions)
+}
+
+fn check_collisions<'a, I>(kind: &str, enum_ty: &syn::Ty, names: I, transitions: &[TransitionEntry]) -> Result<(), FsmDefinitionError>
+    where I: Iterator<Item = (&'a syn::Ty, syn::Ty)>
+{
+    let mut seen: Vec<(&syn::Ty, syn::Ty)> = Vec::new();
+
+    for (ty, name) in names {
+        if let Some(&(other, _)) = seen.iter().find(|(_, n)| n == &name) {
+            let field = transitions.iter()
+                .find(|t| &t.source_state == ty || &t.target_state == ty || &t.event == ty)
+                .and_then(|t| t.field);
+
+            return Err(FsmDefinitionError::new(field, format!(
+                "The {} `{}` and `{}` would both be named `{}` in `{}`. Write a type with the same path everywhere, \
+                or import one of them under another name.",
+                kind, ty_to_string(other), ty_to_string(ty), ty_to_variant_string(ty), ty_to_string(enum_ty)
+            )));
+        }
+
+        seen.push((ty, name));
     }
+
+    Ok(())
 }
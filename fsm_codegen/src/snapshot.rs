@@ -0,0 +1,176 @@
+extern crate quote;
+extern crate syn;
+
+use crate::codegen::region_state;
+use crate::fsm_def::*;
+
+pub fn build_snapshot(fsm: &FsmDescription) -> quote::Tokens {
+    if !fsm.snapshot {
+        return quote! {};
+    }
+
+    let fsm_ty = fsm.get_fsm_ty();
+    let impl_suffix = fsm.get_impl_suffix();
+    let events_ty = fsm.get_events_ty();
+    let states_path = fsm.get_states_path();
+    let snapshot_ty = fsm.get_snapshot_ty();
+    let snapshot_path = fsm.get_snapshot_path();
+    let snapshot_declaration = fsm.get_snapshot_declaration();
+    let states_snapshot_ty = fsm.get_states_snapshot_ty();
+    let states_snapshot_path = fsm.get_states_snapshot_path();
+    let states_snapshot_declaration = fsm.get_states_snapshot_declaration();
+    // the snapshot of a generic machine is serializable when its state data and events are
+    let snapshot_where_clause = if fsm.is_generic() {
+        fsm.get_where_clause_with(quote! { #snapshot_ty: serde::Serialize + serde::de::DeserializeOwned })
+    } else {
+        quote! {}
+    };
+
+    // state data, submachines are captured through their own snapshots
+    let mut fields = quote::Tokens::new();
+    let mut capture = quote::Tokens::new();
+    let mut restore = quote::Tokens::new();
+    let mut candidates = quote::Tokens::new();
+    let mut field_tys = Vec::new();
+
+    for state in &fsm.get_all_states() {
+        let f = FsmDescription::to_state_field_name(state);
+
+        if fsm.is_submachine(state) {
+            let field_ty = quote! { <#state as FsmSnapshot>::Snapshot };
+            fields.append(quote! { pub #f: #field_ty, }.as_str());
+            field_tys.push(field_ty);
+            capture.append(quote! { #f: self.states.#f.read().await.snapshot().await, }.as_str());
+            restore.append(quote! { self.states.#f.read().await.restore(snapshot.states.#f).await?; }.as_str());
+        } else {
+            fields.append(quote! { pub #f: #state, }.as_str());
+            field_tys.push(quote! { #state });
+            capture.append(quote! { #f: self.states.#f.read().await.clone(), }.as_str());
+            restore.append(quote! { *self.states.#f.write().await = snapshot.states.#f; }.as_str());
+        }
+
+        let variant = FsmDescription::to_state_variant_name(state);
+        candidates.append(quote! { #states_path::#variant(std::sync::Arc::clone(&self.states.#f)), }.as_str());
+    }
+
+    for sub in fsm.get_submachine_types() {
+        let f = FsmDescription::to_state_sub_started_field_name(sub);
+
+        fields.append(quote! { pub #f: bool, }.as_str());
+        capture.append(quote! { #f: self.states.#f.load(std::sync::atomic::Ordering::SeqCst), }.as_str());
+        restore.append(quote! { self.states.#f.store(snapshot.states.#f, std::sync::atomic::Ordering::SeqCst); }.as_str());
+    }
+
+    if fsm.is_generic() {
+        let phantom_ty = fsm.get_phantom_ty();
+        fields.append(quote! { #[serde(skip)] _fsm_ty: #phantom_ty, }.as_str());
+        capture.append(quote! { _fsm_ty: std::marker::PhantomData, }.as_str());
+    }
+
+    // the active state of each region is stored by its name
+    let mut current_state = quote::Tokens::new();
+    let mut restored_state = quote::Tokens::new();
+    let mut timeouts = quote::Tokens::new();
+
+    for region in &fsm.regions {
+        let id = region.id;
+        let s = region_state(fsm, region, quote! { state });
+
+        current_state.append(quote! { #s.to_string(), }.as_str());
+        restored_state.append(quote! { state_from_name(&snapshot.current_state[#id])?, }.as_str());
+
+        if !fsm.state_timeouts.is_empty() {
+            timeouts.append(quote! { self.start_state_timeouts(&#s); }.as_str());
+        }
+    }
+
+    let states_snapshot_bound = fsm.get_serde_bound(&field_tys);
+    let snapshot_bound = fsm.get_serde_bound(&[quote! { #states_snapshot_ty }, quote! { #events_ty }]);
+
+    let regions = fsm.regions.len();
+    let restored_state = if fsm.has_multiple_regions() {
+        quote! { (#restored_state) }
+    } else {
+        quote! { state_from_name(&snapshot.current_state[0])? }
+    };
+
+    quote! {
+        #[derive(Debug, serde::Serialize, serde::Deserialize)]
+        #states_snapshot_bound
+        pub struct #states_snapshot_declaration {
+            #fields
+        }
+
+        #[derive(Debug, serde::Serialize, serde::Deserialize)]
+        #snapshot_bound
+        pub struct #snapshot_declaration {
+            pub current_state: Vec<String>,
+            pub states: #states_snapshot_ty,
+            pub queue: Vec<(FsmEventPriority, #events_ty)>,
+            pub deferred_queue: Vec<#events_ty>
+        }
+
+        #[async_trait]
+        impl #impl_suffix FsmSnapshot for #fsm_ty #snapshot_where_clause {
+            type Snapshot = #snapshot_ty;
+
+            async fn snapshot(&self) -> #snapshot_ty {
+                let current_state = {
+                    let state = self.state.read().await;
+                    vec![#current_state]
+                };
+
+                #snapshot_path {
+                    current_state,
+                    states: #states_snapshot_path {
+                        #capture
+                    },
+                    queue: self.queue.read().await.events_with_priority().into_iter().map(|(priority, event)| (priority, event.clone())).collect(),
+                    deferred_queue: self.deferred_queue.read().await.events().into_iter().cloned().collect()
+                }
+            }
+
+            async fn restore(&self, snapshot: #snapshot_ty) -> Result<(), FsmSnapshotError> {
+                if snapshot.current_state.len() != #regions {
+                    return Err(FsmSnapshotError::RegionMismatch);
+                }
+
+                let state_from_name = |name: &str| {
+                    let candidates = vec![#candidates];
+                    candidates.into_iter()
+                        .find(|state| state.to_string() == name)
+                        .ok_or_else(|| FsmSnapshotError::UnknownState(name.to_string()))
+                };
+
+                let state = #restored_state;
+
+                #restore
+
+                // an event that the queue rejects or drops would be lost
+                {
+                    let mut queue = self.queue.write().await;
+                    for (priority, event) in snapshot.queue {
+                        if queue.enqueue_event_with_priority(event, priority) != Ok(FsmEnqueueStatus::Queued) {
+                            return Err(FsmSnapshotError::QueueFull);
+                        }
+                    }
+                }
+
+                {
+                    let mut deferred_queue = self.deferred_queue.write().await;
+                    for event in snapshot.deferred_queue {
+                        if deferred_queue.enqueue_event(event) != Ok(FsmEnqueueStatus::Queued) {
+                            return Err(FsmSnapshotError::QueueFull);
+                        }
+                    }
+                }
+
+                #timeouts
+                *self.state.write().await = state;
+                self.completed.store(self.is_completed().await, std::sync::atomic::Ordering::SeqCst);
+
+                Ok(())
+            }
+        }
+    }
+}
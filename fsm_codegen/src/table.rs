@@ -0,0 +1,362 @@
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Ident, Punct, Spacing, Span, TokenStream, TokenTree};
+
+use crate::diagnostics::compile_error;
+
+/// A machine of the `fsm!` macro, translated into the tuple struct of the derive.
+pub struct FsmTable {
+    pub definition: TokenStream
+}
+
+/// A syntax error in the transition table.
+pub struct FsmTableError {
+    start: Span,
+    end: Span,
+    message: String
+}
+
+impl FsmTableError {
+    fn new(start: Span, end: Span, message: &str) -> Self {
+        FsmTableError { start, end, message: message.to_string() }
+    }
+
+    fn at(token: &TokenTree, message: &str) -> Self {
+        FsmTableError::new(token.span(), token.span(), message)
+    }
+
+    pub fn to_compile_error(&self) -> TokenStream {
+        compile_error(self.start, self.end, &self.message)
+    }
+}
+
+const TABLE_SYNTAX: &str = "Expected `Source + Event => Target / Action;`, `Source + Event [Guard] => Target;`, \
+`Source + Event => self;`, `Source + Event / Action;`, `initial State;`, `error State;`, `final State;`, \
+`context Type;`, `inspection Type;`, `queue Type;`, `submachine Machine;`, `dispatch InnerFirst;`, `copyable_events;`, `snapshot;`, \
+`interrupt State until Event;`, `defer Event in State;`, `timeout State after Duration => Event;`, \
+`shallow_history Event => State;`, `deep_history Event => State;`, `fork Source + Event => Sub in (TargetA, TargetB);` \
+or `join Sub in (SourceA, SourceB) + Event => Target;`";
+
+/// Parse `machine Name { entries }` blocks, every entry becomes a field of the definition tuple struct.
+pub fn parse_tables(input: TokenStream) -> Result<Vec<FsmTable>, FsmTableError> {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    let mut tables = Vec::new();
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let attributes = take_attributes(&tokens, &mut i);
+
+        match tokens.get(i) {
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "machine" => (),
+            Some(token) => return Err(FsmTableError::at(token, "Expected `machine Name { ... }`.")),
+            None => return Err(FsmTableError::new(Span::call_site(), Span::call_site(), "Expected `machine Name { ... }` after the attributes."))
+        }
+
+        let name = match tokens.get(i + 1) {
+            Some(TokenTree::Ident(name)) => name.clone(),
+            Some(token) => return Err(FsmTableError::at(token, "Expected the name of the machine.")),
+            None => return Err(FsmTableError::at(&tokens[i], "Expected the name of the machine."))
+        };
+
+        let body = match tokens.get(i + 2) {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g.clone(),
+            Some(token) => return Err(FsmTableError::at(token, "Expected the transition table of the machine in braces.")),
+            None => return Err(FsmTableError::new(name.span(), name.span(), "Expected the transition table of the machine in braces."))
+        };
+
+        tables.push(parse_table(attributes, &name, &body)?);
+        i += 3;
+    }
+
+    Ok(tables)
+}
+
+fn parse_table(attributes: Vec<TokenTree>, name: &Ident, body: &Group) -> Result<FsmTable, FsmTableError> {
+    let machine = TokenTree::Ident(name.clone());
+    let tokens: Vec<TokenTree> = body.stream().into_iter().collect();
+
+    let mut fields: Vec<TokenTree> = Vec::new();
+    for entry in tokens.split(|t| is_punct(t, ';')).filter(|e| !e.is_empty()) {
+        let mut i = 0;
+        fields.extend(take_attributes(entry, &mut i));
+
+        if i == entry.len() {
+            return Err(FsmTableError::at(&entry[i - 1], "Expected an entry after the attributes."));
+        }
+
+        fields.extend(parse_entry(&machine, &entry[i..])?);
+        fields.push(punct(',', entry[entry.len() - 1].span()));
+    }
+
+    let mut definition: Vec<TokenTree> = attributes;
+    definition.push(punct('#', name.span()));
+    definition.push(group(Delimiter::Bracket, vec![
+        ident("allow", name.span()),
+        group(Delimiter::Parenthesis, vec![ident("dead_code", name.span())], name.span())
+    ], name.span()));
+    definition.push(ident("struct", name.span()));
+    definition.push(ident(&format!("{}Definition", name), name.span()));
+    definition.push(group(Delimiter::Parenthesis, fields, body.span()));
+    definition.push(punct(';', body.span()));
+
+    Ok(FsmTable {
+        definition: definition.into_iter().collect()
+    })
+}
+
+/// Translate an entry of the table into a definition type.
+fn parse_entry(machine: &TokenTree, entry: &[TokenTree]) -> Result<Vec<TokenTree>, FsmTableError> {
+    let keyword = match entry[0] {
+        TokenTree::Ident(ref ident) => ident.to_string(),
+        _ => String::new()
+    };
+    let args = &entry[1..];
+
+    let (definition, params): (&str, Vec<&[TokenTree]>) = match keyword.as_str() {
+        "initial" => ("InitialState", vec![machine_ty(machine), single(entry, args)?]),
+        "error" => ("ErrorState", vec![machine_ty(machine), single(entry, args)?]),
+        "final" => ("FinalState", vec![machine_ty(machine), single(entry, args)?]),
+        "context" => ("ContextType", vec![single(entry, args)?]),
+        "inspection" => ("InspectionType", vec![machine_ty(machine), single(entry, args)?]),
+        "queue" => ("QueueType", vec![machine_ty(machine), single(entry, args)?]),
+        "submachine" => ("SubMachine", vec![single(entry, args)?]),
+        "dispatch" => ("HierarchicalDispatch", vec![machine_ty(machine), single(entry, args)?]),
+        "copyable_events" | "snapshot" if !args.is_empty() => {
+            return Err(FsmTableError::at(&args[0], &format!("`{}` takes no parameters.", keyword)));
+        },
+        "copyable_events" => ("CopyableEvents", vec![]),
+        "snapshot" => ("Snapshot", vec![]),
+        "interrupt" => {
+            let (state, event) = pair(entry, args, &Separator::Word("until"), "interrupt State until Event")?;
+            ("InterruptState", vec![machine_ty(machine), state, event])
+        },
+        "defer" => {
+            let (event, state) = pair(entry, args, &Separator::Word("in"), "defer Event in State")?;
+            ("DeferredEvent", vec![machine_ty(machine), state, event])
+        },
+        "timeout" => {
+            let usage = "timeout State after Duration => Event";
+            let (state, rest) = pair(entry, args, &Separator::Word("after"), usage)?;
+            let (duration, event) = pair(entry, rest, &Separator::Arrow, usage)?;
+            ("StateTimeout", vec![machine_ty(machine), state, duration, event])
+        },
+        "shallow_history" | "deep_history" => {
+            let definition = if keyword == "shallow_history" { "ShallowHistory" } else { "DeepHistory" };
+            let (event, state) = pair(entry, args, &Separator::Arrow, &format!("{} Event => State", keyword))?;
+            (definition, vec![machine_ty(machine), event, state])
+        },
+        "fork" => {
+            let usage = "fork Source + Event => Sub in (TargetA, TargetB)";
+            let (source, rest) = pair(entry, args, &Separator::Punct('+'), usage)?;
+            let (event, rest) = pair(entry, rest, &Separator::Arrow, usage)?;
+            let (sub, targets) = pair(entry, rest, &Separator::Word("in"), usage)?;
+            ("Fork", vec![machine_ty(machine), source, event, sub, targets])
+        },
+        "join" => {
+            let usage = "join Sub in (SourceA, SourceB) + Event => Target";
+            let (rest, target) = pair(entry, args, &Separator::Arrow, usage)?;
+            let (rest, event) = pair(entry, rest, &Separator::Punct('+'), usage)?;
+            let (sub, sources) = pair(entry, rest, &Separator::Word("in"), usage)?;
+            ("Join", vec![machine_ty(machine), sub, sources, event, target])
+        },
+        _ => return parse_transition(machine, entry)
+    };
+
+    Ok(definition_ty(definition, &params, entry))
+}
+
+/// `Source + Event [Guard] => Target / Action`, the guard and the action are optional. The target is
+/// `self` for a self transition and left out for an internal transition.
+fn parse_transition(machine: &TokenTree, entry: &[TokenTree]) -> Result<Vec<TokenTree>, FsmTableError> {
+    let (source, rest) = match split(entry, &Separator::Punct('+')) {
+        Some((source, rest)) if !source.is_empty() => (source, rest),
+        _ => return Err(FsmTableError::new(entry[0].span(), entry[entry.len() - 1].span(), TABLE_SYNTAX))
+    };
+
+    let (rest, action) = match split(rest, &Separator::Punct('/')) {
+        Some((rest, action)) => (rest, Some(expect_ty(action, &entry[entry.len() - 1], "Expected an action after `/`.")?)),
+        None => (rest, None)
+    };
+
+    let (rest, target) = match split(rest, &Separator::Arrow) {
+        Some((rest, target)) => (rest, Some(expect_ty(target, &entry[entry.len() - 1], "Expected a target state after `=>`.")?)),
+        None => (rest, None)
+    };
+
+    let (event, guard) = match rest.last() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => (&rest[..rest.len() - 1], Some(g.stream().into_iter().collect::<Vec<_>>())),
+        _ => (rest, None)
+    };
+    let event = expect_ty(event, &entry[0], "Expected an event after `+`.")?;
+
+    if let Some(ref guard) = guard {
+        if guard.is_empty() {
+            return Err(FsmTableError::at(&rest[rest.len() - 1], "Expected a guard in the brackets."));
+        }
+    }
+
+    let no_action = [ident("NoAction", entry[entry.len() - 1].span())];
+    let action = action.unwrap_or(&no_action);
+
+    let self_target = match target {
+        Some([TokenTree::Ident(ident)]) => ident.to_string() == "self",
+        _ => false
+    };
+
+    let mut params = vec![machine_ty(machine), source, event];
+    let definition = match target {
+        Some(target) if !self_target => {
+            params.push(target);
+            "Transition"
+        },
+        Some(_) => "TransitionSelf",
+        None => "TransitionInternal"
+    };
+    params.push(action);
+
+    match guard {
+        Some(ref guard) => {
+            params.push(guard);
+            Ok(definition_ty(&format!("{}Guard", definition), &params, entry))
+        },
+        None => Ok(definition_ty(definition, &params, entry))
+    }
+}
+
+enum Separator {
+    Punct(char),
+    Arrow,
+    Word(&'static str)
+}
+
+/// Split the tokens on the first separator outside of the generic parameters.
+fn split<'a>(tokens: &'a [TokenTree], separator: &Separator) -> Option<(&'a [TokenTree], &'a [TokenTree])> {
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let arrow = is_arrow(tokens, i);
+
+        if depth == 0 {
+            match *separator {
+                Separator::Arrow if arrow => return Some((&tokens[..i], &tokens[i + 2..])),
+                Separator::Punct(c) if !arrow && is_punct(&tokens[i], c) => return Some((&tokens[..i], &tokens[i + 1..])),
+                Separator::Word(w) => match tokens[i] {
+                    TokenTree::Ident(ref ident) if ident.to_string() == w => return Some((&tokens[..i], &tokens[i + 1..])),
+                    _ => ()
+                },
+                _ => ()
+            }
+        }
+
+        if arrow || is_joint(tokens, i, '-', '>') {
+            i += 2;
+            continue;
+        }
+        if is_punct(&tokens[i], '<') {
+            depth += 1;
+        } else if is_punct(&tokens[i], '>') {
+            depth -= 1;
+        }
+        i += 1;
+    }
+
+    None
+}
+
+fn pair<'a>(entry: &'a [TokenTree], args: &'a [TokenTree], separator: &Separator, usage: &str) -> Result<(&'a [TokenTree], &'a [TokenTree]), FsmTableError> {
+    let message = format!("Expected `{}`.", usage);
+
+    match split(args, separator) {
+        Some((first, second)) if !first.is_empty() && !second.is_empty() => Ok((first, second)),
+        _ => Err(FsmTableError::new(entry[0].span(), entry[entry.len() - 1].span(), &message))
+    }
+}
+
+fn single<'a>(entry: &'a [TokenTree], args: &'a [TokenTree]) -> Result<&'a [TokenTree], FsmTableError> {
+    expect_ty(args, &entry[0], &format!("Expected a type after `{}`.", entry[0]))
+}
+
+fn expect_ty<'a>(tokens: &'a [TokenTree], near: &TokenTree, message: &str) -> Result<&'a [TokenTree], FsmTableError> {
+    match tokens.is_empty() {
+        true => Err(FsmTableError::at(near, message)),
+        false => Ok(tokens)
+    }
+}
+
+fn machine_ty(machine: &TokenTree) -> &[TokenTree] {
+    std::slice::from_ref(machine)
+}
+
+/// `Definition<params...>`, spanning the whole entry so that the errors of the derive point at it.
+fn definition_ty(definition: &str, params: &[&[TokenTree]], entry: &[TokenTree]) -> Vec<TokenTree> {
+    let start = entry[0].span();
+    let end = entry[entry.len() - 1].span();
+
+    let mut ty = vec![ident(definition, start)];
+    if params.is_empty() {
+        return ty;
+    }
+
+    ty.push(punct('<', start));
+    for (i, param) in params.iter().enumerate() {
+        if i > 0 {
+            ty.push(punct(',', start));
+        }
+        ty.extend(param.iter().cloned());
+    }
+    ty.push(punct('>', end));
+
+    ty
+}
+
+/// The outer attributes and doc comments in front of the token at `i`.
+fn take_attributes(tokens: &[TokenTree], i: &mut usize) -> Vec<TokenTree> {
+    let mut attributes = Vec::new();
+
+    while let (Some(hash), Some(TokenTree::Group(g))) = (tokens.get(*i), tokens.get(*i + 1)) {
+        if !is_punct(hash, '#') || g.delimiter() != Delimiter::Bracket {
+            break;
+        }
+
+        attributes.push(hash.clone());
+        attributes.push(TokenTree::Group(g.clone()));
+        *i += 2;
+    }
+
+    attributes
+}
+
+fn is_punct(token: &TokenTree, c: char) -> bool {
+    match token {
+        TokenTree::Punct(p) => p.as_char() == c,
+        _ => false
+    }
+}
+
+fn is_joint(tokens: &[TokenTree], i: usize, first: char, second: char) -> bool {
+    match (tokens.get(i), tokens.get(i + 1)) {
+        (Some(TokenTree::Punct(a)), Some(b)) => a.as_char() == first && a.spacing() == Spacing::Joint && is_punct(b, second),
+        _ => false
+    }
+}
+
+fn is_arrow(tokens: &[TokenTree], i: usize) -> bool {
+    is_joint(tokens, i, '=', '>')
+}
+
+fn ident(name: &str, span: Span) -> TokenTree {
+    TokenTree::Ident(Ident::new(name, span))
+}
+
+fn punct(c: char, span: Span) -> TokenTree {
+    let mut punct = Punct::new(c, Spacing::Alone);
+    punct.set_span(span);
+    TokenTree::Punct(punct)
+}
+
+fn group(delimiter: Delimiter, tokens: Vec<TokenTree>, span: Span) -> TokenTree {
+    let mut group = Group::new(delimiter, tokens.into_iter().collect());
+    group.set_span(span);
+    TokenTree::Group(group)
+}
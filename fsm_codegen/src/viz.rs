@@ -23,8 +23,9 @@ pub fn build_viz(fsm: &FsmDescription) -> quote::Tokens {
         let var_region = format!("{}_region_{}", fsm.name, region.id);
         writeln!(out, r#"var {} = f.add_region("Region {}");"#, var_region, region.id).unwrap();
 
-        let initial_name = ty_to_string(&region.initial_state_ty);
-        writeln!(out, r#"var state_{} = {}.add_initial_state("{}");"#, initial_name, var_region, initial_name).unwrap();
+        let initial_var = ty_to_variant_string(&region.initial_state_ty);
+        let initial_name = ty_to_name(&region.initial_state_ty);
+        writeln!(out, r#"var state_{} = {}.add_initial_state("{}");"#, initial_var, var_region, initial_name).unwrap();
         for state in &region.get_all_internal_states() {
             if state == &region.initial_state_ty {
                 continue;
@@ -32,15 +33,17 @@ pub fn build_viz(fsm: &FsmDescription) -> quote::Tokens {
 
             let is_initial_state = state == &region.initial_state_ty;
             let is_interrupt_state = region.interrupt_states.iter().any(|x| &x.interrupt_state_ty == state);
+            let is_final_state = region.final_states.contains(state);
 
-            let info = format!(r#"{{ is_initial_state: {:?}, is_interrupt_state: {:?} }}"#, is_initial_state, is_interrupt_state);
+            let info = format!(r#"{{ is_initial_state: {:?}, is_interrupt_state: {:?}, is_final_state: {:?} }}"#, is_initial_state, is_interrupt_state, is_final_state);
 
-            let name = ty_to_string(state);
-            writeln!(out, r#"var state_{} = {}.add_state("{}", {});"#, name, var_region, name, info).unwrap();
+            let var = ty_to_variant_string(state);
+            let name = ty_to_name(state);
+            writeln!(out, r#"var state_{} = {}.add_state("{}", {});"#, var, var_region, name, info).unwrap();
         }
 
         for sub in region.get_all_states().iter().filter(|ref x| fsm.is_submachine(x)) {
-            let s = ty_to_string(&sub);
+            let s = ty_to_variant_string(sub);
 
             // submachines
             writeln!(out, "// submachine {} start", s).unwrap();
@@ -51,38 +54,65 @@ pub fn build_viz(fsm: &FsmDescription) -> quote::Tokens {
                 let a = format!("// ##SUB_{}##", s);
                 let p = format!("fsm_{}", fsm.name);
                 subs.append(quote! {
-                    let t = t.replace(#a, &#sub::viz_cytoscape_fsm(#p));
+                    let t = t.replace(#a, &<#sub>::viz_cytoscape_fsm(#p));
                 }.as_str());
             }
         }
 
         for transition in &region.transitions {
-            let s_from = ty_to_string(&transition.source_state);
-            let s_to = ty_to_string(&transition.target_state);
-            let ev = ty_to_string(&transition.event);
-            let ac = ty_to_string(&transition.action);
+            let s_from = ty_to_variant_string(&transition.source_state);
+            let s_to = ty_to_variant_string(&transition.target_state);
+            let ev = ty_to_name(&transition.event);
+            let ac = ty_to_name(&transition.action);
 
 
-            let is_shallow_history = fsm.shallow_history_events.iter().find(|ref x| &x.event_ty == &transition.event && &x.target_state_ty == &transition.target_state).is_some();
+            let history_type = fsm.get_history_type(&transition.event, &transition.target_state);
+            let is_shallow_history = history_type == Some(HistoryType::Shallow);
+            let is_deep_history = history_type == Some(HistoryType::Deep);
             let is_resume_event = region.interrupt_states.iter().any(|x| &x.interrupt_state_ty == &transition.source_state && x.resume_event_ty.iter().any(|y| y == &transition.event));
             // let is_internal = transition.transition_type == TransitionType::Internal;
             let is_anonymous = transition.is_anonymous_transition();
 
             let guard_json = match transition.guard {
-                Some(ref g) => ty_to_string(g),
+                Some(ref g) => ty_to_name(g),
                 None => "".into()
             };
+            let pseudo_state = if !transition.fork_targets.is_empty() {
+                "Fork"
+            } else if !transition.join_sources.is_empty() {
+                "Join"
+            } else {
+                ""
+            };
             let data = {
                 let ac = if ac == "NoAction" { "".into() } else { ac };
-                format!("{{ event: '{}', action: '{}', guard: '{}', transition_type: '{}', is_anonymous: {:?}, shallow_history: {:?}, resume_event: {:?} }}", ev, ac, guard_json, transition.transition_type, is_anonymous, is_shallow_history, is_resume_event)
+                format!("{{ event: '{}', action: '{}', guard: '{}', transition_type: '{}', is_anonymous: {:?}, shallow_history: {:?}, deep_history: {:?}, resume_event: {:?}, entry_point: {:?}, pseudo_state: '{}' }}", ev, ac, guard_json, transition.transition_type, is_anonymous, is_shallow_history, is_deep_history, is_resume_event, transition.entry_point.is_some(), pseudo_state)
             };
 
+            // a fork or a join is drawn between the outer state and each of the states it lists
+            if !transition.fork_targets.is_empty() {
+                for fork_target in &transition.fork_targets {
+                    writeln!(out, r#"fsm_{}.add_transition_to_state(state_{}, "{}", {});"#, s_to, s_from, ty_to_name(fork_target), data).unwrap();
+                }
+                continue;
+            }
+            if !transition.join_sources.is_empty() {
+                for join_source in &transition.join_sources {
+                    writeln!(out, r#"fsm_{}.add_transition_from_state("{}", state_{}, {});"#, s_from, ty_to_name(join_source), s_to, data).unwrap();
+                }
+                continue;
+            }
+
             let (from, to) = {
                 if fsm.is_submachine(&transition.source_state) {
                     (format!("fsm_{}", s_from), format!("state_{}", s_to))
                 } else if fsm.is_submachine(&transition.target_state) {
 
-                    if is_shallow_history {
+                    if let Some(ref entry_point) = transition.entry_point {
+                        let entry_state = ty_to_name(&entry_point.state_ty);
+                        writeln!(out, r#"fsm_{}.add_transition_to_state(state_{}, "{}", {});"#, s_to, s_from, entry_state, data).unwrap();
+                        continue;
+                    } else if history_type.is_some() {
                         (format!("state_{}", s_from), format!("fsm_{}", s_to))
                     } else {
                         writeln!(out, r#"fsm_{}.add_transition_to_start({}, {});"#, s_to, format!("state_{}", s_from), data).unwrap();
@@ -97,8 +127,6 @@ pub fn build_viz(fsm: &FsmDescription) -> quote::Tokens {
         }
     }
 
-
-
     quote! {
         fn viz_cytoscape_fsm(parent: &str) -> String {
             let t = (#out).replace("##parent##", parent);
@@ -123,6 +151,11 @@ pub fn build_test_viz_build(_: &FsmDescription) -> quote::Tokens {
 
 #[cfg(feature = "viz")]
 pub fn build_test_viz_build(fsm: &FsmDescription) -> quote::Tokens {
+    // the generic parameters aren't known outside of the machine
+    if fsm.is_generic() {
+        return quote! { };
+    }
+
     let fn_name = fsm.get_build_viz_fn();
     let fn_name_docs = fsm.get_build_viz_docs_fn();
     let ty = fsm.get_fsm_ty_inline();
@@ -42,6 +42,13 @@ function init_cy_fsm() {
             }
             },
             {
+            selector: 'node.final',
+            css: {
+                'border-width': 6,
+                'border-style': 'double'
+            }
+            },
+            {
             selector: 'node.interrupt',
             css: {
                 'background-color': '#F25F5C'
@@ -125,6 +132,7 @@ function newFsm(cy, name, parent) {
     cy.add({group: "nodes", data: { id: fsm_id, label: name, parent: parent } });
     
     var regions = [];
+    var states = {};
 
     return {
         fsm_id: fsm_id,
@@ -148,6 +156,14 @@ function newFsm(cy, name, parent) {
             });
         },
 
+        add_transition_to_state(state_start, name, data) {
+            regions[0].add_transition(state_start, states[name], data);
+        },
+
+        add_transition_from_state(name, state_target, data) {
+            regions[0].add_transition(states[name], state_target, data);
+        },
+
         add_region: function(region) {
             var region_id = fsm_id + "_" + region;
             var start_id = region_id + "__start";            
@@ -165,10 +181,11 @@ function newFsm(cy, name, parent) {
                     var initial_state_id = fsm_id + "_" + name;
                     cy.add({group: "nodes", data: { id: initial_state_id, label: name, parent: region_id }, classes: "initial" });
                     cy.add({group: "edges", data: { source: start_id, target: initial_state_id }, classes: "start" });
-                    return {
+                    states[name] = {
                         state_id: initial_state_id,
                         node_id: initial_state_id
                     };
+                    return states[name];
                 },
                 add_state: function(name, info) {
                     var state_id = region_id + "_" + name;
@@ -176,12 +193,16 @@ function newFsm(cy, name, parent) {
                     if (info.is_interrupt_state) {
                         node.classes += "interrupt";
                     }
+                    if (info.is_final_state) {
+                        node.classes += " final";
+                    }
 
                     cy.add(node);
-                    return {
+                    states[name] = {
                         state_id: state_id,
                         node_id: state_id
                     };
+                    return states[name];
                 },
                 add_transition(state_start, state_target, info) {
 
@@ -203,9 +224,18 @@ function newFsm(cy, name, parent) {
                     if (info.shallow_history == true) {
                         label += "(H)\n";
                     }
+                    if (info.deep_history == true) {
+                        label += "(H*)\n";
+                    }
                     if (info.resume_event == true) {
                         label += "(Resume)\n";
                     }
+                    if (info.entry_point == true) {
+                        label += "(Entry)\n";
+                    }
+                    if (info.pseudo_state !== undefined && info.pseudo_state != "") {
+                        label += "(" + info.pseudo_state + ")\n";
+                    }
 
 
                     var data = { source: state_start.node_id, target: state_target.node_id, label: label.trim() };
@@ -0,0 +1,135 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Start;
+impl FsmEvent for Start {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Stop;
+impl FsmEvent for Stop {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Jam;
+impl FsmEvent for Jam {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Calibrate;
+impl FsmEvent for Calibrate {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Load;
+impl FsmEvent for Load {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Reset;
+impl FsmEvent for Reset {}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Idle;
+#[async_trait]
+impl FsmState<Robot> for Idle { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Running;
+#[async_trait]
+impl FsmState<Robot> for Running { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Fault;
+#[async_trait]
+impl FsmState<Robot> for Fault { }
+
+#[derive(Debug, Default)]
+pub struct RobotContext {
+	ignored: Vec<&'static str>,
+	fault: Option<&'static str>
+}
+
+pub struct LogIgnored;
+#[async_trait]
+impl FsmActionSelf<Robot, Idle> for LogIgnored {
+	async fn action(event_context: &EventContext<'_, Robot>, _: &Idle) -> FsmTransitionResult<()> {
+		event_context.context.write().await.ignored.push(event_context.event.name());
+		Ok(())
+	}
+}
+
+pub struct RecordFault;
+#[async_trait]
+impl FsmAction<Robot, Running, Fault> for RecordFault {
+	async fn action(event_context: &EventContext<'_, Robot>, _: &Running, _: &Fault) -> FsmTransitionResult<()> {
+		event_context.context.write().await.fault = Some(event_context.event.name());
+		Ok(())
+	}
+}
+
+pub struct NeverCalibrated;
+impl FsmGuard<Robot> for NeverCalibrated {
+	fn guard(_: &EventContext<Robot>, _: &RobotStatesStore) -> bool {
+		false
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct RobotDefinition(
+	InitialState<Robot, Idle>,
+	ContextType<RobotContext>,
+
+	Transition        < Robot, Idle,    Start,     Running, NoAction >,
+	TransitionInternal< Robot, Idle,    AnyEvent,           LogIgnored >,
+
+	Transition        < Robot, Running, Stop,      Idle,    NoAction >,
+	TransitionGuard   < Robot, Running, Calibrate, Idle,    NoAction, NeverCalibrated >,
+	Transition        < Robot, Running, AnyEvent,  Fault,   RecordFault >,
+	DeferredEvent     < Robot, Running, Load >,
+
+	Transition        < Robot, Fault,   Reset,     Idle,    NoAction >,
+	TransitionInternal< Robot, Fault,   Jam,                NoAction >
+);
+
+#[tokio::test]
+async fn test_any_event() {
+	let robot = Robot::new(&Default::default());
+	robot.start().await;
+	assert_matches!(robot.get_current_state().await, RobotStates::Idle(_));
+
+	// logged and ignored, the machine's own events aren't matched
+	robot.process_event(RobotEvents::Jam(Jam)).await.unwrap();
+	robot.process_event(RobotEvents::Reset(Reset)).await.unwrap();
+	assert_matches!(robot.process_event(RobotEvents::NoEvent(NoEvent)).await, Err(FsmError::NoTransition));
+	assert_eq!(robot.get_context().read().await.ignored, vec!["Jam", "Reset"]);
+	assert_matches!(robot.get_current_state().await, RobotStates::Idle(_));
+
+	// the specific transition takes priority
+	robot.process_event(RobotEvents::Start(Start)).await.unwrap();
+	assert_matches!(robot.get_current_state().await, RobotStates::Running(_));
+
+	// a deferred event doesn't fall through to the fault
+	robot.process_event(RobotEvents::Load(Load)).await.unwrap();
+	assert_matches!(robot.get_current_state().await, RobotStates::Running(_));
+
+	// an event whose guards all failed does
+	robot.process_event(RobotEvents::Calibrate(Calibrate)).await.unwrap();
+	assert_matches!(robot.get_current_state().await, RobotStates::Fault(_));
+	assert_eq!(robot.get_context().read().await.fault, Some("Calibrate"));
+
+	robot.process_event(RobotEvents::Reset(Reset)).await.unwrap();
+	robot.process_event(RobotEvents::Start(Start)).await.unwrap();
+	robot.process_event(RobotEvents::Jam(Jam)).await.unwrap();
+	assert_matches!(robot.get_current_state().await, RobotStates::Fault(_));
+	assert_eq!(robot.get_context().read().await.fault, Some("Jam"));
+
+	assert_matches!(robot.process_event(RobotEvents::Start(Start)).await, Err(FsmError::NoTransition));
+	robot.process_event(RobotEvents::Reset(Reset)).await.unwrap();
+	assert_matches!(robot.get_current_state().await, RobotStates::Idle(_));
+
+	assert_eq!(<RobotEvents as FsmEvents<Robot>>::variants(), &["Start", "Stop", "Calibrate", "Reset", "Jam", "Load"]);
+}
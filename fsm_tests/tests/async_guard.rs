@@ -0,0 +1,180 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Pressure(u32);
+impl FsmEvent for Pressure {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Arm;
+impl FsmEvent for Arm {}
+
+// guards
+
+pub struct OverLimit;
+#[async_trait]
+impl FsmGuardAsync<Valve, Closed> for OverLimit {
+	async fn guard(event_context: &EventContext<'_, Valve>, source_state: &Closed, _states: &ValveStatesStore) -> bool {
+		let limit = event_context.context.read().await.limit;
+
+		match event_context.event {
+			ValveEvents::Pressure(Pressure(p)) => *source_state.armed.read().await && *p > limit,
+			_ => false
+		}
+	}
+}
+
+// states
+
+#[derive(Debug, Clone, Default)]
+pub struct Closed {
+	armed: FsmArc<bool>
+}
+#[async_trait]
+impl FsmState<Valve> for Closed {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Open;
+#[async_trait]
+impl FsmState<Valve> for Open {}
+
+// actions
+
+pub struct ArmValve;
+#[async_trait]
+impl FsmActionSelf<Valve, Closed> for ArmValve {
+	async fn action(_event_context: &EventContext<'_, Valve>, state: &Closed) -> FsmTransitionResult<()> {
+		*state.armed.write().await = true;
+
+		Ok(())
+	}
+}
+
+#[derive(Debug)]
+pub struct ValveContext {
+	limit: u32
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct ValveDefinition(
+	InitialState<Valve, Closed>,
+	ContextType<ValveContext>,
+
+	TransitionInternal< Valve, Closed, Arm,                ArmValve >,
+	TransitionGuard   < Valve, Closed, Pressure, Open,     NoAction,  OverLimit >,
+);
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_async_guard() {
+	let context = std::sync::Arc::new(tokio::sync::RwLock::new(ValveContext { limit: 10 }));
+	let fsm = Valve::new(&context);
+	fsm.start().await;
+
+	// not armed yet
+	assert_eq!(fsm.process_event(ValveEvents::Pressure(Pressure(20))).await, Err(FsmError::NoTransition));
+	assert_matches!(fsm.get_current_state().await, ValveStates::Closed(_));
+
+	fsm.process_event(ValveEvents::Arm(Arm)).await.unwrap();
+
+	assert_eq!(fsm.process_event(ValveEvents::Pressure(Pressure(5))).await, Err(FsmError::NoTransition));
+	assert_matches!(fsm.get_current_state().await, ValveStates::Closed(_));
+
+	context.write().await.limit = 4;
+	fsm.process_event(ValveEvents::Pressure(Pressure(5))).await.unwrap();
+	assert_matches!(fsm.get_current_state().await, ValveStates::Open(_));
+}
+
+// self and internal transitions with async guards
+
+#[derive(Debug, Default)]
+pub struct PumpContext {
+	limit: u32,
+	guards: Vec<&'static str>,
+	entries: usize,
+	low_readings: usize
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Running;
+#[async_trait]
+impl FsmState<Pump> for Running {
+	async fn on_entry(&self, event_context: &EventContext<'_, Pump>) -> FsmTransitionResult<()> {
+		event_context.context.write().await.entries += 1;
+		Ok(())
+	}
+}
+
+fn pressure(event_context: &EventContext<'_, Pump>) -> u32 {
+	match event_context.event {
+		PumpEvents::Pressure(Pressure(p)) => *p,
+		_ => 0
+	}
+}
+
+pub struct HighPressure;
+#[async_trait]
+impl FsmGuardAsync<Pump, Running> for HighPressure {
+	async fn guard(event_context: &EventContext<'_, Pump>, _source_state: &Running, _states: &PumpStatesStore) -> bool {
+		let mut context = event_context.context.write().await;
+		context.guards.push("HighPressure");
+		pressure(event_context) > context.limit
+	}
+}
+
+pub struct LowPressure;
+#[async_trait]
+impl FsmGuardAsync<Pump, Running> for LowPressure {
+	async fn guard(event_context: &EventContext<'_, Pump>, _source_state: &Running, _states: &PumpStatesStore) -> bool {
+		let mut context = event_context.context.write().await;
+		context.guards.push("LowPressure");
+		pressure(event_context) <= context.limit
+	}
+}
+
+pub struct CountLow;
+#[async_trait]
+impl FsmActionSelf<Pump, Running> for CountLow {
+	async fn action(event_context: &EventContext<'_, Pump>, _state: &Running) -> FsmTransitionResult<()> {
+		event_context.context.write().await.low_readings += 1;
+		Ok(())
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct PumpDefinition(
+	InitialState<Pump, Running>,
+	ContextType<PumpContext>,
+
+	TransitionSelfGuard    < Pump, Running, Pressure, NoAction, HighPressure >,
+	TransitionInternalGuard< Pump, Running, Pressure, CountLow, LowPressure >,
+);
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_async_guard_self_and_internal() {
+	let context = std::sync::Arc::new(tokio::sync::RwLock::new(PumpContext { limit: 10, ..Default::default() }));
+	let fsm = Pump::new(&context);
+	fsm.start().await;
+	context.write().await.entries = 0;
+
+	// the first guard passes, the second one isn't awaited
+	fsm.process_event(PumpEvents::Pressure(Pressure(20))).await.unwrap();
+	assert_eq!(context.read().await.guards, vec!["HighPressure"]);
+	assert_eq!(context.read().await.entries, 1);
+
+	fsm.process_event(PumpEvents::Pressure(Pressure(5))).await.unwrap();
+	assert_eq!(context.read().await.guards, vec!["HighPressure", "HighPressure", "LowPressure"]);
+	assert_eq!(context.read().await.entries, 1);
+	assert_eq!(context.read().await.low_readings, 1);
+}
@@ -0,0 +1,123 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Item(u32);
+impl FsmEvent for Item {}
+
+// states
+
+#[derive(Debug, Clone, Default)]
+pub struct Running;
+#[async_trait]
+impl FsmState<Conveyor> for Running {}
+
+// actions
+
+pub struct Sort;
+#[async_trait]
+impl FsmActionSelf<Conveyor, Running> for Sort {
+	async fn action(event_context: &EventContext<'_, Conveyor>, _state: &Running) -> FsmTransitionResult<()> {
+		if let ConveyorEvents::Item(Item(n)) = event_context.event {
+			event_context.context.write().await.sorted.push(*n);
+		}
+
+		Ok(())
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct ConveyorContext {
+	sorted: Vec<u32>
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct ConveyorDefinition(
+	InitialState<Conveyor, Running>,
+	ContextType<ConveyorContext>,
+
+	TransitionInternal< Conveyor, Running, Item, Sort >,
+);
+
+async fn conveyor(capacity: usize, overflow: FsmQueueOverflow) -> Conveyor {
+	let queue = std::sync::Arc::new(tokio::sync::RwLock::new(FsmEventQueueBounded::new(capacity, overflow)));
+	let mut fsm = Conveyor::new_with_queue(&Default::default(), queue);
+	fsm.execute_queue_pre = false;
+	fsm.start().await;
+
+	fsm
+}
+
+async fn enqueue(fsm: &Conveyor, n: u32) -> FsmEnqueueResult {
+	fsm.get_queue().write().await.enqueue_event(ConveyorEvents::Item(Item(n)))
+}
+
+async fn sorted(fsm: &Conveyor) -> Vec<u32> {
+	fsm.execute_queued_events().await;
+
+	let sorted = fsm.get_context().read().await.sorted.clone();
+	sorted
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_overflow_reject() {
+	let fsm = conveyor(2, FsmQueueOverflow::Reject).await;
+
+	assert_eq!(enqueue(&fsm, 1).await, Ok(FsmEnqueueStatus::Queued));
+	assert_eq!(enqueue(&fsm, 2).await, Ok(FsmEnqueueStatus::Queued));
+	assert_eq!(enqueue(&fsm, 3).await, Err(FsmQueueError::Full));
+	assert_eq!(fsm.get_message_queue_size().await, 2);
+
+	assert_eq!(sorted(&fsm).await, vec![1, 2]);
+	assert_eq!(enqueue(&fsm, 4).await, Ok(FsmEnqueueStatus::Queued));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_overflow_drop() {
+	let fsm = conveyor(2, FsmQueueOverflow::DropOldest).await;
+
+	enqueue(&fsm, 1).await.unwrap();
+	enqueue(&fsm, 2).await.unwrap();
+	assert_eq!(enqueue(&fsm, 3).await, Ok(FsmEnqueueStatus::DroppedOldest));
+	assert_eq!(sorted(&fsm).await, vec![2, 3]);
+
+	let fsm = conveyor(2, FsmQueueOverflow::DropNewest).await;
+
+	enqueue(&fsm, 1).await.unwrap();
+	enqueue(&fsm, 2).await.unwrap();
+	assert_eq!(enqueue(&fsm, 3).await, Ok(FsmEnqueueStatus::DroppedNewest));
+	assert_eq!(sorted(&fsm).await, vec![1, 2]);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_overflow_wait() {
+	let fsm = conveyor(1, FsmQueueOverflow::Wait).await;
+	enqueue(&fsm, 1).await.unwrap();
+
+	// the queue doesn't accept the event without waiting
+	assert_eq!(enqueue(&fsm, 2).await, Err(FsmQueueError::Full));
+
+	let queue = std::sync::Arc::clone(fsm.get_queue());
+	let producer = tokio::spawn(async move {
+		fsm_enqueue_event(&queue, ConveyorEvents::Item(Item(2)), 0).await
+	});
+
+	tokio::task::yield_now().await;
+	assert_eq!(fsm.get_message_queue_size().await, 1);
+
+	fsm.execute_single_queued_event().await;
+	assert_eq!(producer.await.unwrap(), Ok(FsmEnqueueStatus::Queued));
+
+	assert_eq!(sorted(&fsm).await, vec![1, 2]);
+}
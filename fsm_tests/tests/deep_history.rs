@@ -0,0 +1,203 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Start;
+impl FsmEvent for Start {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Pause;
+impl FsmEvent for Pause {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Resume;
+impl FsmEvent for Resume {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Restart;
+impl FsmEvent for Restart {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Undock;
+impl FsmEvent for Undock {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Grip;
+impl FsmEvent for Grip {}
+
+// Robot
+
+#[derive(Debug, Clone, Default)]
+pub struct Idle;
+#[async_trait]
+impl FsmState<Robot> for Idle {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Paused;
+#[async_trait]
+impl FsmState<Robot> for Paused {}
+
+#[async_trait]
+impl FsmState<Robot> for ToolChanger {}
+
+impl FsmStateFactory<()> for ToolChanger {
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		ToolChanger::new(parent_context)
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct RobotDefinition(
+	InitialState<Robot, Idle>,
+
+	SubMachine<ToolChanger>,
+	DeepHistory<Robot, Resume, ToolChanger>,
+	ShallowHistory<Robot, Restart, ToolChanger>,
+
+	Transition<Robot, Idle,        Start,   ToolChanger, NoAction>,
+	Transition<Robot, ToolChanger, Pause,   Paused,      NoAction>,
+	Transition<Robot, Paused,      Resume,  ToolChanger, NoAction>,
+	Transition<Robot, Paused,      Restart, ToolChanger, NoAction>,
+);
+
+// ToolChanger
+
+#[derive(Debug, Clone, Default)]
+pub struct Docked;
+#[async_trait]
+impl FsmState<ToolChanger> for Docked {}
+
+#[async_trait]
+impl FsmState<ToolChanger> for Gripper {}
+
+impl FsmStateFactory<()> for Gripper {
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		Gripper::new(parent_context)
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct ToolChangerDefinition(
+	InitialState<ToolChanger, Docked>,
+
+	SubMachine<Gripper>,
+
+	Transition<ToolChanger, Docked, Undock, Gripper, NoAction>,
+);
+
+// Gripper
+
+#[derive(Debug, Clone, Default)]
+pub struct Released;
+#[async_trait]
+impl FsmState<Gripper> for Released {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Gripping {
+	entry: FsmArc<usize>,
+	exit: FsmArc<usize>
+}
+#[async_trait]
+impl FsmState<Gripper> for Gripping {
+	async fn on_entry(&self, _event_context: &EventContext<'_, Gripper>) -> FsmTransitionResult<()> {
+		*self.entry.write().await += 1;
+
+		Ok(())
+	}
+
+	async fn on_exit(&self, _event_context: &EventContext<'_, Gripper>) -> FsmTransitionResult<()> {
+		*self.exit.write().await += 1;
+
+		Ok(())
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct GripperDefinition(
+	InitialState<Gripper, Released>,
+
+	Transition<Gripper, Released, Grip, Gripping, NoAction>,
+);
+
+async fn grip(robot: &Robot) {
+	robot.process_event(RobotEvents::Start(Start)).await.unwrap();
+
+	let tool_changer: FsmArc<ToolChanger> = robot.get_state();
+	let tool_changer = tool_changer.read().await;
+	tool_changer.process_event(ToolChangerEvents::Undock(Undock)).await.unwrap();
+
+	let gripper: FsmArc<Gripper> = tool_changer.get_state();
+	let gripper = gripper.read().await;
+	gripper.process_event(GripperEvents::Grip(Grip)).await.unwrap();
+}
+
+async fn gripper_state(robot: &Robot) -> (ToolChangerStates, GripperStates) {
+	let tool_changer: FsmArc<ToolChanger> = robot.get_state();
+	let tool_changer = tool_changer.read().await;
+
+	let gripper: FsmArc<Gripper> = tool_changer.get_state();
+	let gripper = gripper.read().await;
+
+	(tool_changer.get_current_state().await, gripper.get_current_state().await)
+}
+
+async fn gripping_counters(robot: &Robot) -> (usize, usize) {
+	let tool_changer: FsmArc<ToolChanger> = robot.get_state();
+	let tool_changer = tool_changer.read().await;
+
+	let gripper: FsmArc<Gripper> = tool_changer.get_state();
+	let gripper = gripper.read().await;
+
+	let gripping: FsmArc<Gripping> = gripper.get_state();
+	let gripping = gripping.read().await;
+
+	let counters = (*gripping.entry.read().await, *gripping.exit.read().await);
+	counters
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_deep_history() {
+	let robot = Robot::new(&Default::default());
+	robot.start().await;
+
+	grip(&robot).await;
+	assert_matches!(gripper_state(&robot).await, (ToolChangerStates::Gripper(_), GripperStates::Gripping(_)));
+	assert_eq!(gripping_counters(&robot).await, (1, 0));
+
+	robot.process_event(RobotEvents::Pause(Pause)).await.unwrap();
+	assert_matches!(robot.get_current_state().await, RobotStates::Paused(_));
+	assert_eq!(gripping_counters(&robot).await, (1, 1));
+
+	robot.process_event(RobotEvents::Resume(Resume)).await.unwrap();
+	assert_matches!(robot.get_current_state().await, RobotStates::ToolChanger(_));
+	assert_matches!(gripper_state(&robot).await, (ToolChangerStates::Gripper(_), GripperStates::Gripping(_)));
+	assert_eq!(gripping_counters(&robot).await, (2, 1));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_shallow_history_restarts_nested_submachines() {
+	let robot = Robot::new(&Default::default());
+	robot.start().await;
+
+	grip(&robot).await;
+
+	robot.process_event(RobotEvents::Pause(Pause)).await.unwrap();
+	robot.process_event(RobotEvents::Restart(Restart)).await.unwrap();
+
+	assert_matches!(robot.get_current_state().await, RobotStates::ToolChanger(_));
+	assert_matches!(gripper_state(&robot).await, (ToolChangerStates::Gripper(_), GripperStates::Released(_)));
+	assert_eq!(gripping_counters(&robot).await, (1, 1));
+}
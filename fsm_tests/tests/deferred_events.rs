@@ -0,0 +1,159 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Loaded;
+impl FsmEvent for Loaded {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Process;
+impl FsmEvent for Process {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Done;
+impl FsmEvent for Done {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Tick;
+impl FsmEvent for Tick {}
+
+// states
+
+#[derive(Debug, Clone, Default)]
+pub struct Loading;
+#[async_trait]
+impl FsmState<Loader> for Loading {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Ready;
+#[async_trait]
+impl FsmState<Loader> for Ready {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Processing;
+#[async_trait]
+impl FsmState<Loader> for Processing {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Monitoring;
+#[async_trait]
+impl FsmState<Loader> for Monitoring {}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct LoaderDefinition(
+	InitialState<Loader, (Loading, Monitoring)>,
+
+	Transition        < Loader, Loading,    Loaded, Ready,      NoAction >,
+	Transition        < Loader, Ready,      Process, Processing, NoAction >,
+	Transition        < Loader, Processing, Done,   Ready,      NoAction >,
+	TransitionInternal< Loader, Monitoring, Tick,               NoAction >,
+
+	DeferredEvent     < Loader, (Loading, Processing), Process >
+);
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_deferred_events() {
+	let fsm = Loader::new(&Default::default());
+	fsm.start().await;
+	assert_matches!(fsm.get_current_state().await, (LoaderStates::Loading(_), LoaderStates::Monitoring(_)));
+
+	fsm.process_event(LoaderEvents::Process(Process)).await.unwrap();
+	assert_matches!(fsm.get_current_state().await, (LoaderStates::Loading(_), LoaderStates::Monitoring(_)));
+	assert_eq!(fsm.get_deferred_queue().read().await.len(), 1);
+
+	// an event handled by the other region doesn't release the deferred event
+	fsm.process_event(LoaderEvents::Tick(Tick)).await.unwrap();
+	assert_eq!(fsm.get_deferred_queue().read().await.len(), 1);
+
+	fsm.process_event(LoaderEvents::Loaded(Loaded)).await.unwrap();
+	assert_matches!(fsm.get_current_state().await, (LoaderStates::Processing(_), LoaderStates::Monitoring(_)));
+	assert_eq!(fsm.get_deferred_queue().read().await.len(), 0);
+
+	// events without a deferral are still rejected
+	assert_eq!(fsm.process_event(LoaderEvents::Loaded(Loaded)).await, Err(FsmError::NoTransition));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_deferred_queued_events() {
+	let fsm = Loader::new(&Default::default());
+	fsm.start().await;
+
+	fsm.process_event(LoaderEvents::Loaded(Loaded)).await.unwrap();
+	fsm.process_event(LoaderEvents::Process(Process)).await.unwrap();
+	assert_matches!(fsm.get_current_state().await, (LoaderStates::Processing(_), LoaderStates::Monitoring(_)));
+
+	{
+		let mut queue = fsm.get_queue().write().await;
+		queue.enqueue_event(LoaderEvents::Process(Process)).unwrap();
+		queue.enqueue_event(LoaderEvents::Process(Process)).unwrap();
+	}
+	fsm.execute_queued_events().await;
+	assert_eq!(fsm.get_deferred_queue().read().await.len(), 2);
+
+	// the first deferred event is taken by Ready, the second one is deferred again by Processing
+	fsm.process_event(LoaderEvents::Done(Done)).await.unwrap();
+	assert_matches!(fsm.get_current_state().await, (LoaderStates::Processing(_), LoaderStates::Monitoring(_)));
+	assert_eq!(fsm.get_deferred_queue().read().await.len(), 1);
+
+	fsm.process_event(LoaderEvents::Done(Done)).await.unwrap();
+	assert_matches!(fsm.get_current_state().await, (LoaderStates::Processing(_), LoaderStates::Monitoring(_)));
+	assert_eq!(fsm.get_deferred_queue().read().await.len(), 0);
+}
+
+// a deferred event released into an interrupt state
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Halt;
+impl FsmEvent for Halt {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Resume;
+impl FsmEvent for Resume {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Waiting;
+#[async_trait]
+impl FsmState<Picker> for Waiting {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Halted;
+#[async_trait]
+impl FsmState<Picker> for Halted {}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct PickerDefinition(
+	InitialState<Picker, Waiting>,
+
+	Transition        < Picker, Waiting, Halt,    Halted,  NoAction >,
+	Transition        < Picker, Halted,  Resume,  Waiting, NoAction >,
+
+	InterruptState    < Picker, Halted, Resume >,
+	DeferredEvent     < Picker, Waiting, Process >
+);
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_deferred_event_errors() {
+	let fsm = Picker::new(&Default::default());
+	fsm.start().await;
+
+	fsm.process_event(PickerEvents::Process(Process)).await.unwrap();
+	assert_eq!(fsm.get_deferred_queue().read().await.len(), 1);
+
+	// the transition happens, the released event is rejected by the interrupt state
+	assert_eq!(fsm.process_event(PickerEvents::Halt(Halt)).await, Err(FsmError::Interrupted));
+	assert_matches!(fsm.get_current_state().await, PickerStates::Halted(_));
+	assert_eq!(fsm.get_deferred_queue().read().await.len(), 0);
+}
@@ -0,0 +1,8 @@
+// The definitions that the `Fsm` derive rejects, with the diagnostics in `ui/*.stderr`.
+// Regenerate them with `TRYBUILD=overwrite cargo test --test definition_errors`.
+
+#[test]
+fn test_definition_errors() {
+	let t = trybuild::TestCases::new();
+	t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,145 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+use std::sync::Mutex;
+
+use fsm::*;
+
+static ENTRIES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+fn take_entries() -> Vec<&'static str> {
+	std::mem::take(&mut *ENTRIES.lock().unwrap())
+}
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Load;
+impl FsmEvent for Load {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Resume;
+impl FsmEvent for Resume {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Unload;
+impl FsmEvent for Unload {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Picked;
+impl FsmEvent for Picked {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Calibrated;
+impl FsmEvent for Calibrated {}
+
+// Cell
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Idle;
+#[async_trait]
+impl FsmState<Cell> for Idle { }
+
+#[async_trait]
+impl FsmState<Cell> for Assembly {
+	async fn on_entry(&self, _event_context: &EventContext<'_, Cell>) -> FsmTransitionResult<()> {
+		ENTRIES.lock().unwrap().push("Assembly");
+		Ok(())
+	}
+}
+
+impl FsmStateFactory<()> for Assembly {
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		Assembly::new(parent_context)
+	}
+}
+
+pub struct LogResume;
+#[async_trait]
+impl FsmAction<Cell, Idle, EntryPoint<Cell, Assembly, Calibrating>> for LogResume {
+	async fn action(_: &EventContext<'_, Cell>, _: &Idle, _: &EntryPoint<Cell, Assembly, Calibrating>) -> FsmTransitionResult<()> {
+		ENTRIES.lock().unwrap().push("LogResume");
+		Ok(())
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct CellDefinition(
+	InitialState<Cell, Idle>,
+
+	SubMachine<Assembly>,
+
+	Transition<Cell, Idle,     Load,   Assembly,                                NoAction>,
+	Transition<Cell, Idle,     Resume, EntryPoint<Cell, Assembly, Calibrating>, LogResume>,
+	Transition<Cell, Assembly, Unload, Idle,                                    NoAction>
+);
+
+// Assembly
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Picking;
+#[async_trait]
+impl FsmState<Assembly> for Picking {
+	async fn on_entry(&self, _event_context: &EventContext<'_, Assembly>) -> FsmTransitionResult<()> {
+		ENTRIES.lock().unwrap().push("Picking");
+		Ok(())
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Calibrating;
+#[async_trait]
+impl FsmState<Assembly> for Calibrating {
+	async fn on_entry(&self, _event_context: &EventContext<'_, Assembly>) -> FsmTransitionResult<()> {
+		ENTRIES.lock().unwrap().push("Calibrating");
+		Ok(())
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Screwing;
+#[async_trait]
+impl FsmState<Assembly> for Screwing { }
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct AssemblyDefinition(
+	InitialState<Assembly, Picking>,
+
+	Transition<Assembly, Picking,     Picked,     Calibrating, NoAction>,
+	Transition<Assembly, Calibrating, Calibrated, Screwing,    NoAction>
+);
+
+async fn assembly_state(cell: &Cell) -> AssemblyStates {
+	let assembly: FsmArc<Assembly> = cell.get_state();
+	let assembly = assembly.read().await;
+	assembly.get_current_state().await
+}
+
+#[tokio::test]
+async fn test_entry_point() {
+	let cell = Cell::new(&Default::default());
+	cell.start().await;
+
+	cell.process_event(CellEvents::Load(Load)).await.unwrap();
+	assert_eq!(take_entries(), vec!["Assembly", "Picking"]);
+	assert_matches!(assembly_state(&cell).await, AssemblyStates::Picking(_));
+
+	cell.process_event(CellEvents::Unload(Unload)).await.unwrap();
+
+	// the initial state of the submachine is skipped
+	cell.process_event(CellEvents::Resume(Resume)).await.unwrap();
+	assert_eq!(take_entries(), vec!["LogResume", "Assembly", "Calibrating"]);
+	assert_matches!(cell.get_current_state().await, CellStates::Assembly(_));
+	assert_matches!(assembly_state(&cell).await, AssemblyStates::Calibrating(_));
+
+	{
+		let assembly: FsmArc<Assembly> = cell.get_state();
+		assembly.read().await.process_event(AssemblyEvents::Calibrated(Calibrated)).await.unwrap();
+	}
+	assert_matches!(assembly_state(&cell).await, AssemblyStates::Screwing(_));
+}
@@ -26,6 +26,10 @@ impl FsmEvent for GoToChild {}
 pub struct GoToBrokenChild;
 impl FsmEvent for GoToBrokenChild {}
 
+#[derive(Clone, PartialEq, Debug)]
+pub struct GoToFailingAction;
+impl FsmEvent for GoToFailingAction {}
+
 // ------------------------------------------
 
 #[derive(Debug, Clone, Default)]
@@ -46,24 +50,36 @@ pub struct Recovered;
 // ------------------------------------------
 
 trait WithFailure {
-  fn fail(&self) -> FsmTransitionResult<()> {
-    Err(FsmTransitionError(
-      Arc::new(std::io::Error::last_os_error().into())
-    ))
-  }
+	fn fail(&self) -> FsmTransitionResult<()> {
+		Err(FsmTransitionError(
+			Arc::new(std::io::Error::last_os_error().into())
+		))
+	}
 }
 
 impl WithFailure for InitialWithFailure {}
 
 impl WithFailure for ProcessWithFailure {}
 
+impl WithFailure for FailingAction {}
+
+// ------------------------------------------
+
+pub struct FailingAction;
+#[async_trait]
+impl FsmAction<Parent, Initial, Recovered> for FailingAction {
+	async fn action(_: &EventContext<'_, Parent>, _: &Initial, _: &Recovered) -> FsmTransitionResult<()> {
+		FailingAction.fail()
+	}
+}
+
 // ------------------------------------------
 
 #[async_trait]
 impl FsmState<Broken> for InitialWithFailure {
-  async fn on_entry(&self, _: &EventContext<'_, Broken>) -> FsmTransitionResult<()> {
-    self.fail()
-  }
+	async fn on_entry(&self, _: &EventContext<'_, Broken>) -> FsmTransitionResult<()> {
+		self.fail()
+	}
 }
 
 #[async_trait]
@@ -76,9 +92,9 @@ impl FsmState<Parent> for Initial {}
 
 #[async_trait]
 impl FsmState<Parent> for ProcessWithFailure {
-  async fn on_entry(&self, _: &EventContext<'_, Parent>) -> FsmTransitionResult<()> {
-    self.fail()
-  }
+	async fn on_entry(&self, _: &EventContext<'_, Parent>) -> FsmTransitionResult<()> {
+		self.fail()
+	}
 }
 
 #[async_trait]
@@ -97,9 +113,9 @@ impl FsmState<Parent> for BrokenChild {}
 
 #[async_trait]
 impl FsmState<BrokenChild> for InitialWithFailure {
-  async fn on_entry(&self, _: &EventContext<'_, BrokenChild>) -> FsmTransitionResult<()> {
-    self.fail()
-  }
+	async fn on_entry(&self, _: &EventContext<'_, BrokenChild>) -> FsmTransitionResult<()> {
+		self.fail()
+	}
 }
 
 #[async_trait]
@@ -112,9 +128,9 @@ impl FsmState<Child> for Initial {}
 
 #[async_trait]
 impl FsmState<Child> for ProcessWithFailure {
-  async fn on_entry(&self, _: &EventContext<'_, Child>) -> FsmTransitionResult<()> {
-    self.fail()
-  }
+	async fn on_entry(&self, _: &EventContext<'_, Child>) -> FsmTransitionResult<()> {
+		self.fail()
+	}
 }
 
 #[async_trait]
@@ -124,16 +140,16 @@ impl FsmState<Child> for Error {}
 
 #[async_trait]
 impl FsmStateFactory<()> for Child {
-  fn new_state(parent_context: &FsmArc<()>) -> Self {
-    Child::new(parent_context)
-  }
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		Child::new(parent_context)
+	}
 }
 
 #[async_trait]
 impl FsmStateFactory<()> for BrokenChild {
-  fn new_state(parent_context: &FsmArc<()>) -> Self {
-    BrokenChild::new(parent_context)
-  }
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		BrokenChild::new(parent_context)
+	}
 }
 
 // --------------------------------------------
@@ -142,39 +158,40 @@ impl FsmStateFactory<()> for BrokenChild {
 #[allow(dead_code)]
 struct BrokenDefinition(
 	InitialState<Broken, InitialWithFailure>,
-  ErrorState<Broken, Error>,
+	ErrorState<Broken, Error>,
 );
 
 #[derive(Fsm)]
 #[allow(dead_code)]
 struct ParentDefinition(
 	InitialState<Parent, Initial>,
-  ErrorState<Parent, Error>,
+	ErrorState<Parent, Error>,
 
-  SubMachine<Child>,
-  SubMachine<BrokenChild>,
+	SubMachine<Child>,
+	SubMachine<BrokenChild>,
 
-  Transition<Parent, Initial, GoToProcess, ProcessWithFailure, NoAction>,
-  Transition<Parent, Error, GoToRecovered, Recovered, NoAction>,
+	Transition<Parent, Initial, GoToProcess, ProcessWithFailure, NoAction>,
+	Transition<Parent, Error, GoToRecovered, Recovered, NoAction>,
 
-  Transition<Parent, Initial, GoToChild, Child, NoAction>,
-  Transition<Parent, Initial, GoToBrokenChild, BrokenChild, NoAction>,
+	Transition<Parent, Initial, GoToChild, Child, NoAction>,
+	Transition<Parent, Initial, GoToBrokenChild, BrokenChild, NoAction>,
+	Transition<Parent, Initial, GoToFailingAction, Recovered, FailingAction>,
 );
 
 #[derive(Fsm)]
 #[allow(dead_code)]
 struct BrokenChildDefinition(
 	InitialState<BrokenChild, InitialWithFailure>,
-  ErrorState<BrokenChild, Error>,
+	ErrorState<BrokenChild, Error>,
 );
 
 #[derive(Fsm)]
 #[allow(dead_code)]
 struct ChildDefinition(
 	InitialState<Child, Initial>,
-  ErrorState<Child, Error>,
+	ErrorState<Child, Error>,
 
-  Transition<Child, Initial, GoToProcess, ProcessWithFailure, NoAction>,
+	Transition<Child, Initial, GoToProcess, ProcessWithFailure, NoAction>,
 );
 
 #[cfg(test)]
@@ -200,9 +217,9 @@ async fn test_error_during_processing_event() {
 
 	assert_matches!(fsm.get_current_state().await, ParentStates::Initial(_));
 
-  fsm.process_event(ParentEvents::GoToProcess(GoToProcess)).await.unwrap();
+	fsm.process_event(ParentEvents::GoToProcess(GoToProcess)).await.unwrap();
 
-  assert_matches!(fsm.get_current_state().await, ParentStates::Error(_));
+	assert_matches!(fsm.get_current_state().await, ParentStates::Error(_));
 }
 
 #[cfg(test)]
@@ -212,13 +229,13 @@ async fn test_error_recovery() {
 
 	fsm.start().await;
 
-  fsm.process_event(ParentEvents::GoToProcess(GoToProcess)).await.unwrap();
+	fsm.process_event(ParentEvents::GoToProcess(GoToProcess)).await.unwrap();
 
-  assert_matches!(fsm.get_current_state().await, ParentStates::Error(_));
+	assert_matches!(fsm.get_current_state().await, ParentStates::Error(_));
 
-  fsm.process_event(ParentEvents::GoToRecovered(GoToRecovered)).await.unwrap();
+	fsm.process_event(ParentEvents::GoToRecovered(GoToRecovered)).await.unwrap();
 
-  assert_matches!(fsm.get_current_state().await, ParentStates::Recovered(_));
+	assert_matches!(fsm.get_current_state().await, ParentStates::Recovered(_));
 }
 
 #[cfg(test)]
@@ -228,16 +245,16 @@ async fn test_error_in_initial_state_of_child() {
 
 	parent.start().await;
 
-  parent.process_event(ParentEvents::GoToBrokenChild(GoToBrokenChild)).await.unwrap();
+	parent.process_event(ParentEvents::GoToBrokenChild(GoToBrokenChild)).await.unwrap();
 
-  assert_matches!(parent.get_current_state().await, ParentStates::BrokenChild(_));
+	assert_matches!(parent.get_current_state().await, ParentStates::BrokenChild(_));
 
-  {
-    let child: FsmArc<BrokenChild> = parent.get_state();
-    let child = child.read().await;
+	{
+		let child: FsmArc<BrokenChild> = parent.get_state();
+		let child = child.read().await;
 
-    assert_matches!(child.get_current_state().await, BrokenChildStates::Error(_));
-  }
+		assert_matches!(child.get_current_state().await, BrokenChildStates::Error(_));
+	}
 }
 
 #[cfg(test)]
@@ -247,18 +264,30 @@ async fn test_error_during_processing_event_in_child() {
 
 	parent.start().await;
 
-  parent.process_event(ParentEvents::GoToChild(GoToChild)).await.unwrap();
+	parent.process_event(ParentEvents::GoToChild(GoToChild)).await.unwrap();
+
+	assert_matches!(parent.get_current_state().await, ParentStates::Child(_));
 
-  assert_matches!(parent.get_current_state().await, ParentStates::Child(_));
+	{
+		let child: FsmArc<Child> = parent.get_state();
+		let child = child.read().await;
 
-  {
-    let child: FsmArc<Child> = parent.get_state();
-    let child = child.read().await;
+		assert_matches!(child.get_current_state().await, ChildStates::Initial(_));
 
-    assert_matches!(child.get_current_state().await, ChildStates::Initial(_));
+		child.process_event(ChildEvents::GoToProcess(GoToProcess)).await.unwrap();
+
+		assert_matches!(child.get_current_state().await, ChildStates::Error(_));
+	}
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_error_in_transition_action() {
+	let fsm = Parent::new(&Default::default());
+
+	fsm.start().await;
 
-    child.process_event(ChildEvents::GoToProcess(GoToProcess)).await.unwrap();
+	fsm.process_event(ParentEvents::GoToFailingAction(GoToFailingAction)).await.unwrap();
 
-    assert_matches!(child.get_current_state().await, ChildStates::Error(_));
-  }
+	assert_matches!(fsm.get_current_state().await, ParentStates::Error(_));
 }
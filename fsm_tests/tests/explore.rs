@@ -0,0 +1,144 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+
+use fsm::*;
+use fsm::testing::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Fill(u32);
+impl FsmEvent for Fill {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Drain(u32);
+impl FsmEvent for Drain {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Seal;
+impl FsmEvent for Seal {}
+
+// states
+
+#[derive(Debug, Clone, Default)]
+pub struct Open;
+#[async_trait]
+impl FsmState<Tank> for Open {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sealed;
+#[async_trait]
+impl FsmState<Tank> for Sealed {}
+
+// actions
+
+pub struct FillTank;
+#[async_trait]
+impl FsmActionSelf<Tank, Open> for FillTank {
+	async fn action(event_context: &EventContext<'_, Tank>, _: &Open) -> FsmTransitionResult<()> {
+		if let TankEvents::Fill(Fill(amount)) = event_context.event {
+			// no overflow check
+			event_context.context.write().await.level += amount;
+		}
+
+		Ok(())
+	}
+}
+
+pub struct DrainTank;
+#[async_trait]
+impl FsmActionSelf<Tank, Open> for DrainTank {
+	async fn action(event_context: &EventContext<'_, Tank>, _: &Open) -> FsmTransitionResult<()> {
+		if let TankEvents::Drain(Drain(amount)) = event_context.event {
+			let mut context = event_context.context.write().await;
+			context.level = context.level.saturating_sub(*amount);
+		}
+
+		Ok(())
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct TankContext {
+	level: u32
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct TankDefinition(
+	InitialState<Tank, Open>,
+	ContextType<TankContext>,
+
+	TransitionInternal<Tank, Open, Fill,  FillTank>,
+	TransitionInternal<Tank, Open, Drain, DrainTank>,
+	Transition        <Tank, Open, Seal,  Sealed,   NoAction>,
+);
+
+fn explorer() -> FsmExplorer<Tank> {
+	FsmExplorer::new(
+		|| Tank::new(&Default::default()),
+		|name, random| match name {
+			"Fill" => Some(TankEvents::Fill(Fill(random.below(30) as u32))),
+			"Drain" => Some(TankEvents::Drain(Drain(random.below(30) as u32))),
+			// sealing ends the walk early, keep it rare
+			"Seal" if random.below(10) == 0 => Some(TankEvents::Seal(Seal)),
+			_ => None
+		}
+	)
+}
+
+fn fill_amount(event: &str) -> u32 {
+	event.trim_start_matches("Fill(Fill(").trim_end_matches("))").parse().unwrap()
+}
+
+#[test]
+fn test_variants() {
+	assert_eq!(TankEvents::variants(), &["Fill", "Drain", "Seal"]);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_explore() {
+	let stats = explorer()
+		.invariant("bounded by the fills of a walk", |context, _| match context.level {
+			level if level > 29 * 50 => Err(format!("level {}", level)),
+			_ => Ok(())
+		})
+		.walks(20)
+		.steps(50)
+		.run()
+		.await
+		.unwrap();
+
+	assert!(stats.steps > 0);
+	assert!(stats.visited_states.contains("Open"));
+	assert!(stats.visited_states.contains("Sealed"));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_explore_shrinks_failure() {
+	let failure = explorer()
+		.invariant("no overflow", |context, _| match context.level {
+			level if level > 100 => Err(format!("level {}", level)),
+			_ => Ok(())
+		})
+		.seed(7)
+		.run()
+		.await
+		.unwrap_err();
+
+	assert_eq!(failure.invariant, "no overflow");
+	assert_eq!(failure.state, "Open");
+
+	// only fills remain, and none of them can be removed
+	assert!(failure.events.iter().all(|e| e.starts_with("Fill")));
+	let amounts: Vec<u32> = failure.events.iter().map(|e| fill_amount(e)).collect();
+	let total: u32 = amounts.iter().sum();
+	assert!(total > 100);
+	assert!(amounts.iter().all(|a| total - a <= 100));
+	assert_eq!(failure.message, format!("level {}", total));
+}
@@ -0,0 +1,130 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Begin;
+impl FsmEvent for Begin {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Move;
+impl FsmEvent for Move {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Verify;
+impl FsmEvent for Verify {}
+
+// Job
+
+#[derive(Debug, Clone, Default)]
+pub struct Idle;
+#[async_trait]
+impl FsmState<Job> for Idle {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Finished;
+#[async_trait]
+impl FsmState<Job> for Finished {}
+
+#[async_trait]
+impl FsmState<Job> for Task {}
+
+impl FsmStateFactory<()> for Task {
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		Task::new(parent_context)
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct JobDefinition(
+	InitialState<Job, Idle>,
+
+	SubMachine<Task>,
+
+	Transition<Job, Idle, Begin,           Task,     NoAction>,
+	Transition<Job, Task, Completed<Task>, Finished, NoAction>,
+);
+
+// Task
+
+#[derive(Debug, Clone, Default)]
+pub struct Moving;
+#[async_trait]
+impl FsmState<Task> for Moving {}
+
+#[derive(Debug, Clone, Default)]
+pub struct InPosition;
+#[async_trait]
+impl FsmState<Task> for InPosition {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Checking;
+#[async_trait]
+impl FsmState<Task> for Checking {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Verified;
+#[async_trait]
+impl FsmState<Task> for Verified {}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct TaskDefinition(
+	InitialState<Task, (Moving, Checking)>,
+	FinalState<Task, (InPosition, Verified)>,
+
+	Transition<Task, Moving,   Move,   InPosition, NoAction>,
+	Transition<Task, Checking, Verify, Verified,   NoAction>,
+	TransitionInternal<Task, Verified, Verify, NoAction>,
+);
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_completion_event() {
+	let job = Job::new(&Default::default());
+	job.start().await;
+
+	job.process_event(JobEvents::Begin(Begin)).await.unwrap();
+	assert_matches!(job.get_current_state().await, JobStates::Task(_));
+
+	{
+		let task: FsmArc<Task> = job.get_state();
+		let task = task.read().await;
+		assert!(!task.is_completed().await);
+
+		task.process_event(TaskEvents::Move(Move)).await.unwrap();
+		assert!(!task.is_completed().await);
+	}
+
+	job.execute_queued_events().await;
+	assert_matches!(job.get_current_state().await, JobStates::Task(_));
+
+	{
+		let task: FsmArc<Task> = job.get_state();
+		let task = task.read().await;
+
+		task.process_event(TaskEvents::Verify(Verify)).await.unwrap();
+		assert!(task.is_completed().await);
+		assert_eq!(task.get_current_state().await.name().await, "(InPosition, Verified)");
+	}
+
+	assert_eq!(job.get_message_queue_size().await, 1);
+
+	// staying in the final states doesn't complete the machine again
+	{
+		let task: FsmArc<Task> = job.get_state();
+		task.read().await.process_event(TaskEvents::Verify(Verify)).await.unwrap();
+	}
+	assert_eq!(job.get_message_queue_size().await, 1);
+
+	job.execute_queued_events().await;
+	assert_matches!(job.get_current_state().await, JobStates::Finished(_));
+}
@@ -0,0 +1,313 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Start;
+impl FsmEvent for Start {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct StartLeft;
+impl FsmEvent for StartLeft {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Move;
+impl FsmEvent for Move {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct LeftArrived;
+impl FsmEvent for LeftArrived {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct RightArrived;
+impl FsmEvent for RightArrived {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Collect;
+impl FsmEvent for Collect {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Abort;
+impl FsmEvent for Abort {}
+
+#[derive(Debug, Default)]
+pub struct CellContext {
+	entries: Vec<&'static str>,
+	exits: Vec<&'static str>
+}
+
+async fn log_entry<F: Fsm<C = CellContext>>(event_context: &EventContext<'_, F>, state: &'static str) -> FsmTransitionResult<()> {
+	event_context.context.write().await.entries.push(state);
+	Ok(())
+}
+
+async fn log_exit<F: Fsm<C = CellContext>>(event_context: &EventContext<'_, F>, state: &'static str) -> FsmTransitionResult<()> {
+	event_context.context.write().await.exits.push(state);
+	Ok(())
+}
+
+// the arms, a submachine with a region for each arm
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LeftIdle;
+#[async_trait]
+impl FsmState<Arms> for LeftIdle {
+	async fn on_entry(&self, event_context: &EventContext<'_, Arms>) -> FsmTransitionResult<()> {
+		log_entry(event_context, "LeftIdle").await
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LeftMoving;
+#[async_trait]
+impl FsmState<Arms> for LeftMoving {
+	async fn on_entry(&self, event_context: &EventContext<'_, Arms>) -> FsmTransitionResult<()> {
+		log_entry(event_context, "LeftMoving").await
+	}
+
+	async fn on_exit(&self, event_context: &EventContext<'_, Arms>) -> FsmTransitionResult<()> {
+		log_exit(event_context, "LeftMoving").await
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LeftDone;
+#[async_trait]
+impl FsmState<Arms> for LeftDone {
+	async fn on_exit(&self, event_context: &EventContext<'_, Arms>) -> FsmTransitionResult<()> {
+		log_exit(event_context, "LeftDone").await
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RightIdle;
+#[async_trait]
+impl FsmState<Arms> for RightIdle {
+	async fn on_entry(&self, event_context: &EventContext<'_, Arms>) -> FsmTransitionResult<()> {
+		log_entry(event_context, "RightIdle").await
+	}
+
+	async fn on_exit(&self, event_context: &EventContext<'_, Arms>) -> FsmTransitionResult<()> {
+		log_exit(event_context, "RightIdle").await
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RightMoving;
+#[async_trait]
+impl FsmState<Arms> for RightMoving {
+	async fn on_entry(&self, event_context: &EventContext<'_, Arms>) -> FsmTransitionResult<()> {
+		log_entry(event_context, "RightMoving").await
+	}
+
+	async fn on_exit(&self, event_context: &EventContext<'_, Arms>) -> FsmTransitionResult<()> {
+		log_exit(event_context, "RightMoving").await
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RightDone;
+#[async_trait]
+impl FsmState<Arms> for RightDone {
+	async fn on_exit(&self, event_context: &EventContext<'_, Arms>) -> FsmTransitionResult<()> {
+		log_exit(event_context, "RightDone").await
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct ArmsDefinition(
+	InitialState<Arms, (LeftIdle, RightIdle)>,
+	ContextType<CellContext>,
+
+	Transition<Arms, LeftIdle,    Move,         LeftMoving,  NoAction>,
+	Transition<Arms, LeftMoving,  LeftArrived,  LeftDone,    NoAction>,
+	Transition<Arms, RightIdle,   Move,         RightMoving, NoAction>,
+	Transition<Arms, RightMoving, RightArrived, RightDone,   NoAction>
+);
+
+impl FsmStateFactory<CellContext> for Arms {
+	fn new_state(parent_context: &FsmArc<CellContext>) -> Self {
+		Arms::new(parent_context)
+	}
+}
+
+// the cell
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Idle;
+#[async_trait]
+impl FsmState<Cell> for Idle {
+	async fn on_entry(&self, event_context: &EventContext<'_, Cell>) -> FsmTransitionResult<()> {
+		log_entry(event_context, "Idle").await
+	}
+}
+
+#[async_trait]
+impl FsmState<Cell> for Arms {
+	async fn on_entry(&self, event_context: &EventContext<'_, Cell>) -> FsmTransitionResult<()> {
+		log_entry(event_context, "Arms").await
+	}
+
+	async fn on_exit(&self, event_context: &EventContext<'_, Cell>) -> FsmTransitionResult<()> {
+		log_exit(event_context, "Arms").await
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct CellDefinition(
+	InitialState<Cell, Idle>,
+	ContextType<CellContext>,
+	SubMachine<Arms>,
+	HierarchicalDispatch<Cell, InnerFirst>,
+
+	Fork<Cell, Idle, Start,     Arms, (LeftMoving, RightMoving)>,
+	Fork<Cell, Idle, StartLeft, Arms, LeftMoving>,
+	Join<Cell, Arms, (LeftDone, RightDone), Collect, Idle>,
+
+	Transition<Cell, Arms, Abort, Idle, NoAction>
+);
+
+async fn arms_state(cell: &Cell) -> (ArmsStates, ArmsStates) {
+	let arms: FsmArc<Arms> = cell.get_state();
+	let arms = arms.read().await;
+	arms.get_current_state().await
+}
+
+async fn take_entries(cell: &Cell) -> Vec<&'static str> {
+	std::mem::take(&mut cell.get_context().write().await.entries)
+}
+
+async fn take_exits(cell: &Cell) -> Vec<&'static str> {
+	std::mem::take(&mut cell.get_context().write().await.exits)
+}
+
+#[tokio::test]
+async fn test_fork_join() {
+	let cell = Cell::new(&Default::default());
+	cell.start().await;
+	take_entries(&cell).await;
+
+	assert_matches!(cell.process_event(CellEvents::Collect(Collect)).await, Err(FsmError::NoTransition));
+
+	// the fork enters the submachine in a state of each of its regions
+	cell.process_event(CellEvents::Start(Start)).await.unwrap();
+	assert_matches!(cell.get_current_state().await, CellStates::Arms(_));
+	assert_matches!(arms_state(&cell).await, (ArmsStates::LeftMoving(_), ArmsStates::RightMoving(_)));
+	assert_eq!(take_entries(&cell).await, vec!["Arms", "LeftMoving", "RightMoving"]);
+
+	// only from its source state
+	assert_matches!(cell.process_event(CellEvents::Start(Start)).await, Err(FsmError::NoTransition));
+
+	// the join waits for both arms
+	cell.process_event(ArmsEvents::LeftArrived(LeftArrived).into()).await.unwrap();
+	assert_matches!(cell.process_event(CellEvents::Collect(Collect)).await, Err(FsmError::NoTransition));
+	assert_matches!(cell.get_current_state().await, CellStates::Arms(_));
+	cell.process_event(ArmsEvents::RightArrived(RightArrived).into()).await.unwrap();
+	take_exits(&cell).await;
+
+	// and leaves the submachine, its states first
+	cell.process_event(CellEvents::Collect(Collect)).await.unwrap();
+	assert_matches!(cell.get_current_state().await, CellStates::Idle(_));
+	assert_eq!(take_exits(&cell).await, vec!["LeftDone", "RightDone", "Arms"]);
+	assert_eq!(take_entries(&cell).await, vec!["Idle"]);
+}
+
+#[tokio::test]
+async fn test_fork_into_some_regions() {
+	let cell = Cell::new(&Default::default());
+	cell.start().await;
+	take_entries(&cell).await;
+
+	// the region without a target starts in its initial state
+	cell.process_event(CellEvents::StartLeft(StartLeft)).await.unwrap();
+	assert_matches!(arms_state(&cell).await, (ArmsStates::LeftMoving(_), ArmsStates::RightIdle(_)));
+	assert_eq!(take_entries(&cell).await, vec!["Arms", "LeftMoving", "RightIdle"]);
+
+	// the regions still have their own transitions
+	cell.process_event(ArmsEvents::Move(Move).into()).await.unwrap();
+	assert_matches!(arms_state(&cell).await, (ArmsStates::LeftMoving(_), ArmsStates::RightMoving(_)));
+	assert_eq!(take_exits(&cell).await, vec!["RightIdle"]);
+
+	// the submachine can still be left without the join
+	cell.process_event(CellEvents::Abort(Abort)).await.unwrap();
+	assert_matches!(cell.get_current_state().await, CellStates::Idle(_));
+	assert_eq!(take_exits(&cell).await, vec!["LeftMoving", "RightMoving", "Arms"]);
+}
+
+// the same pseudo states in a transition table
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Released;
+#[async_trait]
+impl FsmState<Clamp> for Released { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Closed;
+#[async_trait]
+impl FsmState<Clamp> for Closed { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Unlocked;
+#[async_trait]
+impl FsmState<Clamp> for Unlocked { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Locked;
+#[async_trait]
+impl FsmState<Clamp> for Locked { }
+
+fsm! {
+	machine Clamp {
+		initial (Released, Unlocked);
+
+		Released + Start => Closed;
+		Unlocked + Start => Locked;
+	}
+}
+
+impl FsmStateFactory<()> for Clamp {
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		Clamp::new(parent_context)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Open;
+#[async_trait]
+impl FsmState<Press> for Open { }
+
+#[async_trait]
+impl FsmState<Press> for Clamp { }
+
+fsm! {
+	machine Press {
+		initial Open;
+		submachine Clamp;
+
+		fork Open + Start => Clamp in (Closed, Locked);
+		join Clamp in (Closed, Locked) + Collect => Open;
+	}
+}
+
+#[tokio::test]
+async fn test_fork_join_table() {
+	let press = Press::new(&Default::default());
+	press.start().await;
+
+	press.process_event(PressEvents::Start(Start)).await.unwrap();
+	assert_matches!(press.get_current_state().await, PressStates::Clamp(_));
+	let clamp: FsmArc<Clamp> = press.get_state();
+	assert_matches!(clamp.read().await.get_current_state().await, (ClampStates::Closed(_), ClampStates::Locked(_)));
+
+	press.process_event(PressEvents::Collect(Collect)).await.unwrap();
+	assert_matches!(press.get_current_state().await, PressStates::Open(_));
+}
@@ -98,24 +98,30 @@ impl FsmState<FsmOne> for State2 {
 pub struct InitAction;
 #[async_trait]
 impl FsmAction<FsmOne, Initial, State1> for InitAction {
-	async fn action(_event_context: &EventContext<'_, FsmOne>, _source_state: &Initial, _target_state: &State1) {
+	async fn action(_event_context: &EventContext<'_, FsmOne>, _source_state: &Initial, _target_state: &State1) -> FsmTransitionResult<()> {
 		println!("Init action!");
+
+		Ok(())
 	}
 }
 
 pub struct State1InternalAction;
 #[async_trait]
 impl FsmActionSelf<FsmOne, State1> for State1InternalAction {
-	async fn action(_event_context: &EventContext<'_, FsmOne>, state: &State1) {
+	async fn action(_event_context: &EventContext<'_, FsmOne>, state: &State1) -> FsmTransitionResult<()> {
 		*state.internal_action.write().await += 1;
+
+		Ok(())
 	}
 }
 
 pub struct InternalTrigger;
 #[async_trait]
 impl FsmActionSelf<FsmOne, State1> for InternalTrigger {
-	async fn action(event_context: &EventContext<'_, FsmOne>, _state: &State1) {
-		event_context.enqueue_event(FsmOneEvents::Event2(Event2)).await;
+	async fn action(event_context: &EventContext<'_, FsmOne>, _state: &State1) -> FsmTransitionResult<()> {
+		event_context.enqueue_event(FsmOneEvents::Event2(Event2)).await.unwrap();
+
+		Ok(())
 	}
 }
 
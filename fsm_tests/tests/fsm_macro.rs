@@ -0,0 +1,139 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct OpenClose;
+impl FsmEvent for OpenClose {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct CdDetected { tracks: usize }
+impl FsmEvent for CdDetected {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Play;
+impl FsmEvent for Play {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Pause;
+impl FsmEvent for Pause {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Stop;
+impl FsmEvent for Stop {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Eject;
+impl FsmEvent for Eject {}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Empty;
+#[async_trait]
+impl FsmState<Player> for Empty { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Open;
+#[async_trait]
+impl FsmState<Player> for Open { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Stopped;
+#[async_trait]
+impl FsmState<Player> for Stopped { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Playing;
+#[async_trait]
+impl FsmState<Player> for Playing { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Paused;
+#[async_trait]
+impl FsmState<Player> for Paused { }
+
+#[derive(Debug, Default)]
+pub struct PlayerContext {
+	drawer_opened: usize,
+	stops: usize
+}
+
+pub struct OpenDrawer;
+#[async_trait]
+impl FsmAction<Player, Empty, Open> for OpenDrawer {
+	async fn action(event_context: &EventContext<'_, Player>, _: &Empty, _: &Open) -> FsmTransitionResult<()> {
+		event_context.context.write().await.drawer_opened += 1;
+		Ok(())
+	}
+}
+
+pub struct CountStop;
+#[async_trait]
+impl FsmActionSelf<Player, Stopped> for CountStop {
+	async fn action(event_context: &EventContext<'_, Player>, _: &Stopped) -> FsmTransitionResult<()> {
+		event_context.context.write().await.stops += 1;
+		Ok(())
+	}
+}
+
+pub struct HasTracks;
+impl FsmGuard<Player> for HasTracks {
+	fn guard(event_context: &EventContext<Player>, _: &PlayerStatesStore) -> bool {
+		matches!(event_context.event, PlayerEvents::CdDetected(CdDetected { tracks }) if *tracks > 0)
+	}
+}
+
+fsm! {
+	/// A CD player, with the transition table of the Boost.MSM tutorial.
+	machine Player {
+		initial Empty;
+		context PlayerContext;
+
+		Empty + OpenClose => Open / OpenDrawer;
+		Open + OpenClose => Empty;
+		/// A disc without tracks stays in the drawer.
+		Empty + CdDetected [HasTracks] => Stopped;
+
+		Stopped + Play => Playing;
+		Stopped + Stop / CountStop;
+		Playing + Pause => Paused;
+		Paused + Pause => Playing;
+		Paused + Play => self;
+
+		(Playing, Paused) + Stop => Stopped;
+		(Stopped, Playing, Paused) + Eject => Empty;
+	}
+}
+
+#[tokio::test]
+async fn test_fsm_macro() {
+	let player = Player::new(&Default::default());
+	player.start().await;
+	assert_matches!(player.get_current_state().await, PlayerStates::Empty(_));
+
+	player.process_event(PlayerEvents::OpenClose(OpenClose)).await.unwrap();
+	player.process_event(PlayerEvents::OpenClose(OpenClose)).await.unwrap();
+	assert_eq!(player.get_context().read().await.drawer_opened, 1);
+
+	assert_matches!(player.process_event(PlayerEvents::CdDetected(CdDetected { tracks: 0 })).await, Err(FsmError::NoTransition));
+	player.process_event(PlayerEvents::CdDetected(CdDetected { tracks: 12 })).await.unwrap();
+	assert_matches!(player.get_current_state().await, PlayerStates::Stopped(_));
+
+	player.process_event(PlayerEvents::Stop(Stop)).await.unwrap();
+	assert_eq!(player.get_context().read().await.stops, 1);
+
+	player.process_event(PlayerEvents::Play(Play)).await.unwrap();
+	player.process_event(PlayerEvents::Pause(Pause)).await.unwrap();
+	player.process_event(PlayerEvents::Play(Play)).await.unwrap();
+	assert_matches!(player.get_current_state().await, PlayerStates::Paused(_));
+
+	player.process_event(PlayerEvents::Stop(Stop)).await.unwrap();
+	assert_matches!(player.get_current_state().await, PlayerStates::Stopped(_));
+
+	player.process_event(PlayerEvents::Eject(Eject)).await.unwrap();
+	assert_matches!(player.get_current_state().await, PlayerStates::Empty(_));
+}
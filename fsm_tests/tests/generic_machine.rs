@@ -0,0 +1,120 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+use std::fmt::Debug;
+
+use fsm::*;
+
+// drivers
+
+pub trait Driver: Debug + Default + Send + Sync + 'static {
+	fn name(&self) -> &'static str;
+	fn move_to(&mut self, position: i32);
+}
+
+#[derive(Debug, Default)]
+pub struct Stepper {
+	steps: Vec<i32>
+}
+
+impl Driver for Stepper {
+	fn name(&self) -> &'static str { "stepper" }
+	fn move_to(&mut self, position: i32) { self.steps.push(position); }
+}
+
+#[derive(Debug, Default)]
+pub struct Servo {
+	position: i32
+}
+
+impl Driver for Servo {
+	fn name(&self) -> &'static str { "servo" }
+	fn move_to(&mut self, position: i32) { self.position = position; }
+}
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct MoveTo(i32);
+impl FsmEvent for MoveTo {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Arrived;
+impl FsmEvent for Arrived {}
+
+// states
+
+#[derive(Debug, Default)]
+pub struct AxisCtx<D: Driver> {
+	driver: D,
+	moves: usize
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Idle;
+#[async_trait]
+impl<D: Driver> FsmState<Axis<D>> for Idle { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Moving;
+#[async_trait]
+impl<D: Driver> FsmState<Axis<D>> for Moving { }
+
+pub struct StartMove;
+#[async_trait]
+impl<D: Driver> FsmAction<Axis<D>, Idle, Moving> for StartMove {
+	async fn action(event_context: &EventContext<'_, Axis<D>>, _: &Idle, _: &Moving) -> FsmTransitionResult<()> {
+		if let AxisEvents::MoveTo(MoveTo(position)) = event_context.event {
+			let mut context = event_context.context.write().await;
+			context.driver.move_to(*position);
+			context.moves += 1;
+		}
+		Ok(())
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct AxisDefinition<D: Driver>(
+	InitialState<Axis<D>, Idle>,
+	ContextType<AxisCtx<D>>,
+
+	Transition<Axis<D>, Idle,   MoveTo,  Moving, StartMove>,
+	Transition<Axis<D>, Moving, Arrived, Idle,   NoAction>
+);
+
+async fn run_axis<D: Driver>() -> Axis<D> {
+	let axis: Axis<D> = Axis::new(&Default::default());
+	axis.start().await;
+	assert_matches!(axis.get_current_state().await, AxisStates::Idle(_));
+
+	axis.process_event(MoveTo(10).into()).await.unwrap();
+	assert_matches!(axis.get_current_state().await, AxisStates::Moving(_));
+	assert_eq!(axis.get_current_state().await.to_string(), "Moving");
+
+	assert_matches!(axis.process_event(MoveTo(20).into()).await, Err(FsmError::NoTransition));
+
+	axis.process_event(AxisEvents::Arrived(Arrived)).await.unwrap();
+	axis.process_event(MoveTo(-5).into()).await.unwrap();
+	assert_eq!(axis.get_context().read().await.moves, 2);
+
+	axis
+}
+
+#[tokio::test]
+async fn test_generic_machine() {
+	let stepper = run_axis::<Stepper>().await;
+	let context = stepper.get_context().read().await;
+	assert_eq!(context.driver.name(), "stepper");
+	assert_eq!(context.driver.steps, vec![10, -5]);
+
+	let servo = run_axis::<Servo>().await;
+	let context = servo.get_context().read().await;
+	assert_eq!(context.driver.name(), "servo");
+	assert_eq!(context.driver.position, -5);
+
+	assert_eq!(<AxisEvents<Servo> as FsmEvents<Axis<Servo>>>::variants(), &["MoveTo", "Arrived"]);
+}
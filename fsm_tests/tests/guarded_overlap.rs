@@ -0,0 +1,66 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Reading(u32);
+impl FsmEvent for Reading {}
+
+// guards
+
+pub struct Overheated;
+impl FsmGuard<Thermostat> for Overheated {
+	fn guard(event_context: &EventContext<Thermostat>, _: &ThermostatStatesStore) -> bool {
+		match event_context.event {
+			ThermostatEvents::Reading(Reading(t)) => *t > 90,
+			_ => false
+		}
+	}
+}
+
+// states
+
+#[derive(Debug, Clone, Default)]
+pub struct Idle;
+#[async_trait]
+impl FsmState<Thermostat> for Idle {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Heating;
+#[async_trait]
+impl FsmState<Thermostat> for Heating {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Alarm;
+#[async_trait]
+impl FsmState<Thermostat> for Alarm {}
+
+// the same source state and event is allowed when the first transition is guarded
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct ThermostatDefinition(
+	InitialState<Thermostat, Idle>,
+
+	TransitionGuard<Thermostat, (Idle, Heating), Reading, Alarm,   NoAction, Overheated>,
+	Transition     <Thermostat, Idle,            Reading, Heating, NoAction>,
+);
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_guarded_overlap() {
+	let thermostat = Thermostat::new(&Default::default());
+	thermostat.start().await;
+
+	thermostat.process_event(ThermostatEvents::Reading(Reading(20))).await.unwrap();
+	assert_matches!(thermostat.get_current_state().await, ThermostatStates::Heating(_));
+
+	thermostat.process_event(ThermostatEvents::Reading(Reading(95))).await.unwrap();
+	assert_matches!(thermostat.get_current_state().await, ThermostatStates::Alarm(_));
+}
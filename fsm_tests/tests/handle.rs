@@ -0,0 +1,125 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+use std::time::Duration;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Coin;
+impl FsmEvent for Coin {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Push;
+impl FsmEvent for Push {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Relock;
+impl FsmEvent for Relock {}
+
+// durations
+
+fsm_duration!(RelockDuration, Duration::from_secs(5));
+
+// states
+
+#[derive(Debug, Clone, Default)]
+pub struct Locked;
+#[async_trait]
+impl FsmState<Turnstile> for Locked {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Unlocked;
+#[async_trait]
+impl FsmState<Turnstile> for Unlocked {
+	async fn on_exit(&self, event_context: &EventContext<'_, Turnstile>) -> FsmTransitionResult<()> {
+		event_context.context.write().await.passed += 1;
+
+		Ok(())
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct TurnstileContext {
+	passed: u32
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct TurnstileDefinition(
+	InitialState<Turnstile, Locked>,
+	ContextType<TurnstileContext>,
+
+	Transition<Turnstile, Locked,   Coin, Unlocked, NoAction>,
+	Transition<Turnstile, Unlocked, Push, Locked,   NoAction>,
+	Transition<Turnstile, Unlocked, Relock, Locked, NoAction>,
+
+	StateTimeout<Turnstile, Unlocked, RelockDuration, Relock>
+);
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_handle_events() {
+	let fsm = Turnstile::new(&Default::default());
+	fsm.start().await;
+
+	let handle = fsm.spawn();
+
+	// the events are sent in order, before their results are awaited
+	let coin = handle.process_event(TurnstileEvents::Coin(Coin));
+	let second_coin = handle.process_event(TurnstileEvents::Coin(Coin));
+	assert_eq!(second_coin.await, Err(FsmError::NoTransition));
+	assert_eq!(coin.await, Ok(()));
+
+	let other = handle.clone();
+	tokio::spawn(async move {
+		other.process_event(TurnstileEvents::Push(Push)).await
+	}).await.unwrap().unwrap();
+
+	handle.stop();
+	assert_eq!(handle.process_event(TurnstileEvents::Coin(Coin)).await, Err(FsmError::Stopped));
+
+	let fsm = handle.join().await.unwrap();
+	assert_matches!(fsm.get_current_state().await, TurnstileStates::Locked(_));
+	assert_eq!(fsm.get_context().read().await.passed, 1);
+
+	assert!(handle.is_stopped());
+	assert!(handle.join().await.is_none());
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_handle_stop_exits_states() {
+	let fsm = Turnstile::new(&Default::default());
+	fsm.start().await;
+
+	let handle = fsm.spawn();
+	handle.process_event(TurnstileEvents::Coin(Coin)).await.unwrap();
+	handle.stop();
+
+	let fsm = handle.join().await.unwrap();
+	assert_matches!(fsm.get_current_state().await, TurnstileStates::Unlocked(_));
+	assert_eq!(fsm.get_context().read().await.passed, 1);
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_handle_executes_state_timeouts() {
+	let fsm = Turnstile::new(&Default::default());
+	fsm.start().await;
+	let mut transitions = fsm.subscribe();
+
+	let handle = fsm.spawn();
+	handle.process_event(TurnstileEvents::Coin(Coin)).await.unwrap();
+	assert_eq!(transitions.recv().await.unwrap().target_state, "Unlocked");
+
+	// the timeout is executed without another event sent to the machine
+	let relocked = tokio::time::timeout(Duration::from_secs(10), transitions.recv()).await.unwrap().unwrap();
+	assert_eq!(relocked.event, "Relock");
+	assert_eq!(relocked.target_state, "Locked");
+}
@@ -0,0 +1,353 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Start;
+impl FsmEvent for Start {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Picked;
+impl FsmEvent for Picked {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Stop;
+impl FsmEvent for Stop {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Pause;
+impl FsmEvent for Pause {}
+
+// Assembly, handles Pause itself
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Picking;
+#[async_trait]
+impl FsmState<Assembly> for Picking { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Placing;
+#[async_trait]
+impl FsmState<Assembly> for Placing { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Holding;
+#[async_trait]
+impl FsmState<Assembly> for Holding { }
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct AssemblyDefinition(
+	InitialState<Assembly, Picking>,
+
+	Transition<Assembly, Picking, Picked, Placing, NoAction>,
+	Transition<Assembly, Placing, Pause,  Holding, NoAction>,
+	Transition<Assembly, Placing, Stop,   Holding, NoAction>
+);
+
+async fn assembly_state<F: FsmRetrieveState<Assembly>>(machine: &F) -> AssemblyStates {
+	let assembly = machine.get_state();
+	let assembly = assembly.read().await;
+	assembly.get_current_state().await
+}
+
+// Cell, the submachine gets the events first
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Idle;
+#[async_trait]
+impl FsmState<Cell> for Idle { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Paused;
+#[async_trait]
+impl FsmState<Cell> for Paused { }
+
+#[async_trait]
+impl FsmState<Cell> for Assembly { }
+
+impl FsmStateFactory<()> for Assembly {
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		Assembly::new(parent_context)
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct CellDefinition(
+	InitialState<Cell, Idle>,
+	SubMachine<Assembly>,
+	HierarchicalDispatch<Cell, InnerFirst>,
+
+	Transition<Cell, Idle,     Start, Assembly, NoAction>,
+	Transition<Cell, Assembly, Pause, Paused,   NoAction>,
+	Transition<Cell, Assembly, Stop,  Idle,     NoAction>
+);
+
+#[tokio::test]
+async fn test_inner_first() {
+	let cell = Cell::new(&Default::default());
+	cell.start().await;
+	cell.process_event(CellEvents::Start(Start)).await.unwrap();
+	assert_matches!(assembly_state(&cell).await, AssemblyStates::Picking(_));
+
+	// only the submachine accepts Picked
+	cell.process_event(AssemblyEvents::Picked(Picked).into()).await.unwrap();
+	assert_matches!(assembly_state(&cell).await, AssemblyStates::Placing(_));
+
+	// both accept Pause, the submachine wins and the machine handles what it doesn't
+	cell.process_event(CellEvents::Pause(Pause)).await.unwrap();
+	assert_matches!(cell.get_current_state().await, CellStates::Assembly(_));
+	assert_matches!(assembly_state(&cell).await, AssemblyStates::Holding(_));
+
+	cell.process_event(CellEvents::Pause(Pause)).await.unwrap();
+	assert_matches!(cell.get_current_state().await, CellStates::Paused(_));
+
+	// the submachine isn't active anymore
+	assert_matches!(cell.process_event(AssemblyEvents::Picked(Picked).into()).await, Err(FsmError::NoTransition));
+}
+
+#[tokio::test]
+async fn test_submachine_events_bubble_up() {
+	let cell = Cell::new(&Default::default());
+	cell.start().await;
+	cell.process_event(CellEvents::Start(Start)).await.unwrap();
+
+	// the submachine's Stop is lifted into the machine's own event
+	cell.process_event(AssemblyEvents::Stop(Stop).into()).await.unwrap();
+	assert_matches!(cell.get_current_state().await, CellStates::Idle(_));
+}
+
+// Line, the machine gets the events first
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Stopped;
+#[async_trait]
+impl FsmState<Line> for Stopped { }
+
+#[async_trait]
+impl FsmState<Line> for Assembly { }
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct LineDefinition(
+	InitialState<Line, Stopped>,
+	SubMachine<Assembly>,
+	HierarchicalDispatch<Line, OuterFirst>,
+
+	Transition<Line, Stopped,  Start, Assembly, NoAction>,
+	Transition<Line, Assembly, Stop,  Stopped,  NoAction>
+);
+
+#[tokio::test]
+async fn test_outer_first() {
+	let line = Line::new(&Default::default());
+	line.start().await;
+	line.process_event(LineEvents::Start(Start)).await.unwrap();
+
+	// the machine doesn't accept Picked, it goes to the submachine
+	line.process_event(AssemblyEvents::Picked(Picked).into()).await.unwrap();
+	assert_matches!(assembly_state(&line).await, AssemblyStates::Placing(_));
+
+	// both accept Stop, the machine wins
+	line.process_event(LineEvents::Stop(Stop)).await.unwrap();
+	assert_matches!(line.get_current_state().await, LineStates::Stopped(_));
+	assert_matches!(assembly_state(&line).await, AssemblyStates::Placing(_));
+}
+
+// Feeder, a second submachine
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Feeding;
+#[async_trait]
+impl FsmState<Feeder> for Feeding { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Halted;
+#[async_trait]
+impl FsmState<Feeder> for Halted { }
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct FeederDefinition(
+	InitialState<Feeder, Feeding>,
+
+	Transition<Feeder, Feeding, Stop, Halted, NoAction>
+);
+
+impl FsmStateFactory<()> for Feeder {
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		Feeder::new(parent_context)
+	}
+}
+
+// Workcell, a submachine in each region
+
+/// The events processed by a machine with their results.
+#[derive(Debug, Default)]
+pub struct ProcessedEvents(std::sync::Mutex<Vec<(&'static str, Result<(), FsmError>)>>);
+
+#[async_trait]
+impl FsmInspect<Workcell> for ProcessedEvents {
+	fn new_from_context(_context: &FsmArc<()>) -> Self {
+		Default::default()
+	}
+
+	async fn on_event_processed(&self, event_context: &EventContext<'_, Workcell>, result: &Result<(), FsmError>) {
+		self.0.lock().unwrap().push((event_context.event.name(), *result));
+	}
+}
+
+#[async_trait]
+impl FsmState<Workcell> for Idle { }
+
+#[async_trait]
+impl FsmState<Workcell> for Assembly { }
+
+#[async_trait]
+impl FsmState<Workcell> for Feeder { }
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct WorkcellDefinition(
+	InitialState<Workcell, (Assembly, Feeder)>,
+	SubMachine<Assembly>,
+	SubMachine<Feeder>,
+	HierarchicalDispatch<Workcell, InnerFirst>,
+	InspectionType<Workcell, ProcessedEvents>,
+
+	Transition<Workcell, Assembly, Stop, Idle, NoAction>,
+
+	DeferredEvent<Workcell, Feeder, Pause>
+);
+
+async fn feeder_state(workcell: &Workcell) -> FeederStates {
+	let feeder: FsmArc<Feeder> = workcell.get_state();
+	let feeder = feeder.read().await;
+	feeder.get_current_state().await
+}
+
+#[tokio::test]
+async fn test_dispatch_to_every_region() {
+	let workcell = Workcell::new(&Default::default());
+	workcell.start().await;
+	workcell.process_event(AssemblyEvents::Picked(Picked).into()).await.unwrap();
+	assert_matches!(assembly_state(&workcell).await, AssemblyStates::Placing(_));
+
+	// both submachines accept Stop, each of them handles it
+	workcell.process_event(WorkcellEvents::Stop(Stop)).await.unwrap();
+	assert_matches!(assembly_state(&workcell).await, AssemblyStates::Holding(_));
+	assert_matches!(feeder_state(&workcell).await, FeederStates::Halted(_));
+	assert_matches!(workcell.get_current_state().await, (WorkcellStates::Assembly(_), WorkcellStates::Feeder(_)));
+}
+
+#[tokio::test]
+async fn test_submachine_events_are_inspected() {
+	let workcell = Workcell::new(&Default::default());
+	workcell.start().await;
+	workcell.get_inspection().0.lock().unwrap().clear();
+
+	workcell.process_event(AssemblyEvents::Picked(Picked).into()).await.unwrap();
+	assert_matches!(workcell.process_event(AssemblyEvents::Picked(Picked).into()).await, Err(FsmError::NoTransition));
+
+	let processed = workcell.get_inspection().0.lock().unwrap().clone();
+	assert_eq!(processed, vec![("Picked", Ok(())), ("Picked", Err(FsmError::NoTransition))]);
+}
+
+#[tokio::test]
+async fn test_inner_first_defers_before_the_submachines() {
+	let workcell = Workcell::new(&Default::default());
+	workcell.start().await;
+	workcell.process_event(AssemblyEvents::Picked(Picked).into()).await.unwrap();
+
+	// the Feeder defers Pause, the Assembly doesn't get it either
+	workcell.process_event(WorkcellEvents::Pause(Pause)).await.unwrap();
+	assert_matches!(assembly_state(&workcell).await, AssemblyStates::Placing(_));
+	assert_eq!(workcell.get_deferred_queue().read().await.len(), 1);
+
+	// a wrapped event of the submachine is deferred the same way
+	workcell.process_event(AssemblyEvents::Pause(Pause).into()).await.unwrap();
+	assert_matches!(assembly_state(&workcell).await, AssemblyStates::Placing(_));
+	assert_eq!(workcell.get_deferred_queue().read().await.len(), 2);
+}
+
+// Gripper, jams on a pick until it is started again
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Released;
+#[async_trait]
+impl FsmState<Gripper> for Released { }
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Jammed;
+#[async_trait]
+impl FsmState<Gripper> for Jammed { }
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct GripperDefinition(
+	InitialState<Gripper, Released>,
+	InterruptState<Gripper, Jammed, Start>,
+
+	Transition<Gripper, Released, Picked, Jammed,   NoAction>,
+	Transition<Gripper, Jammed,   Start,  Released, NoAction>,
+	TransitionInternal<Gripper, Released, Pause, NoAction>
+);
+
+impl FsmStateFactory<()> for Gripper {
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		Gripper::new(parent_context)
+	}
+}
+
+// Packer, the submachine of its second region fails
+
+#[async_trait]
+impl FsmState<Packer> for Assembly { }
+
+#[async_trait]
+impl FsmState<Packer> for Gripper { }
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct PackerDefinition(
+	InitialState<Packer, (Assembly, Gripper)>,
+	SubMachine<Assembly>,
+	SubMachine<Gripper>,
+	HierarchicalDispatch<Packer, InnerFirst>,
+
+	// its own events go to the submachines of both regions
+	TransitionInternal<Packer, Assembly, Picked, NoAction>,
+	TransitionInternal<Packer, Assembly, Pause,  NoAction>,
+	TransitionInternal<Packer, Assembly, Start,  NoAction>
+);
+
+async fn gripper_state(packer: &Packer) -> GripperStates {
+	let gripper: FsmArc<Gripper> = packer.get_state();
+	let gripper = gripper.read().await;
+	gripper.get_current_state().await
+}
+
+#[tokio::test]
+async fn test_dispatch_returns_the_first_error() {
+	let packer = Packer::new(&Default::default());
+	packer.start().await;
+	packer.process_event(PackerEvents::Picked(Picked)).await.unwrap();
+	assert_matches!(gripper_state(&packer).await, GripperStates::Jammed(_));
+
+	// the Assembly handles Pause, the error of the interrupted Gripper is returned
+	assert_matches!(packer.process_event(PackerEvents::Pause(Pause)).await, Err(FsmError::Interrupted));
+	assert_matches!(assembly_state(&packer).await, AssemblyStates::Holding(_));
+	assert_matches!(gripper_state(&packer).await, GripperStates::Jammed(_));
+
+	// the Assembly has no transition for Start, the Gripper's result is returned
+	packer.process_event(PackerEvents::Start(Start)).await.unwrap();
+	assert_matches!(gripper_state(&packer).await, GripperStates::Released(_));
+}
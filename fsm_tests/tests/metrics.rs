@@ -0,0 +1,167 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Start;
+impl FsmEvent for Start {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Stop;
+impl FsmEvent for Stop {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Jam;
+impl FsmEvent for Jam {}
+
+// states
+
+#[derive(Debug, Clone, Default)]
+pub struct Idle;
+#[async_trait]
+impl FsmState<Press> for Idle {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Running;
+#[async_trait]
+impl FsmState<Press> for Running {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Fault;
+#[async_trait]
+impl FsmState<Press> for Fault {}
+
+pub struct JamAction;
+#[async_trait]
+impl FsmAction<Press, Idle, Running> for JamAction {
+	async fn action(_: &EventContext<'_, Press>, _: &Idle, _: &Running) -> FsmTransitionResult<()> {
+		Err(FsmTransitionError(Arc::new(std::io::Error::other("jammed").into())))
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct PressDefinition(
+	InitialState<Press, Idle>,
+	ErrorState<Press, Fault>,
+	InspectionType<Press, FsmInspectMetrics<Press>>,
+
+	Transition<Press, Idle,    Start, Running, NoAction>,
+	Transition<Press, Running, Stop,  Idle,    NoAction>,
+	Transition<Press, Idle,    Jam,   Running, JamAction>,
+
+	InterruptState<Press, Running, Stop>,
+);
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_metrics() {
+	let press = Press::new(&Default::default());
+	press.start().await;
+
+	press.process_event(PressEvents::Start(Start)).await.unwrap();
+	tokio::time::advance(Duration::from_millis(300)).await;
+	assert_eq!(press.process_event(PressEvents::Start(Start)).await, Err(FsmError::Interrupted));
+	press.process_event(PressEvents::Stop(Stop)).await.unwrap();
+	assert_eq!(press.process_event(PressEvents::Stop(Stop)).await, Err(FsmError::NoTransition));
+
+	press.process_event(PressEvents::Jam(Jam)).await.unwrap();
+	assert_matches!(press.get_current_state().await, PressStates::Fault(_));
+
+	let metrics = press.get_inspection();
+	assert_eq!(metrics.transitions("Idle", "Start", "Running"), 1);
+	assert_eq!(metrics.transitions("Running", "Stop", "Idle"), 1);
+	assert_eq!(metrics.rejected_events("Start", &FsmError::Interrupted), 1);
+	assert_eq!(metrics.rejected_events("Stop", &FsmError::NoTransition), 1);
+	assert_eq!(metrics.error_state_entries("Fault"), 1);
+	assert_eq!(metrics.dwell_time("Running"), (1, Duration::from_millis(300)));
+
+	let text = metrics.to_prometheus();
+	assert!(text.contains("# TYPE fsm_transitions_total counter\n"));
+	assert!(text.contains("fsm_transitions_total{machine=\"Press\",source=\"Idle\",event=\"Start\",target=\"Running\"} 1\n"));
+	assert!(text.contains("fsm_rejected_events_total{machine=\"Press\",event=\"Start\",reason=\"interrupted\"} 1\n"));
+	assert!(text.contains("fsm_error_state_entries_total{machine=\"Press\",state=\"Fault\"} 1\n"));
+	assert!(text.contains("# TYPE fsm_state_dwell_seconds histogram\n"));
+	assert!(text.contains("fsm_state_dwell_seconds_bucket{machine=\"Press\",state=\"Running\",le=\"0.25\"} 0\n"));
+	assert!(text.contains("fsm_state_dwell_seconds_bucket{machine=\"Press\",state=\"Running\",le=\"0.5\"} 1\n"));
+	assert!(text.contains("fsm_state_dwell_seconds_sum{machine=\"Press\",state=\"Running\"} 0.3\n"));
+	assert!(text.contains("fsm_state_dwell_seconds_count{machine=\"Press\",state=\"Running\"} 1\n"));
+}
+
+// a submachine state, its name changes with the active state of the submachine
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Load;
+impl FsmEvent for Load {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Loading;
+#[async_trait]
+impl FsmState<Feeder> for Loading {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Loaded;
+#[async_trait]
+impl FsmState<Feeder> for Loaded {}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct FeederDefinition(
+	InitialState<Feeder, Loading>,
+
+	Transition<Feeder, Loading, Load, Loaded, NoAction>
+);
+
+impl FsmStateFactory<()> for Feeder {
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		Feeder::new(parent_context)
+	}
+}
+
+#[async_trait]
+impl FsmState<Line> for Feeder {}
+
+#[async_trait]
+impl FsmState<Line> for Idle {}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct LineDefinition(
+	InitialState<Line, Idle>,
+	SubMachine<Feeder>,
+	InspectionType<Line, FsmInspectMetrics<Line>>,
+
+	Transition<Line, Idle,   Start, Feeder, NoAction>,
+	Transition<Line, Feeder, Stop,  Idle,   NoAction>
+);
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_metrics_submachine_dwell_time() {
+	let line = Line::new(&Default::default());
+	line.start().await;
+
+	line.process_event(LineEvents::Start(Start)).await.unwrap();
+	tokio::time::advance(Duration::from_millis(200)).await;
+
+	// the submachine changes its state while it is active
+	let feeder: FsmArc<Feeder> = line.get_state();
+	feeder.read().await.process_event(FeederEvents::Load(Load)).await.unwrap();
+	tokio::time::advance(Duration::from_millis(300)).await;
+
+	line.process_event(LineEvents::Stop(Stop)).await.unwrap();
+	assert_matches!(line.get_current_state().await, LineStates::Idle(_));
+
+	let metrics = line.get_inspection();
+	assert_eq!(metrics.dwell_time("Feeder"), (1, Duration::from_millis(500)));
+	assert!(metrics.to_prometheus().contains("fsm_state_dwell_seconds_count{machine=\"Line\",state=\"Feeder\"} 1\n"));
+}
@@ -0,0 +1,85 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+pub mod events {
+	use fsm::*;
+
+	#[derive(Clone, PartialEq, Default, Debug)]
+	pub struct Go;
+	impl FsmEvent for Go {}
+
+	#[derive(Clone, PartialEq, Default, Debug)]
+	pub struct Halt;
+	impl FsmEvent for Halt {}
+
+	#[derive(Clone, PartialEq, Default, Debug)]
+	pub struct Load;
+	impl FsmEvent for Load {}
+}
+
+pub mod states {
+	use async_trait::async_trait;
+	use fsm::*;
+
+	use crate::Conveyor;
+
+	#[derive(Debug, Clone, PartialEq, Default)]
+	pub struct Idle;
+	#[async_trait]
+	impl FsmState<Conveyor> for Idle { }
+
+	#[derive(Debug, Clone, PartialEq, Default)]
+	pub struct Moving;
+	#[async_trait]
+	impl FsmState<Conveyor> for Moving { }
+
+	#[derive(Debug, Clone, PartialEq, Default)]
+	pub struct Loaded;
+	#[async_trait]
+	impl FsmState<Conveyor> for Loaded { }
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct ConveyorDefinition(
+	InitialState<Conveyor, states::Idle>,
+
+	Transition         < Conveyor, states::Idle,   crate::events::Go,   states::Moving, NoAction >,
+	Transition         < Conveyor, states::Moving, events::Halt,        states::Idle,   NoAction >,
+	Transition         < Conveyor, states::Idle,   events::Load,        states::Loaded, NoAction >,
+	TransitionSelf     < Conveyor, states::Loaded, events::Load,                        NoAction >,
+
+	DeferredEvent      < Conveyor, states::Moving, events::Load >,
+	InterruptState     < Conveyor, states::Loaded, events::Halt >
+);
+
+#[tokio::test]
+async fn test_module_paths() {
+	let fsm = Conveyor::new(&Default::default());
+	fsm.start().await;
+	assert_matches!(fsm.get_current_state().await, ConveyorStates::Idle(_));
+	assert_eq!(fsm.get_current_state().await.to_string(), "Idle");
+
+	fsm.process_event(events::Go.into()).await.unwrap();
+	assert_matches!(fsm.get_current_state().await, ConveyorStates::Moving(_));
+
+	// deferred until the conveyor halts
+	fsm.process_event(ConveyorEvents::Load(events::Load)).await.unwrap();
+	fsm.process_event(ConveyorEvents::Halt(events::Halt)).await.unwrap();
+	assert_matches!(fsm.get_current_state().await, ConveyorStates::Loaded(_));
+	assert_eq!(fsm.get_current_state().await.name().await, "Loaded");
+
+	assert_matches!(fsm.process_event(ConveyorEvents::Go(events::Go)).await, Err(FsmError::Interrupted));
+
+	let state: FsmArc<states::Loaded> = fsm.get_state();
+	assert_eq!(*state.read().await, states::Loaded);
+
+	assert_eq!(ConveyorEvents::Go(events::Go).name(), "Go");
+	assert_eq!(<ConveyorEvents as FsmEvents<Conveyor>>::variants(), &["Go", "Halt", "Load"]);
+}
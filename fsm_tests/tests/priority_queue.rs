@@ -0,0 +1,122 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Telemetry(u32);
+impl FsmEvent for Telemetry {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Overheated;
+impl FsmEvent for Overheated {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct EStop;
+impl FsmEvent for EStop {}
+
+// states
+
+#[derive(Debug, Clone, Default)]
+pub struct Running;
+#[async_trait]
+impl FsmState<Pump> for Running {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Stopped;
+#[async_trait]
+impl FsmState<Pump> for Stopped {}
+
+// actions
+
+pub struct RecordTelemetry;
+#[async_trait]
+impl FsmActionSelf<Pump, Running> for RecordTelemetry {
+	async fn action(event_context: &EventContext<'_, Pump>, _state: &Running) -> FsmTransitionResult<()> {
+		if let PumpEvents::Telemetry(Telemetry(n)) = event_context.event {
+			event_context.context.write().await.received.push(*n);
+		}
+
+		Ok(())
+	}
+}
+
+pub struct RequestEStop;
+#[async_trait]
+impl FsmActionSelf<Pump, Running> for RequestEStop {
+	async fn action(event_context: &EventContext<'_, Pump>, _state: &Running) -> FsmTransitionResult<()> {
+		event_context.enqueue_event_with_priority(PumpEvents::EStop(EStop), 10).await?;
+
+		Ok(())
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct PumpContext {
+	received: Vec<u32>
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct PumpDefinition(
+	InitialState<Pump, Running>,
+	ContextType<PumpContext>,
+	QueueType<Pump, FsmEventQueuePriority<Pump>>,
+
+	TransitionInternal< Pump, Running, Telemetry,           RecordTelemetry >,
+	TransitionInternal< Pump, Running, Overheated,          RequestEStop >,
+	Transition        < Pump, Running, EStop,     Stopped,  NoAction >,
+);
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_priority_order() {
+	let mut fsm = Pump::new(&Default::default());
+	fsm.execute_queue_pre = false;
+	fsm.start().await;
+
+	{
+		let mut queue = fsm.get_queue().write().await;
+		queue.enqueue_event(PumpEvents::Telemetry(Telemetry(1))).unwrap();
+		queue.enqueue_event(PumpEvents::Telemetry(Telemetry(2))).unwrap();
+		queue.enqueue_event_with_priority(PumpEvents::Telemetry(Telemetry(3)), 5).unwrap();
+		queue.enqueue_event_with_priority(PumpEvents::Telemetry(Telemetry(4)), -1).unwrap();
+		queue.enqueue_event_with_priority(PumpEvents::Telemetry(Telemetry(5)), 5).unwrap();
+	}
+	assert_eq!(fsm.get_message_queue_size().await, 5);
+
+	fsm.execute_queued_events().await;
+	assert_eq!(fsm.get_message_queue_size().await, 0);
+
+	let context = fsm.get_context();
+	assert_eq!(context.read().await.received, vec![3, 5, 1, 2, 4]);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_estop_skips_backlog() {
+	let mut fsm = Pump::new(&Default::default());
+	fsm.execute_queue_pre = false;
+	fsm.execute_queue_post = false;
+	fsm.start().await;
+
+	{
+		let mut queue = fsm.get_queue().write().await;
+		for n in 0..10 {
+			queue.enqueue_event(PumpEvents::Telemetry(Telemetry(n))).unwrap();
+		}
+	}
+
+	fsm.process_event(PumpEvents::Overheated(Overheated)).await.unwrap();
+	assert_eq!(fsm.get_message_queue_size().await, 11);
+
+	fsm.execute_single_queued_event().await;
+	assert_matches!(fsm.get_current_state().await, PumpStates::Stopped(_));
+	assert_eq!(fsm.get_message_queue_size().await, 10);
+}
@@ -0,0 +1,126 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+use serde::{Serialize, Deserialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug, Serialize, Deserialize)]
+pub struct Grip(u32);
+impl FsmEvent for Grip {}
+
+#[derive(Clone, PartialEq, Default, Debug, Serialize, Deserialize)]
+pub struct Gripped;
+impl FsmEvent for Gripped {}
+
+#[derive(Clone, PartialEq, Default, Debug, Serialize, Deserialize)]
+pub struct Release;
+impl FsmEvent for Release {}
+
+// states
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Opened;
+#[async_trait]
+impl FsmState<Gripper> for Opened {}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Closing;
+#[async_trait]
+impl FsmState<Gripper> for Closing {}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Holding;
+#[async_trait]
+impl FsmState<Gripper> for Holding {}
+
+// actions and guards
+
+pub struct CloseJaws;
+#[async_trait]
+impl FsmAction<Gripper, Opened, Closing> for CloseJaws {
+	async fn action(event_context: &EventContext<'_, Gripper>, _: &Opened, _: &Closing) -> FsmTransitionResult<()> {
+		event_context.enqueue_event(GripperEvents::Gripped(Gripped)).await.unwrap();
+
+		Ok(())
+	}
+}
+
+pub struct JawsFree;
+#[async_trait]
+impl FsmGuardAsync<Gripper, Holding> for JawsFree {
+	async fn guard(event_context: &EventContext<'_, Gripper>, _: &Holding, _: &GripperStatesStore) -> bool {
+		!event_context.context.read().await.stuck_jaws
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct GripperContext {
+	stuck_jaws: bool
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct GripperDefinition(
+	InitialState<Gripper, Opened>,
+	ContextType<GripperContext>,
+	InspectionType<Gripper, FsmInspectRecorder<Gripper>>,
+	Snapshot,
+
+	Transition     <Gripper, Opened,  Grip,    Closing, CloseJaws>,
+	Transition     <Gripper, Closing, Gripped, Holding, NoAction>,
+	TransitionGuard<Gripper, Holding, Release, Opened,  NoAction, JawsFree>,
+);
+
+async fn record() -> String {
+	let gripper = Gripper::new(&Default::default());
+	gripper.start().await;
+
+	gripper.process_event(GripperEvents::Grip(Grip(40))).await.unwrap();
+	assert_eq!(gripper.process_event(GripperEvents::Grip(Grip(50))).await, Err(FsmError::NoTransition));
+	gripper.process_event(GripperEvents::Release(Release)).await.unwrap();
+
+	let mut lines = Vec::new();
+	gripper.get_inspection().recording().write_json_lines(&mut lines).unwrap();
+
+	String::from_utf8(lines).unwrap()
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_record_replay() {
+	let lines = record().await;
+	assert!(lines.lines().any(|l| l == r#"{"depth":0,"event":{"Grip":40},"transitions":[{"region":0,"source":"Opened","target":"Closing"}],"error":null,"state":"Closing"}"#));
+
+	let recording = FsmRecording::<GripperEvents>::read_json_lines(lines.as_bytes()).unwrap();
+	assert!(recording.steps[0].event.is_none());
+	assert!(recording.steps.iter().any(|s| s.error == Some("NoTransition".to_string())));
+
+	let gripper = Gripper::new(&Default::default());
+	recording.replay(&gripper, gripper.get_inspection()).await.unwrap();
+	assert_eq!(gripper.get_inspection().recording().to_json_lines().unwrap(), lines);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_replay_divergence() {
+	let recording = FsmRecording::<GripperEvents>::from_json_lines(&record().await).unwrap();
+
+	let context = Arc::new(RwLock::new(GripperContext { stuck_jaws: true }));
+	let gripper = Gripper::new(&context);
+	let divergence = recording.replay(&gripper, gripper.get_inspection()).await.unwrap_err();
+
+	let expected = divergence.expected.unwrap();
+	let observed = divergence.observed.unwrap();
+	assert_matches!(expected.event, Some(GripperEvents::Release(_)));
+	assert_eq!(expected.state, "Opened");
+	assert_eq!(observed.error, Some("NoTransition".to_string()));
+	assert_eq!(observed.state, "Holding");
+}
@@ -0,0 +1,109 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+
+use fsm::*;
+use fsm::testing::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Open;
+impl FsmEvent for Open {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Close;
+impl FsmEvent for Close {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Lock;
+impl FsmEvent for Lock {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Unlock;
+impl FsmEvent for Unlock {}
+
+// states
+
+#[derive(Debug, Clone, Default)]
+pub struct Closed;
+#[async_trait]
+impl FsmState<Door> for Closed {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Opened;
+#[async_trait]
+impl FsmState<Door> for Opened {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Locked;
+#[async_trait]
+impl FsmState<Door> for Locked {}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct DoorDefinition(
+	InitialState<Door, Closed>,
+	InspectionType<Door, FsmInspectHooks<Door>>,
+
+	Transition<Door, Closed, Open,   Opened, NoAction>,
+	Transition<Door, Opened, Close,  Closed, NoAction>,
+	Transition<Door, Closed, Lock,   Locked, NoAction>,
+	Transition<Door, Locked, Unlock, Closed, NoAction>,
+
+	InterruptState<Door, Locked, Unlock>,
+);
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_scenario() {
+	let door = Door::new(&Default::default());
+
+	FsmScenario::new()
+		.start()
+			.expect_state("Closed")
+			.expect_hooks(&[FsmHook::entry("Closed")])
+		.event(DoorEvents::Open(Open))
+			.expect_state("Opened")
+			.expect_hooks(&[FsmHook::exit("Closed"), FsmHook::action("Closed"), FsmHook::entry("Opened")])
+		.event(DoorEvents::Lock(Lock))
+			.expect_no_transition()
+			.expect_hooks(&[])
+		.event(DoorEvents::Close(Close))
+		.event(DoorEvents::Lock(Lock))
+			.expect_state("Locked")
+		.event(DoorEvents::Open(Open))
+			.expect_interrupted()
+			.expect_state("Locked")
+		.run(&door, door.get_inspection())
+		.await;
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_scenario_failure() {
+	let door = Door::new(&Default::default());
+
+	let failure = FsmScenario::new()
+		.start()
+		.event(DoorEvents::Open(Open))
+			.expect_state("Locked")
+			.expect_hooks(&[FsmHook::exit("Closed"), FsmHook::entry("Locked")])
+		.try_run(&door, door.get_inspection())
+		.await
+		.unwrap_err();
+
+	assert_eq!(failure.to_string(), "\
+Scenario step 1 (event Open) failed
+state:
+  - Locked
+  + Opened
+hooks:
+    exit Closed
+  - entry Locked
+  + action Closed
+  + entry Opened
+");
+}
@@ -0,0 +1,188 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+use serde::{Serialize, Deserialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug, Serialize, Deserialize)]
+pub struct Load;
+impl FsmEvent for Load {}
+
+#[derive(Clone, PartialEq, Default, Debug, Serialize, Deserialize)]
+pub struct Screw(u32);
+impl FsmEvent for Screw {}
+
+#[derive(Clone, PartialEq, Default, Debug, Serialize, Deserialize)]
+pub struct Unload;
+impl FsmEvent for Unload {}
+
+#[derive(Clone, PartialEq, Default, Debug, Serialize, Deserialize)]
+pub struct Inspect;
+impl FsmEvent for Inspect {}
+
+// Cell
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Empty;
+#[async_trait]
+impl FsmState<Cell> for Empty {}
+
+#[async_trait]
+impl FsmState<Cell> for Assembly {}
+
+impl FsmStateFactory<()> for Assembly {
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		Assembly::new(parent_context)
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct CellDefinition(
+	InitialState<Cell, Empty>,
+	Snapshot,
+
+	SubMachine<Assembly>,
+
+	Transition        <Cell, Empty,    Load,    Assembly, NoAction>,
+	Transition        <Cell, Assembly, Unload,  Empty,    NoAction>,
+	TransitionInternal<Cell, Empty,    Inspect,           NoAction>,
+	DeferredEvent     <Cell, Assembly, Inspect>,
+);
+
+// Assembly
+
+static SCREWING_ENTRIES: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Screwing {
+	torques: Vec<u32>
+}
+#[async_trait]
+impl FsmState<Assembly> for Screwing {
+	async fn on_entry(&self, _event_context: &EventContext<'_, Assembly>) -> FsmTransitionResult<()> {
+		SCREWING_ENTRIES.fetch_add(1, Ordering::SeqCst);
+
+		Ok(())
+	}
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Monitoring;
+#[async_trait]
+impl FsmState<Assembly> for Monitoring {}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct AssemblyDefinition(
+	InitialState<Assembly, (Screwing, Monitoring)>,
+	Snapshot,
+
+	TransitionInternal<Assembly, Screwing, Screw, NoAction>,
+);
+
+async fn assembly_state(cell: &Cell) -> ((AssemblyStates, AssemblyStates), Vec<u32>) {
+	let assembly: FsmArc<Assembly> = cell.get_state();
+	let assembly = assembly.read().await;
+
+	let screwing: FsmArc<Screwing> = assembly.get_state();
+	let torques = screwing.read().await.torques.clone();
+
+	(assembly.get_current_state().await, torques)
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_snapshot_restore() {
+	let cell = Cell::new(&Default::default());
+	cell.start().await;
+
+	cell.process_event(CellEvents::Load(Load)).await.unwrap();
+	assert_eq!(SCREWING_ENTRIES.load(Ordering::SeqCst), 1);
+
+	{
+		let assembly: FsmArc<Assembly> = cell.get_state();
+		let assembly = assembly.read().await;
+		let screwing: FsmArc<Screwing> = assembly.get_state();
+		screwing.write().await.torques = vec![12, 14];
+	}
+
+	cell.process_event(CellEvents::Inspect(Inspect)).await.unwrap();
+	cell.get_queue().write().await.enqueue_event(CellEvents::Unload(Unload)).unwrap();
+
+	let json = serde_json::to_string(&cell.snapshot().await).unwrap();
+	let restored = Cell::from_snapshot(&Default::default(), serde_json::from_str(&json).unwrap()).await.unwrap();
+
+	assert_matches!(restored.get_current_state().await, CellStates::Assembly(_));
+	assert_matches!(assembly_state(&restored).await, ((AssemblyStates::Screwing(_), AssemblyStates::Monitoring(_)), ref t) if t == &vec![12, 14]);
+	assert_eq!(restored.get_message_queue_size().await, 1);
+	assert_eq!(restored.get_deferred_queue().read().await.len(), 1);
+	assert_eq!(SCREWING_ENTRIES.load(Ordering::SeqCst), 1);
+
+	// the restored machine carries on with the queued and deferred events
+	restored.execute_queued_events().await;
+	assert_matches!(restored.get_current_state().await, CellStates::Empty(_));
+	assert_eq!(restored.get_deferred_queue().read().await.len(), 0);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_restore_unknown_state() {
+	let cell = Cell::new(&Default::default());
+
+	let mut snapshot = cell.snapshot().await;
+	snapshot.current_state[0] = "Loaded".to_string();
+
+	let restored = Cell::new(&Default::default());
+	assert_eq!(restored.restore(snapshot).await, Err(FsmSnapshotError::UnknownState("Loaded".to_string())));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_snapshot_keeps_priorities() {
+	let queue = std::sync::Arc::new(tokio::sync::RwLock::new(FsmEventQueuePriority::new()));
+	let cell = Cell::new_with_queue(&Default::default(), queue);
+	cell.start().await;
+
+	{
+		let mut queue = cell.get_queue().write().await;
+		queue.enqueue_event(CellEvents::Inspect(Inspect)).unwrap();
+		queue.enqueue_event_with_priority(CellEvents::Load(Load), 5).unwrap();
+	}
+
+	let snapshot = cell.snapshot().await;
+	assert_matches!(snapshot.queue.as_slice(), [(5, CellEvents::Load(_)), (0, CellEvents::Inspect(_))]);
+
+	let queue = std::sync::Arc::new(tokio::sync::RwLock::new(FsmEventQueuePriority::new()));
+	let restored = Cell::new_with_queue(&Default::default(), queue);
+	restored.restore(snapshot).await.unwrap();
+
+	// a later event with a priority in between still goes after the restored one
+	restored.get_queue().write().await.enqueue_event_with_priority(CellEvents::Unload(Unload), 3).unwrap();
+	let events = restored.get_queue().read().await.events_with_priority().into_iter().map(|(priority, _)| priority).collect::<Vec<_>>();
+	assert_eq!(events, vec![5, 3, 0]);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_restore_into_full_queue() {
+	let cell = Cell::new(&Default::default());
+	cell.start().await;
+
+	{
+		let mut queue = cell.get_queue().write().await;
+		queue.enqueue_event(CellEvents::Load(Load)).unwrap();
+		queue.enqueue_event(CellEvents::Inspect(Inspect)).unwrap();
+	}
+
+	let queue = std::sync::Arc::new(tokio::sync::RwLock::new(FsmEventQueueBounded::new(1, FsmQueueOverflow::Reject)));
+	let restored = Cell::new_with_queue(&Default::default(), queue);
+	assert_eq!(restored.restore(cell.snapshot().await).await, Err(FsmSnapshotError::QueueFull));
+}
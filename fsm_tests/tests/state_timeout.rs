@@ -0,0 +1,166 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use assert_matches::assert_matches;
+use std::time::Duration;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Connect;
+impl FsmEvent for Connect {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Acknowledge;
+impl FsmEvent for Acknowledge {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Retry;
+impl FsmEvent for Retry {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct HandshakeTimeout;
+impl FsmEvent for HandshakeTimeout {}
+
+// durations
+
+fsm_duration!(HandshakeDuration, Duration::from_secs(5));
+
+// states
+
+#[derive(Debug, Clone, Default)]
+pub struct Disconnected;
+#[async_trait]
+impl FsmState<Link> for Disconnected {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Handshaking;
+#[async_trait]
+impl FsmState<Link> for Handshaking {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Connected;
+#[async_trait]
+impl FsmState<Link> for Connected {}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct LinkDefinition(
+	InitialState<Link, Disconnected>,
+
+	Transition  < Link, Disconnected, Connect,          Handshaking,  NoAction >,
+	Transition  < Link, Handshaking,  Acknowledge,      Connected,    NoAction >,
+	Transition  < Link, Handshaking,  HandshakeTimeout, Disconnected, NoAction >,
+	TransitionSelf < Link, Handshaking, Retry, NoAction >,
+
+	StateTimeout< Link, Handshaking, HandshakeDuration, HandshakeTimeout >
+);
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_state_timeout_expires() {
+	let fsm = Link::new(&Default::default());
+	fsm.start().await;
+
+	fsm.process_event(LinkEvents::Connect(Connect)).await.unwrap();
+	assert_matches!(fsm.get_current_state().await, LinkStates::Handshaking(_));
+
+	tokio::time::sleep(Duration::from_secs(4)).await;
+	assert_eq!(fsm.get_message_queue_size().await, 0);
+
+	tokio::time::sleep(Duration::from_secs(2)).await;
+	assert_eq!(fsm.get_message_queue_size().await, 1);
+
+	fsm.execute_queued_events().await;
+	assert_matches!(fsm.get_current_state().await, LinkStates::Disconnected(_));
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_state_timeout_cancelled_on_exit() {
+	let fsm = Link::new(&Default::default());
+	fsm.start().await;
+
+	fsm.process_event(LinkEvents::Connect(Connect)).await.unwrap();
+	tokio::time::sleep(Duration::from_secs(3)).await;
+
+	fsm.process_event(LinkEvents::Acknowledge(Acknowledge)).await.unwrap();
+	assert_matches!(fsm.get_current_state().await, LinkStates::Connected(_));
+
+	tokio::time::sleep(Duration::from_secs(10)).await;
+	assert_eq!(fsm.get_message_queue_size().await, 0);
+	assert_matches!(fsm.get_current_state().await, LinkStates::Connected(_));
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_state_timeout_of_earlier_entry_is_dropped() {
+	let mut fsm = Link::new(&Default::default());
+	fsm.execute_queue_pre = false;
+	fsm.start().await;
+
+	fsm.process_event(LinkEvents::Connect(Connect)).await.unwrap();
+	tokio::time::sleep(Duration::from_secs(6)).await;
+	assert_eq!(fsm.get_message_queue_size().await, 1);
+
+	// entering the state again restarts its timer, the queued timeout is stale
+	fsm.process_event(LinkEvents::Retry(Retry)).await.unwrap();
+	fsm.execute_queued_events().await;
+	assert_eq!(fsm.get_message_queue_size().await, 0);
+	assert_matches!(fsm.get_current_state().await, LinkStates::Handshaking(_));
+
+	tokio::time::sleep(Duration::from_secs(6)).await;
+	fsm.execute_queued_events().await;
+	assert_matches!(fsm.get_current_state().await, LinkStates::Disconnected(_));
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_state_timeout_aborted_on_drop() {
+	let fsm = Link::new(&Default::default());
+	fsm.start().await;
+
+	fsm.process_event(LinkEvents::Connect(Connect)).await.unwrap();
+	let queue = std::sync::Arc::downgrade(fsm.get_queue());
+
+	drop(fsm);
+	tokio::task::yield_now().await;
+	assert!(queue.upgrade().is_none());
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_stale_state_timeout_executes_queued_events() {
+	let mut fsm = Link::new(&Default::default());
+	fsm.execute_queue_pre = false;
+	fsm.execute_queue_post = false;
+	fsm.start().await;
+
+	fsm.process_event(LinkEvents::Connect(Connect)).await.unwrap();
+	fsm.process_event(LinkEvents::Retry(Retry)).await.unwrap();
+	tokio::time::sleep(Duration::from_secs(6)).await;
+	assert_eq!(fsm.get_message_queue_size().await, 1);
+
+	// the timeout of the first entry is stale, the queue is still executed after it
+	fsm.execute_queue_post = true;
+	fsm.process_event(LinkEvents::FsmTimeoutEvent(FsmTimeoutEvent { timeout: 0, entry: 0 })).await.unwrap();
+	assert_eq!(fsm.get_message_queue_size().await, 0);
+	assert_matches!(fsm.get_current_state().await, LinkStates::Disconnected(_));
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_state_timeout_cancelled_on_stop() {
+	let fsm = Link::new(&Default::default());
+	fsm.start().await;
+
+	fsm.process_event(LinkEvents::Connect(Connect)).await.unwrap();
+	fsm.stop().await;
+
+	tokio::time::sleep(Duration::from_secs(10)).await;
+	assert_eq!(fsm.get_message_queue_size().await, 0);
+}
@@ -0,0 +1,141 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Home;
+impl FsmEvent for Home {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Clamp;
+impl FsmEvent for Clamp {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Heartbeat;
+impl FsmEvent for Heartbeat {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct CoolantOn;
+impl FsmEvent for CoolantOn {}
+
+// Mill
+
+#[derive(Debug, Clone, Default)]
+pub struct Unhomed;
+#[async_trait]
+impl FsmState<Mill> for Unhomed {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Dry;
+#[async_trait]
+impl FsmState<Mill> for Dry {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cooled;
+#[async_trait]
+impl FsmState<Mill> for Cooled {}
+
+#[async_trait]
+impl FsmState<Mill> for Fixture {}
+
+impl FsmStateFactory<()> for Fixture {
+	fn new_state(parent_context: &FsmArc<()>) -> Self {
+		Fixture::new(parent_context)
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct MillDefinition(
+	InitialState<Mill, (Unhomed, Dry)>,
+
+	SubMachine<Fixture>,
+
+	Transition        <Mill, Unhomed, Home,      Fixture, NoAction>,
+	TransitionInternal<Mill, Fixture, Heartbeat,          NoAction>,
+	Transition        <Mill, Dry,     CoolantOn, Cooled,  NoAction>,
+);
+
+// Fixture
+
+#[derive(Debug, Clone, Default)]
+pub struct Open;
+#[async_trait]
+impl FsmState<Fixture> for Open {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Clamped;
+#[async_trait]
+impl FsmState<Fixture> for Clamped {}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct FixtureDefinition(
+	InitialState<Fixture, Open>,
+
+	Transition<Fixture, Open, Clamp, Clamped, NoAction>,
+);
+
+fn transition(machine: &'static str, region: usize, source_state: &str, target_state: &str, event: &'static str) -> FsmTransitionNotification {
+	FsmTransitionNotification {
+		machine,
+		region,
+		source_state: source_state.to_string(),
+		target_state: target_state.to_string(),
+		event
+	}
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_subscribe() {
+	let mill = Mill::new(&Default::default());
+	mill.start().await;
+
+	let mut transitions = mill.subscribe();
+	let mut all_transitions = mill.subscribe_with_submachines();
+
+	mill.process_event(MillEvents::Home(Home)).await.unwrap();
+	mill.process_event(MillEvents::Heartbeat(Heartbeat)).await.unwrap();
+	mill.process_event(MillEvents::CoolantOn(CoolantOn)).await.unwrap();
+
+	{
+		let fixture: FsmArc<Fixture> = mill.get_state();
+		fixture.read().await.process_event(FixtureEvents::Clamp(Clamp)).await.unwrap();
+	}
+
+	assert_eq!(transitions.recv().await.unwrap(), transition("Mill", 0, "Unhomed", "Fixture", "Home"));
+	assert_eq!(transitions.recv().await.unwrap(), transition("Mill", 1, "Dry", "Cooled", "CoolantOn"));
+	assert!(transitions.try_recv().is_err());
+
+	assert_eq!(all_transitions.recv().await.unwrap(), transition("Mill", 0, "Unhomed", "Fixture", "Home"));
+	assert_eq!(all_transitions.recv().await.unwrap(), transition("Mill", 1, "Dry", "Cooled", "CoolantOn"));
+	assert_eq!(all_transitions.recv().await.unwrap(), transition("Fixture", 0, "Open", "Clamped", "Clamp"));
+	assert!(all_transitions.try_recv().is_err());
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_subscribers_detach() {
+	let mill = Mill::new(&Default::default());
+	mill.start().await;
+
+	let first = mill.subscribe();
+	let mut second = mill.subscribe();
+	drop(first);
+
+	mill.process_event(MillEvents::Home(Home)).await.unwrap();
+	assert_eq!(second.recv().await.unwrap().target_state, "Fixture");
+
+	drop(second);
+	mill.process_event(MillEvents::CoolantOn(CoolantOn)).await.unwrap();
+
+	let mut late = mill.subscribe();
+	assert!(late.try_recv().is_err());
+}
@@ -0,0 +1,214 @@
+extern crate fsm;
+#[macro_use]
+extern crate fsm_codegen;
+
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}};
+use tokio::sync::Notify;
+use tracing::{Event, Metadata, Subscriber, field::{Field, Visit}, span};
+
+use fsm::*;
+
+// events
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Open;
+impl FsmEvent for Open {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Close;
+impl FsmEvent for Close {}
+
+// states
+
+#[derive(Debug, Clone, Default)]
+pub struct Closed;
+#[async_trait]
+impl FsmState<Valve> for Closed {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Opened;
+#[async_trait]
+impl FsmState<Valve> for Opened {}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct ValveDefinition(
+	InitialState<Valve, Closed>,
+	InspectionType<Valve, FsmInspectTracing<Valve>>,
+
+	Transition<Valve, Closed, Open,  Opened, NoAction>,
+	Transition<Valve, Opened, Close, Closed, NoAction>,
+);
+
+// Gate, its actions wait until the test releases them
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Lift;
+impl FsmEvent for Lift {}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Ping;
+impl FsmEvent for Ping {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Shut;
+#[async_trait]
+impl FsmState<Gate> for Shut {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Up;
+#[async_trait]
+impl FsmState<Gate> for Up {}
+
+#[derive(Debug, Default)]
+pub struct GateContext {
+	lift: Arc<Notify>,
+	ping: Arc<Notify>
+}
+
+pub struct WaitLift;
+#[async_trait]
+impl FsmAction<Gate, Shut, Up> for WaitLift {
+	async fn action(event_context: &EventContext<'_, Gate>, _: &Shut, _: &Up) -> FsmTransitionResult<()> {
+		let lift = Arc::clone(&event_context.context.read().await.lift);
+		lift.notified().await;
+
+		Ok(())
+	}
+}
+
+pub struct WaitPing;
+#[async_trait]
+impl FsmActionSelf<Gate, Shut> for WaitPing {
+	async fn action(event_context: &EventContext<'_, Gate>, _: &Shut) -> FsmTransitionResult<()> {
+		let ping = Arc::clone(&event_context.context.read().await.ping);
+		ping.notified().await;
+
+		Ok(())
+	}
+}
+
+#[derive(Fsm)]
+#[allow(dead_code)]
+struct GateDefinition(
+	InitialState<Gate, Shut>,
+	ContextType<GateContext>,
+	InspectionType<Gate, FsmInspectTracing<Gate>>,
+
+	Transition        <Gate, Shut, Lift, Up, WaitLift>,
+	TransitionInternal<Gate, Shut, Ping,     WaitPing>,
+);
+
+// records the spans and the events with the fields of their parent span
+#[derive(Clone, Default)]
+struct Recorder {
+	next_id: Arc<AtomicU64>,
+	spans: Arc<Mutex<Vec<String>>>,
+	records: Arc<Mutex<Vec<String>>>
+}
+
+struct Fields(String);
+
+impl Visit for Fields {
+	fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+		self.0.push_str(&format!(" {}={:?}", field.name(), value));
+	}
+}
+
+impl Subscriber for Recorder {
+	fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+		true
+	}
+
+	fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+		let mut fields = Fields(span.metadata().name().to_string());
+		span.record(&mut fields);
+		self.spans.lock().unwrap().push(fields.0);
+
+		span::Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+	}
+
+	fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+		let mut fields = Fields(String::new());
+		values.record(&mut fields);
+		self.spans.lock().unwrap()[span.into_u64() as usize - 1].push_str(&fields.0);
+	}
+
+	fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+	fn event(&self, event: &Event<'_>) {
+		let parent = event.parent().map(|id| self.spans.lock().unwrap()[id.into_u64() as usize - 1].clone());
+		let mut fields = Fields(format!("[{}]", parent.unwrap_or_default()));
+		event.record(&mut fields);
+		self.records.lock().unwrap().push(fields.0);
+	}
+
+	fn enter(&self, _span: &span::Id) {}
+	fn exit(&self, _span: &span::Id) {}
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_tracing_inspect() {
+	let recorder = Recorder::default();
+	let _guard = tracing::subscriber::set_default(recorder.clone());
+
+	let valve = Valve::new(&Default::default());
+	valve.start().await;
+	assert_eq!(recorder.records.lock().unwrap()[0], "[] message=on_state_entry machine=\"Valve\" region=0 state=\"Closed\"");
+
+	recorder.spans.lock().unwrap().clear();
+	recorder.records.lock().unwrap().clear();
+	recorder.next_id.store(0, Ordering::SeqCst);
+
+	valve.process_event(ValveEvents::Open(Open)).await.unwrap();
+	assert_eq!(valve.process_event(ValveEvents::Open(Open)).await, Err(FsmError::NoTransition));
+
+	assert_eq!(*recorder.spans.lock().unwrap(), vec![
+		"process_event machine=\"Valve\" event=\"Open\" result=Ok(())",
+		"process_event machine=\"Valve\" event=\"Open\" result=Err(NoTransition)",
+	]);
+
+	assert_eq!(*recorder.records.lock().unwrap(), vec![
+		"[process_event machine=\"Valve\" event=\"Open\"] message=on_state_exit machine=\"Valve\" region=0 state=\"Closed\"",
+		"[process_event machine=\"Valve\" event=\"Open\"] message=on_action machine=\"Valve\" region=0 state=\"Closed\"",
+		"[process_event machine=\"Valve\" event=\"Open\"] message=on_state_entry machine=\"Valve\" region=0 state=\"Opened\"",
+		"[process_event machine=\"Valve\" event=\"Open\"] message=on_no_transition machine=\"Valve\" region=0 state=\"Opened\"",
+	]);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_tracing_inspect_concurrent_events() {
+	let recorder = Recorder::default();
+	let _guard = tracing::subscriber::set_default(recorder.clone());
+
+	let gate = Gate::new(&Default::default());
+	gate.start().await;
+	let (lift, ping) = {
+		let context = gate.get_context().read().await;
+		(Arc::clone(&context.lift), Arc::clone(&context.ping))
+	};
+
+	recorder.spans.lock().unwrap().clear();
+	recorder.records.lock().unwrap().clear();
+	recorder.next_id.store(0, Ordering::SeqCst);
+
+	// Ping starts after Lift and finishes after it
+	let release = async {
+		lift.notify_one();
+		tokio::task::yield_now().await;
+		ping.notify_one();
+	};
+	let (lifted, pinged, _) = tokio::join!(gate.process_event(GateEvents::Lift(Lift)), gate.process_event(GateEvents::Ping(Ping)), release);
+	lifted.unwrap();
+	pinged.unwrap();
+
+	assert_eq!(*recorder.spans.lock().unwrap(), vec![
+		"process_event machine=\"Gate\" event=\"Lift\" result=Ok(())",
+		"process_event machine=\"Gate\" event=\"Ping\" result=Ok(())",
+	]);
+
+	assert!(recorder.records.lock().unwrap().contains(&"[process_event machine=\"Gate\" event=\"Lift\"] message=on_state_entry machine=\"Gate\" region=0 state=\"Up\"".to_string()));
+}
@@ -0,0 +1,18 @@
+use async_trait::async_trait;
+use fsm::*;
+use fsm_codegen::Fsm;
+
+#[derive(Clone, PartialEq, Default, Debug)] pub struct Start;
+impl FsmEvent for Start {}
+#[derive(Debug, Clone, Default)] pub struct Idle;
+#[async_trait] impl FsmState<Drive> for Idle {}
+#[derive(Debug, Clone, Default)] pub struct Running;
+#[async_trait] impl FsmState<Drive> for Running {}
+
+#[derive(Fsm)]
+struct DriveDefinition(
+	InitialState<Drive, Idle>,
+	Fork<Drive, Idle, Start, Running, (Idle, Running)>
+);
+
+fn main() {}
@@ -0,0 +1,26 @@
+use async_trait::async_trait;
+use fsm::*;
+use fsm_codegen::Fsm;
+
+#[derive(Clone, PartialEq, Default, Debug)] pub struct Start;
+impl FsmEvent for Start {}
+#[derive(Debug, Clone, Default)] pub struct Idle;
+#[async_trait] impl FsmState<Drive> for Idle {}
+#[derive(Debug, Clone, Default)] pub struct Running;
+#[async_trait] impl FsmState<Drive> for Running {}
+
+pub struct Homed;
+impl FsmGuard<Drive> for Homed {
+	fn guard(_: &EventContext<Drive>, _: &DriveStatesStore) -> bool {
+		true
+	}
+}
+
+#[derive(Fsm)]
+struct DriveDefinition(
+	InitialState<Drive, Idle>,
+	TransitionSelf<Drive, Idle, Start, NoAction>,
+	TransitionGuard<Drive, Idle, Start, Running, NoAction, Homed>
+);
+
+fn main() {}
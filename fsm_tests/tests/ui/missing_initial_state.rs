@@ -0,0 +1,17 @@
+use async_trait::async_trait;
+use fsm::*;
+use fsm_codegen::Fsm;
+
+#[derive(Clone, PartialEq, Default, Debug)] pub struct Start;
+impl FsmEvent for Start {}
+#[derive(Debug, Clone, Default)] pub struct Idle;
+#[async_trait] impl FsmState<Drive> for Idle {}
+#[derive(Debug, Clone, Default)] pub struct Running;
+#[async_trait] impl FsmState<Drive> for Running {}
+
+#[derive(Fsm)]
+struct DriveDefinition(
+	Transition<Drive, Idle, Start, Running, NoAction>
+);
+
+fn main() {}
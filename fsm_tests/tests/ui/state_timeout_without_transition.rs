@@ -0,0 +1,23 @@
+use async_trait::async_trait;
+use fsm::*;
+use fsm_codegen::Fsm;
+
+#[derive(Clone, PartialEq, Default, Debug)] pub struct Connect;
+impl FsmEvent for Connect {}
+#[derive(Clone, PartialEq, Default, Debug)] pub struct HandshakeTimeout;
+impl FsmEvent for HandshakeTimeout {}
+#[derive(Debug, Clone, Default)] pub struct Disconnected;
+#[async_trait] impl FsmState<Link> for Disconnected {}
+#[derive(Debug, Clone, Default)] pub struct Handshaking;
+#[async_trait] impl FsmState<Link> for Handshaking {}
+
+fsm_duration!(HandshakeDuration, std::time::Duration::from_secs(5));
+
+#[derive(Fsm)]
+struct LinkDefinition(
+	InitialState<Link, Disconnected>,
+	Transition<Link, Disconnected, Connect, Handshaking, NoAction>,
+	StateTimeout<Link, Handshaking, HandshakeDuration, HandshakeTimeout>
+);
+
+fn main() {}
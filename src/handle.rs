@@ -0,0 +1,116 @@
+use crate::prelude::v1::*;
+
+use std::{
+	future::Future,
+	sync::{Arc, Mutex}
+};
+
+use tokio::{
+	sync::{mpsc, oneshot, Notify},
+	task::JoinHandle
+};
+
+use crate::machine::*;
+
+enum FsmHandleCommand<F: Fsm> {
+	Event(F::E, oneshot::Sender<Result<(), FsmError>>),
+	Stop
+}
+
+/// A cloneable handle to a machine that runs on its own tokio task, see `Fsm::spawn`.
+///
+/// Events are processed one at a time in the order they were sent. Events that end up in the
+/// queue of the machine by other means, e.g. state timeouts, are executed as soon as they are queued,
+/// for queues that notify about them, see `FsmEventQueue::event_queued`.
+pub struct FsmHandle<F: Fsm> {
+	sender: mpsc::UnboundedSender<FsmHandleCommand<F>>,
+	task: Arc<Mutex<Option<JoinHandle<F>>>>
+}
+
+impl<F> FsmHandle<F> where F: Fsm + Send + Sync + 'static, F::E: Send {
+	pub fn spawn(fsm: F) -> Self {
+		let (sender, receiver) = mpsc::unbounded_channel();
+		let task = tokio::spawn(Self::run(fsm, receiver));
+
+		FsmHandle {
+			sender,
+			task: Arc::new(Mutex::new(Some(task)))
+		}
+	}
+
+	async fn run(fsm: F, mut receiver: mpsc::UnboundedReceiver<FsmHandleCommand<F>>) -> F {
+		let event_queued = fsm.get_queue().read().await.event_queued();
+
+		loop {
+			tokio::select! {
+				command = receiver.recv() => match command {
+					Some(FsmHandleCommand::Event(event, response)) => {
+						let result = fsm.process_event(event).await;
+						let _ = response.send(result);
+					},
+					Some(FsmHandleCommand::Stop) | None => break
+				},
+				_ = Self::event_queued(&event_queued) => {
+					fsm.execute_queued_events().await;
+				}
+			}
+		}
+
+		fsm.execute_queued_events().await;
+		fsm.stop().await;
+
+		fsm
+	}
+
+	async fn event_queued(event_queued: &Option<Arc<Notify>>) {
+		match event_queued {
+			Some(event_queued) => event_queued.notified().await,
+			None => std::future::pending().await
+		}
+	}
+
+	/// Send an event to the machine. The returned future resolves once the machine processed it.
+	/// The event is sent right away, the future doesn't have to be polled for the event to be processed.
+	pub fn process_event(&self, event: F::E) -> impl Future<Output = Result<(), FsmError>> {
+		let (response, result) = oneshot::channel();
+		let sent = self.sender.send(FsmHandleCommand::Event(event, response)).is_ok();
+
+		async move {
+			if !sent {
+				return Err(FsmError::Stopped);
+			}
+
+			result.await.unwrap_or(Err(FsmError::Stopped))
+		}
+	}
+
+	/// Stop the machine once the events sent before are processed. Events sent afterwards fail with `FsmError::Stopped`.
+	pub fn stop(&self) {
+		let _ = self.sender.send(FsmHandleCommand::Stop);
+	}
+
+	/// Wait for the machine to stop and take it back. Only the first call across all the clones of the handle gets the machine.
+	pub async fn join(&self) -> Option<F> {
+		let task = self.task.lock().unwrap().take()?;
+		task.await.ok()
+	}
+
+	pub fn is_stopped(&self) -> bool {
+		self.sender.is_closed()
+	}
+}
+
+impl<F: Fsm> Clone for FsmHandle<F> {
+	fn clone(&self) -> Self {
+		FsmHandle {
+			sender: self.sender.clone(),
+			task: Arc::clone(&self.task)
+		}
+	}
+}
+
+impl<F: Fsm> Debug for FsmHandle<F> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("FsmHandle").field("stopped", &self.sender.is_closed()).finish()
+	}
+}
@@ -0,0 +1,76 @@
+use crate::prelude::v1::*;
+
+use async_trait::async_trait;
+use tracing::{Level, Span};
+
+use crate::machine::*;
+
+/// Reports the activity of a machine as `tracing` spans and events, use it as
+/// `InspectionType<F, FsmInspectTracing<F>>`.
+///
+/// Every processed event opens a `process_event` span, the state entries, exits, actions and
+/// unhandled events are recorded as events within it. The span is kept in the event's `EventContext`.
+pub struct FsmInspectTracing<F: Fsm> {
+	_fsm_ty: PhantomData<F>
+}
+
+impl<F: Fsm> FsmInspectTracing<F> {
+	fn machine() -> &'static str {
+		fsm_machine_name::<F>()
+	}
+
+	fn span(event_context: &EventContext<'_, F>) -> Span {
+		event_context.inspection.get().unwrap_or_else(Span::none)
+	}
+}
+
+#[async_trait]
+impl<F> FsmInspect<F> for FsmInspectTracing<F> where F: Fsm + Sync, F::S: FsmRetrieveStateName + Sync {
+	fn new_from_context(_context: &FsmArc<F::C>) -> Self {
+		FsmInspectTracing {
+			_fsm_ty: PhantomData
+		}
+	}
+
+	async fn on_event(&self, event_context: &EventContext<'_, F>) {
+		let span = tracing::span!(Level::INFO, "process_event",
+			machine = Self::machine(),
+			event = event_context.event.name(),
+			result = tracing::field::Empty
+		);
+
+		event_context.inspection.set(span);
+	}
+
+	async fn on_event_processed(&self, event_context: &EventContext<'_, F>, result: &Result<(), FsmError>) {
+		if let Some(span) = event_context.inspection.take::<Span>() {
+			span.record("result", tracing::field::debug(result));
+		}
+	}
+
+	async fn on_state_entry(&self, state: &F::S, event_context: &EventContext<'_, F>) {
+		let state = state.name().await;
+		tracing::event!(parent: &Self::span(event_context), Level::DEBUG, machine = Self::machine(), region = event_context.region, state = state.as_str(), "on_state_entry");
+	}
+
+	async fn on_state_exit(&self, state: &F::S, event_context: &EventContext<'_, F>) {
+		let state = state.name().await;
+		tracing::event!(parent: &Self::span(event_context), Level::DEBUG, machine = Self::machine(), region = event_context.region, state = state.as_str(), "on_state_exit");
+	}
+
+	async fn on_action(&self, state: &F::S, event_context: &EventContext<'_, F>) {
+		let state = state.name().await;
+		tracing::event!(parent: &Self::span(event_context), Level::DEBUG, machine = Self::machine(), region = event_context.region, state = state.as_str(), "on_action");
+	}
+
+	async fn on_no_transition(&self, state: &F::S, event_context: &EventContext<'_, F>) {
+		let state = state.name().await;
+		tracing::event!(parent: &Self::span(event_context), Level::DEBUG, machine = Self::machine(), region = event_context.region, state = state.as_str(), "on_no_transition");
+	}
+}
+
+impl<F: Fsm> Debug for FsmInspectTracing<F> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("FsmInspectTracing").field("machine", &Self::machine()).finish()
+	}
+}
@@ -19,5 +19,34 @@ mod machine;
 
 pub use machine::*;
 
+mod handle;
+mod metrics;
+mod notifications;
+
+pub use handle::*;
+pub use metrics::*;
+pub use notifications::*;
+
+#[cfg(feature = "serde")]
+mod snapshot;
+
+#[cfg(feature = "serde")]
+pub use snapshot::*;
+
+#[cfg(feature = "recorder")]
+mod recorder;
+
+#[cfg(feature = "recorder")]
+pub use recorder::*;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "tracing")]
+mod inspect_tracing;
+
+#[cfg(feature = "tracing")]
+pub use inspect_tracing::*;
+
 #[macro_use]
 mod macros;
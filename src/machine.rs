@@ -1,19 +1,29 @@
 use crate::prelude::v1::*;
 
 use std::{
+	any::Any,
 	fmt,
 	sync::Arc,
-	error::Error
+	error::Error,
+	future::Future,
+	pin::Pin,
+	time::Duration,
+	collections::{BTreeMap, VecDeque}
 };
 
 use async_trait::async_trait;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, Notify, RwLock};
 use thiserror::Error;
 
+use crate::handle::FsmHandle;
+use crate::notifications::FsmTransitionNotification;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum FsmError {
 	NoTransition,
-	Interrupted
+	Interrupted,
+	/// The machine behind an `FsmHandle` was stopped.
+	Stopped
 }
 
 impl fmt::Display for FsmError {
@@ -24,6 +34,20 @@ impl fmt::Display for FsmError {
 
 impl Error for FsmError {}
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FsmQueueError {
+	/// The queue is at its capacity and rejected the event.
+	Full
+}
+
+impl fmt::Display for FsmQueueError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:?}", self)
+	}
+}
+
+impl Error for FsmQueueError {}
+
 #[derive(Error, Clone, Debug)]
 #[error(transparent)]
 pub struct FsmTransitionError(#[from] pub Arc<anyhow::Error>);
@@ -34,20 +58,69 @@ impl From<FsmError> for FsmTransitionError {
 	}
 }
 
+impl From<FsmQueueError> for FsmTransitionError {
+	fn from(err: FsmQueueError) -> Self {
+		FsmTransitionError(Arc::new(err.into()))
+	}
+}
+
 pub type FsmTransitionResult<T, E = FsmTransitionError> = std::result::Result<T, E>;
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FsmHistory {
+	/// Restore the active states of the machine, nested submachines are started from their initial states.
+	Shallow,
+	/// Restore the active states of the machine and of every nested submachine.
+	Deep
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum FsmQueueStatus {
 	Empty,
 	MoreEventsQueued
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FsmEnqueueStatus {
+	Queued,
+	/// The event was queued, the oldest event in the queue was dropped to make room for it.
+	DroppedOldest,
+	/// The queue was full, the event was dropped.
+	DroppedNewest
+}
+
+pub type FsmEnqueueResult = Result<FsmEnqueueStatus, FsmQueueError>;
+
+/// What a bounded queue does with an event when it is full.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FsmQueueOverflow {
+	/// Reject the event with `FsmQueueError::Full`.
+	Reject,
+	DropOldest,
+	DropNewest,
+	/// `EventContext::enqueue_event` waits until an event is dequeued. Only use it for events that are
+	/// queued from outside of the machine, an action waiting for the queue of its own machine never wakes up.
+	Wait
+}
+
 pub trait FsmEvent {
 
 }
 pub trait FsmEvents<F: Fsm>: Send + Sync {
 	fn new_no_event() -> Self;
 	fn new_error_event(error: FsmTransitionError) -> Self;
+	/// The name of the event type.
+	fn name(&self) -> &'static str;
+	/// The names of the events that the machine accepts, without `NoEvent` and `FsmErrorEvent`.
+	fn variants() -> &'static [&'static str];
+}
+
+/// Converts the events between the levels of a hierarchy of machines, for `HierarchicalDispatch`.
+pub trait FsmEventsAny: Sized {
+	/// The event without its enum, or the enum back for `NoEvent` and `FsmErrorEvent`.
+	fn into_any(self) -> Result<Box<dyn Any + Send>, Self>;
+	/// The event of this machine, or the event back if the machine doesn't accept it.
+	fn from_any(event: Box<dyn Any + Send>) -> Result<Self, Box<dyn Any + Send>>;
 }
 
 #[async_trait]
@@ -65,6 +138,9 @@ pub trait FsmInspect<F: Fsm> {
 	async fn on_action(&self, _state: &F::S, _event_context: &EventContext<'_, F>) { }
 	async fn on_transition(&self, _source_state: &F::S, _target_state: &F::S, _event_context: &EventContext<'_, F>) { }
 	async fn on_no_transition(&self, _current_state: &F::S, _event_context: &EventContext<'_, F>) { }
+	/// Invoked before an event is dispatched to the regions, and once it was processed.
+	async fn on_event(&self, _event_context: &EventContext<'_, F>) { }
+	async fn on_event_processed(&self, _event_context: &EventContext<'_, F>, _result: &Result<(), FsmError>) { }
 }
 
 #[derive(Debug, Default)]
@@ -147,15 +223,31 @@ impl<F: Fsm> FsmGuard<F> for NoGuard {
 	}
 }
 
+/// A guard that can await the source state, the context or any other lock.
+/// It is evaluated before the transition is selected. Every `FsmGuard` is also an `FsmGuardAsync`.
+#[async_trait]
+pub trait FsmGuardAsync<F: Fsm, S: Send + Sync> {
+	async fn guard(event_context: &EventContext<'_, F>, source_state: &S, states: &F::SS) -> bool;
+}
 
+#[async_trait]
+impl<F: Fsm, S: Send + Sync, G: FsmGuard<F>> FsmGuardAsync<F, S> for G {
+	#[inline]
+	async fn guard(event_context: &EventContext<'_, F>, _source_state: &S, states: &F::SS) -> bool {
+		<G as FsmGuard<F>>::guard(event_context, states)
+	}
+}
+
+
+/// A transition action. Returning an error routes the machine to its `ErrorState`.
 #[async_trait]
 pub trait FsmAction<F: Fsm, S, T> {
-	async fn action(event_context: &EventContext<'_, F>, source_state: &S, target_state: &T);
+	async fn action(event_context: &EventContext<'_, F>, source_state: &S, target_state: &T) -> FsmTransitionResult<()>;
 }
 
 #[async_trait]
 pub trait FsmActionSelf<F: Fsm, S> {
-	async fn action(event_context: &EventContext<'_, F>, state: &S);
+	async fn action(event_context: &EventContext<'_, F>, state: &S) -> FsmTransitionResult<()>;
 }
 
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -166,17 +258,82 @@ impl FsmEvent for NoEvent { }
 pub struct FsmErrorEvent(pub FsmTransitionError);
 impl FsmEvent for FsmErrorEvent {}
 
+/// A wildcard for the event of `Transition`, `TransitionSelf` and `TransitionInternal`. It matches every event
+/// that the source state has no specific transition for and doesn't defer, except for `NoEvent` and `FsmErrorEvent`,
+/// and the events whose guarded transitions all failed. The action receives the concrete event through `EventContext::event`.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct AnyEvent;
+impl FsmEvent for AnyEvent { }
+
+/// The completion event of a submachine, sent to the parent machine once
+/// every region of the submachine reached a final state.
+pub struct Completed<F: Fsm>(PhantomData<F>);
+
+impl<F: Fsm> Completed<F> {
+	pub fn new() -> Self {
+		Completed(PhantomData)
+	}
+}
+
+impl<F: Fsm> FsmEvent for Completed<F> {}
+
+impl<F: Fsm> Default for Completed<F> {
+	fn default() -> Self {
+		Completed::new()
+	}
+}
+
+impl<F: Fsm> Clone for Completed<F> {
+	fn clone(&self) -> Self {
+		*self
+	}
+}
+
+impl<F: Fsm> Copy for Completed<F> {}
+
+impl<F: Fsm> PartialEq for Completed<F> {
+	fn eq(&self, _other: &Self) -> bool {
+		true
+	}
+}
+
+impl<F: Fsm> Debug for Completed<F> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Completed<{}>", std::any::type_name::<F>())
+	}
+}
+
+/// Invoked by a submachine when it completes, used by the parent machine to queue the `Completed` event.
+#[derive(Clone)]
+pub struct FsmCompletionHook(Arc<dyn Fn() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>);
+
+impl FsmCompletionHook {
+	pub fn new<H, R>(hook: H) -> Self where H: Fn() -> R + Send + Sync + 'static, R: Future<Output = ()> + Send + 'static {
+		FsmCompletionHook(Arc::new(move || Box::pin(hook())))
+	}
+
+	pub async fn call(&self) {
+		(self.0)().await
+	}
+}
+
+impl Debug for FsmCompletionHook {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "FsmCompletionHook")
+	}
+}
+
 #[derive(Debug)]
 pub struct NoAction;
 #[async_trait]
 impl<F: Fsm, S: Send + Sync, T: Send + Sync> FsmAction<F, S, T> for NoAction {
 	#[inline]
-	async fn action(_event_context: &EventContext<'_, F>, _source_state: &S, _target_state: &T) { }
+	async fn action(_event_context: &EventContext<'_, F>, _source_state: &S, _target_state: &T) -> FsmTransitionResult<()> { Ok(()) }
 }
 #[async_trait]
 impl<F: Fsm, S: Send + Sync> FsmActionSelf<F, S> for NoAction {
 	#[inline]
-	async fn action(_event_context: &EventContext<'_, F>, _state: &S) { }
+	async fn action(_event_context: &EventContext<'_, F>, _state: &S) -> FsmTransitionResult<()> { Ok(()) }
 }
 
 
@@ -185,23 +342,101 @@ pub struct EventContext<'a, F: Fsm + 'a> {
 	pub queue: FsmArc<dyn FsmEventQueue<F>>,
 	pub context: FsmArc<F::C>,
 	pub current_state: F::CS,
+	/// The region that is handling the event.
+	pub region: usize,
+	/// What the inspector keeps while the event is processed.
+	pub inspection: FsmInspectionData,
 	//pub states: &'a mut F::SS
 }
 
+/// Data that the inspector keeps for a single event, such as the span of `FsmInspectTracing`. The hooks get the
+/// `EventContext` of the event they are invoked for, events processed concurrently don't share it.
+#[derive(Default)]
+pub struct FsmInspectionData(std::sync::Mutex<Option<Box<dyn Any + Send>>>);
+
+impl FsmInspectionData {
+	pub fn set<T: Any + Send>(&self, data: T) {
+		*self.0.lock().unwrap() = Some(Box::new(data));
+	}
+
+	pub fn get<T: Any + Clone>(&self) -> Option<T> {
+		self.0.lock().unwrap().as_ref()?.downcast_ref::<T>().cloned()
+	}
+
+	pub fn take<T: Any>(&self) -> Option<T> {
+		let data = self.0.lock().unwrap().take()?;
+		data.downcast().ok().map(|data| *data)
+	}
+}
+
+impl Debug for FsmInspectionData {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("FsmInspectionData")
+	}
+}
+
 impl<'a, F: Fsm + 'a> EventContext<'a, F> {
-	pub async fn enqueue_event(&self, event: F::E) {
-		self
-			.queue
-			.write()
-			.await
-			.enqueue_event(event);
+	pub async fn enqueue_event(&self, event: F::E) -> FsmEnqueueResult {
+		fsm_enqueue_event(&self.queue, event, 0).await
+	}
+
+	pub async fn enqueue_event_with_priority(&self, event: F::E, priority: FsmEventPriority) -> FsmEnqueueResult {
+		fsm_enqueue_event(&self.queue, event, priority).await
+	}
+}
+
+/// Queue an event, waiting for space in queues that apply back-pressure.
+pub async fn fsm_enqueue_event<F: Fsm>(queue: &FsmArc<dyn FsmEventQueue<F>>, event: F::E, priority: FsmEventPriority) -> FsmEnqueueResult {
+	loop {
+		let space_available = {
+			let mut queue = queue.write().await;
+			match queue.space_available() {
+				Some(space_available) if queue.is_full() => space_available,
+				_ => return queue.enqueue_event_with_priority(event, priority)
+			}
+		};
+
+		space_available.notified().await;
 	}
 }
 
+/// Events with a higher priority are dequeued first. `enqueue_event` uses the default priority, `0`.
+pub type FsmEventPriority = i32;
+
 pub trait FsmEventQueue<F: Fsm>: Debug + Send + Sync {
-	fn enqueue_event(&mut self, event: F::E);
+	fn enqueue_event(&mut self, event: F::E) -> FsmEnqueueResult;
 	fn dequeue_event(&mut self) -> Option<F::E>;
 	fn len(&self) -> usize;
+
+	/// The queued events, in the order they would be dequeued. Snapshots capture the events that the queue lists,
+	/// queues that don't list them are captured empty.
+	fn events(&self) -> Vec<&F::E> {
+		Vec::new()
+	}
+
+	/// The queued events with the priorities they were queued with, in the order they would be dequeued.
+	fn events_with_priority(&self) -> Vec<(FsmEventPriority, &F::E)> {
+		self.events().into_iter().map(|event| (0, event)).collect()
+	}
+
+	/// Queues that don't support priorities ignore it.
+	fn enqueue_event_with_priority(&mut self, event: F::E, _priority: FsmEventPriority) -> FsmEnqueueResult {
+		self.enqueue_event(event)
+	}
+
+	fn is_full(&self) -> bool {
+		false
+	}
+
+	/// Notified when an event is dequeued, for queues that apply back-pressure when they are full.
+	fn space_available(&self) -> Option<Arc<Notify>> {
+		None
+	}
+
+	/// Notified when an event is queued, a spawned machine executes the events it queues for itself on it.
+	fn event_queued(&self) -> Option<Arc<Notify>> {
+		None
+	}
 }
 
 pub trait FsmRetrieveState<S> {
@@ -210,47 +445,281 @@ pub trait FsmRetrieveState<S> {
 
 #[derive(Debug)]
 pub struct FsmEventQueueVec<F: Fsm> {
-	queue: Vec<F::E>
+	queue: VecDeque<F::E>,
+	event_queued: Arc<Notify>
 }
 
 impl<F: Fsm> FsmEventQueueVec<F> {
 	pub fn new() -> Self {
 		FsmEventQueueVec {
-			queue: Vec::new()
+			queue: VecDeque::new(),
+			event_queued: Arc::new(Notify::new())
 		}
 	}
 }
 
 impl<F: Fsm> FsmEventQueue<F> for FsmEventQueueVec<F> {
-	fn enqueue_event(&mut self, event: F::E) {
-		self.queue.push(event);
+	fn enqueue_event(&mut self, event: F::E) -> FsmEnqueueResult {
+		self.queue.push_back(event);
+		self.event_queued.notify_one();
+		Ok(FsmEnqueueStatus::Queued)
 	}
 
 	fn dequeue_event(&mut self) -> Option<F::E> {
-		if self.queue.len() > 0 {
-			Some(self.queue.remove(0))
-		} else {
-			None
+		self.queue.pop_front()
+	}
+
+	fn len(&self) -> usize {
+		self.queue.len()
+	}
+
+	fn events(&self) -> Vec<&F::E> {
+		self.queue.iter().collect()
+	}
+
+	fn event_queued(&self) -> Option<Arc<Notify>> {
+		Some(Arc::clone(&self.event_queued))
+	}
+}
+
+/// Dequeues the events with the highest priority first, and events of the same priority in the order they were queued.
+#[derive(Debug)]
+pub struct FsmEventQueuePriority<F: Fsm> {
+	queue: BTreeMap<FsmEventPriority, VecDeque<F::E>>,
+	len: usize,
+	event_queued: Arc<Notify>
+}
+
+impl<F: Fsm> FsmEventQueuePriority<F> {
+	pub fn new() -> Self {
+		FsmEventQueuePriority {
+			queue: BTreeMap::new(),
+			len: 0,
+			event_queued: Arc::new(Notify::new())
 		}
 	}
+}
+
+impl<F: Fsm> Default for FsmEventQueuePriority<F> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<F: Fsm> FsmEventQueue<F> for FsmEventQueuePriority<F> {
+	fn enqueue_event(&mut self, event: F::E) -> FsmEnqueueResult {
+		self.enqueue_event_with_priority(event, 0)
+	}
+
+	fn enqueue_event_with_priority(&mut self, event: F::E, priority: FsmEventPriority) -> FsmEnqueueResult {
+		self.queue.entry(priority).or_default().push_back(event);
+		self.len += 1;
+		self.event_queued.notify_one();
+
+		Ok(FsmEnqueueStatus::Queued)
+	}
+
+	fn dequeue_event(&mut self) -> Option<F::E> {
+		let priority = *self.queue.keys().next_back()?;
+		let events = self.queue.get_mut(&priority)?;
+		let event = events.pop_front();
+		if events.is_empty() {
+			self.queue.remove(&priority);
+		}
+
+		self.len -= 1;
+		event
+	}
+
+	fn len(&self) -> usize {
+		self.len
+	}
+
+	fn events(&self) -> Vec<&F::E> {
+		self.queue.values().rev().flatten().collect()
+	}
+
+	fn events_with_priority(&self) -> Vec<(FsmEventPriority, &F::E)> {
+		self.queue.iter().rev().flat_map(|(priority, events)| events.iter().map(move |event| (*priority, event))).collect()
+	}
+
+	fn event_queued(&self) -> Option<Arc<Notify>> {
+		Some(Arc::clone(&self.event_queued))
+	}
+}
+
+/// A ring buffer that holds up to `capacity` events, the overflow policy decides what happens to events queued beyond it.
+#[derive(Debug)]
+pub struct FsmEventQueueBounded<F: Fsm> {
+	queue: VecDeque<F::E>,
+	capacity: usize,
+	overflow: FsmQueueOverflow,
+	space_available: Arc<Notify>,
+	event_queued: Arc<Notify>
+}
+
+impl<F: Fsm> FsmEventQueueBounded<F> {
+	pub fn new(capacity: usize, overflow: FsmQueueOverflow) -> Self {
+		assert!(capacity > 0, "A bounded queue needs room for at least one event");
+
+		FsmEventQueueBounded {
+			queue: VecDeque::with_capacity(capacity),
+			capacity,
+			overflow,
+			space_available: Arc::new(Notify::new()),
+			event_queued: Arc::new(Notify::new())
+		}
+	}
+}
+
+impl<F: Fsm> FsmEventQueue<F> for FsmEventQueueBounded<F> {
+	fn enqueue_event(&mut self, event: F::E) -> FsmEnqueueResult {
+		if self.queue.len() < self.capacity {
+			self.queue.push_back(event);
+			self.event_queued.notify_one();
+			return Ok(FsmEnqueueStatus::Queued);
+		}
+
+		match self.overflow {
+			FsmQueueOverflow::Reject | FsmQueueOverflow::Wait => Err(FsmQueueError::Full),
+			FsmQueueOverflow::DropOldest => {
+				self.queue.pop_front();
+				self.queue.push_back(event);
+				self.event_queued.notify_one();
+				Ok(FsmEnqueueStatus::DroppedOldest)
+			},
+			FsmQueueOverflow::DropNewest => Ok(FsmEnqueueStatus::DroppedNewest)
+		}
+	}
+
+	fn dequeue_event(&mut self) -> Option<F::E> {
+		let event = self.queue.pop_front();
+		if event.is_some() && self.overflow == FsmQueueOverflow::Wait {
+			self.space_available.notify_one();
+		}
+
+		event
+	}
 
 	fn len(&self) -> usize {
 		self.queue.len()
 	}
+
+	fn events(&self) -> Vec<&F::E> {
+		self.queue.iter().collect()
+	}
+
+	fn is_full(&self) -> bool {
+		self.queue.len() >= self.capacity
+	}
+
+	fn space_available(&self) -> Option<Arc<Notify>> {
+		if self.overflow == FsmQueueOverflow::Wait {
+			Some(Arc::clone(&self.space_available))
+		} else {
+			None
+		}
+	}
+
+	fn event_queued(&self) -> Option<Arc<Notify>> {
+		Some(Arc::clone(&self.event_queued))
+	}
 }
 
 pub type FsmArc<T> = Arc<RwLock<T>>;
 
+/// A duration known at compile time, used by `StateTimeout`. See the `fsm_duration!` macro.
+pub trait FsmDuration {
+	fn duration() -> Duration;
+}
+
+/// Queued by an expired `StateTimeout` in place of its event, tagged with the entry of the state that started the timer.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct FsmTimeoutEvent {
+	/// The index of the `StateTimeout` in the machine's definition.
+	pub timeout: usize,
+	pub entry: u64
+}
+impl FsmEvent for FsmTimeoutEvent {}
+
+/// The timer of a `StateTimeout`. It counts the entries of its state, a `FsmTimeoutEvent` of an earlier entry is stale.
+/// The timer is aborted when it is dropped with its machine.
+#[derive(Debug, Default)]
+pub struct FsmStateTimer {
+	entry: u64,
+	task: Option<tokio::task::JoinHandle<()>>
+}
+
+impl FsmStateTimer {
+	/// Starts the timer for a new entry of the state, `expire` gets the entry that its event is tagged with.
+	pub fn start<T: Future<Output = ()> + Send + 'static>(&mut self, expire: impl FnOnce(u64) -> T) {
+		self.cancel();
+		self.task = Some(tokio::spawn(expire(self.entry)));
+	}
+
+	/// Cancels the timer when the state is exited, its event is stale if it was already queued.
+	pub fn cancel(&mut self) {
+		self.entry += 1;
+		if let Some(task) = self.task.take() {
+			task.abort();
+		}
+	}
+
+	pub fn is_current(&self, entry: u64) -> bool {
+		self.task.is_some() && self.entry == entry
+	}
+}
+
+impl Drop for FsmStateTimer {
+	fn drop(&mut self) {
+		if let Some(task) = self.task.take() {
+			task.abort();
+		}
+	}
+}
+
 pub async fn fsm_read_state<S: Clone>(state: &FsmArc<S>) -> S {
 	let state = state.read().await;
 	state.clone()
 }
 
+/// The name of the machine type, without its module path.
+pub fn fsm_machine_name<F: Fsm>() -> &'static str {
+	let name = std::any::type_name::<F>();
+	name.rsplit("::").next().unwrap_or(name)
+}
+
 #[async_trait]
 pub trait FsmRetrieveStateName {
 	async fn name(&self) -> String;
 }
 
+// for the current state of machines with orthogonal regions
+#[async_trait]
+impl<A, B> FsmRetrieveStateName for (A, B) where A: FsmRetrieveStateName + Sync, B: FsmRetrieveStateName + Sync {
+	async fn name(&self) -> String {
+		format!("({}, {})", self.0.name().await, self.1.name().await)
+	}
+}
+#[async_trait]
+impl<A, B, C> FsmRetrieveStateName for (A, B, C) where A: FsmRetrieveStateName + Sync, B: FsmRetrieveStateName + Sync, C: FsmRetrieveStateName + Sync {
+	async fn name(&self) -> String {
+		format!("({}, {}, {})", self.0.name().await, self.1.name().await, self.2.name().await)
+	}
+}
+#[async_trait]
+impl<A, B, C, D> FsmRetrieveStateName for (A, B, C, D) where A: FsmRetrieveStateName + Sync, B: FsmRetrieveStateName + Sync, C: FsmRetrieveStateName + Sync, D: FsmRetrieveStateName + Sync {
+	async fn name(&self) -> String {
+		format!("({}, {}, {}, {})", self.0.name().await, self.1.name().await, self.2.name().await, self.3.name().await)
+	}
+}
+#[async_trait]
+impl<A, B, C, D, E> FsmRetrieveStateName for (A, B, C, D, E) where A: FsmRetrieveStateName + Sync, B: FsmRetrieveStateName + Sync, C: FsmRetrieveStateName + Sync, D: FsmRetrieveStateName + Sync, E: FsmRetrieveStateName + Sync {
+	async fn name(&self) -> String {
+		format!("({}, {}, {}, {}, {})", self.0.name().await, self.1.name().await, self.2.name().await, self.3.name().await, self.4.name().await)
+	}
+}
+
 #[async_trait]
 pub trait Fsm where Self: Debug + Sized {
 	type E: FsmEvents<Self> + Debug;
@@ -260,19 +729,45 @@ pub trait Fsm where Self: Debug + Sized {
 	type SS: Send + Sync;
 
 	fn new(context: &FsmArc<Self::C>) -> Self;
+	fn new_with_queue(context: &FsmArc<Self::C>, queue: FsmArc<dyn FsmEventQueue<Self>>) -> Self;
+
+	/// Move the machine onto its own tokio task. It should be started beforehand.
+	fn spawn(self) -> FsmHandle<Self> where Self: Send + Sync + 'static, Self::E: Send {
+		FsmHandle::spawn(self)
+	}
 
 	async fn start(&self);
+	/// Start the machine in `states` instead of the initial states of their regions, one state for each region. The other
+	/// regions start in their initial states.
+	async fn start_at(&self, states: Vec<Self::S>);
 	async fn stop(&self);
 
 	async fn call_on_entry(&self, state: Self::S) -> FsmTransitionResult<()>;
 	async fn call_on_exit(&self, state: Self::S) -> FsmTransitionResult<()>;
 
+	/// Exit the active state of every region, innermost submachine states first.
+	async fn exit_current_states(&self) -> FsmTransitionResult<()>;
+	/// Re-enter the active state of every region without restarting the machine.
+	async fn resume(&self, history: FsmHistory) -> FsmTransitionResult<()>;
+
 	fn get_queue(&self) -> &FsmArc<dyn FsmEventQueue<Self>>;
+	fn get_deferred_queue(&self) -> &FsmArc<dyn FsmEventQueue<Self>>;
 
 	async fn get_current_state(&self) -> Self::CS;
+	/// Is `state` the active state of one of the regions?
+	async fn is_state_active(&self, state: &Self::S) -> bool;
 
 	fn get_states(&self) -> &Self::SS;
 
+	fn get_context(&self) -> &FsmArc<Self::C>;
+
+	/// Subscribe to the transitions of every region of the machine.
+	fn subscribe(&self) -> broadcast::Receiver<FsmTransitionNotification>;
+	/// Subscribe to the transitions of the machine and of its nested submachines.
+	fn subscribe_with_submachines(&self) -> broadcast::Receiver<FsmTransitionNotification>;
+	/// Forward the transitions of the machine and of its submachines to a parent machine.
+	fn forward_transitions(&self, sender: broadcast::Sender<FsmTransitionNotification>);
+
 	async fn process_anonymous_transitions(&self) -> Result<(), FsmError> {
 		loop {
 			match self.process_event(Self::E::new_no_event()).await {
@@ -288,6 +783,42 @@ pub trait Fsm where Self: Debug + Sized {
 
 	async fn process_event(&self, event: Self::E) -> Result<(), FsmError>;
 
+	/// Is every region of the machine in a final state?
+	async fn is_completed(&self) -> bool;
+
+	/// Set the hook that is invoked when the machine completes.
+	fn set_completion_hook(&self, hook: FsmCompletionHook);
+
+	/// Is the event deferred by any of the currently active states?
+	async fn is_event_deferred(&self, event: &Self::E) -> bool;
+
+	/// Re-dispatch the deferred events that are no longer deferred by the active states. Returns the first error
+	/// of the re-dispatched events, an event that still has no transition is dropped without an error.
+	async fn process_deferred_events(&self) -> Result<(), FsmError> {
+		let events = {
+			let mut queue = self.get_deferred_queue().write().await;
+			let mut events = Vec::with_capacity(queue.len());
+			while let Some(event) = queue.dequeue_event() {
+				events.push(event);
+			}
+			events
+		};
+
+		let mut res = Ok(());
+		for event in events {
+			if self.is_event_deferred(&event).await {
+				let _ = self.get_deferred_queue().write().await.enqueue_event(event);
+			} else {
+				match self.process_event(event).await {
+					Err(FsmError::NoTransition) | Ok(()) => (),
+					Err(error) => if res.is_ok() { res = Err(error); }
+				}
+			}
+		}
+
+		res
+	}
+
 	async fn execute_queued_events(&self) -> FsmQueueStatus {
 		{
 			let queue = self.get_queue();
@@ -333,18 +864,56 @@ pub trait Fsm where Self: Debug + Sized {
 
 pub struct InitialState<F: Fsm, S: FsmState<F>>(PhantomData<F>, S);
 pub struct ErrorState<F: Fsm, S: FsmState<F>>(PhantomData<F>, S);
+pub struct FinalState<F: Fsm, S: FsmState<F>>(PhantomData<F>, S);
 pub struct ContextType<T>(T);
 pub struct InspectionType<F: Fsm, T: FsmInspect<F>>(PhantomData<F>, T);
+pub struct QueueType<F: Fsm, Q: FsmEventQueue<F>>(PhantomData<F>, Q);
 pub struct SubMachine<F: Fsm>(F);
 pub struct ShallowHistory<F: Fsm, E: FsmEvent, StateTarget: FsmState<F> + Fsm>(PhantomData<F>, E, StateTarget);
+pub struct DeepHistory<F: Fsm, E: FsmEvent, StateTarget: FsmState<F> + Fsm>(PhantomData<F>, E, StateTarget);
+
+/// The target of a `Transition` or a `TransitionGuard` that enters the submachine `Sub` in its state `S`, instead of its initial state.
+pub struct EntryPoint<F: Fsm, Sub: FsmState<F> + Fsm, S: FsmState<Sub>>(PhantomData<F>, PhantomData<Sub>, PhantomData<S>);
+
+impl<F: Fsm, Sub: FsmState<F> + Fsm, S: FsmState<Sub>> Default for EntryPoint<F, Sub, S> {
+	fn default() -> Self {
+		EntryPoint(PhantomData, PhantomData, PhantomData)
+	}
+}
+
+#[async_trait]
+impl<F: Fsm, Sub: FsmState<F> + Fsm, S: FsmState<Sub>> FsmState<F> for EntryPoint<F, Sub, S> { }
 pub struct InterruptState<F: Fsm, S: FsmState<F>, E: FsmEvent>(PhantomData<F>, S, E);
+pub struct DeferredEvent<F: Fsm, S: FsmState<F>, E: FsmEvent>(PhantomData<F>, S, E);
+pub struct StateTimeout<F: Fsm, S: FsmState<F>, D: FsmDuration, E: FsmEvent + Default>(PhantomData<F>, S, D, E);
 pub struct CopyableEvents;
+/// Forwards the events to the active submachines. The machine's events must be `Clone`, and its events enum
+/// gets a variant per submachine that wraps the submachine's events.
+pub struct HierarchicalDispatch<F: Fsm, P: FsmDispatchPriority>(PhantomData<F>, P);
+
+/// The order in which a machine with `HierarchicalDispatch` and its active submachines get an event.
+pub trait FsmDispatchPriority {}
+
+/// The active submachine gets the event first, the machine's own transitions get the events it has no transition for.
+pub struct InnerFirst;
+impl FsmDispatchPriority for InnerFirst {}
+
+/// The machine's own transitions get the event first, the active submachine gets the events they don't handle.
+pub struct OuterFirst;
+impl FsmDispatchPriority for OuterFirst {}
 
 
 pub struct Transition<F: Fsm, StateSource: FsmState<F>, E: FsmEvent, StateTarget: FsmState<F>, A: FsmAction<F, StateSource, StateTarget>>(PhantomData<F>, StateSource, E, StateTarget, A);
 pub struct TransitionSelf<F: Fsm, State: FsmState<F>, E: FsmEvent, A: FsmActionSelf<F, State>>(PhantomData<F>, State, E, A);
 pub struct TransitionInternal<F: Fsm, State: FsmState<F>, E: FsmEvent, A: FsmActionSelf<F, State>>(PhantomData<F>, State, E, A);
 
-pub struct TransitionGuard<F: Fsm, StateSource: FsmState<F>, E: FsmEvent, StateTarget: FsmState<F>, A: FsmAction<F, StateSource, StateTarget>, G: FsmGuard<F>>(PhantomData<F>, StateSource, E, StateTarget, A, G);
-pub struct TransitionSelfGuard<F: Fsm, State: FsmState<F>, E: FsmEvent, A: FsmActionSelf<F, State>, G: FsmGuard<F>>(PhantomData<F>, State, E, A, G);
-pub struct TransitionInternalGuard<F: Fsm, State: FsmState<F>, E: FsmEvent, A: FsmActionSelf<F, State>, G: FsmGuard<F>>(PhantomData<F>, State, E, A, G);
+pub struct TransitionGuard<F: Fsm, StateSource: FsmState<F> + Send + Sync, E: FsmEvent, StateTarget: FsmState<F>, A: FsmAction<F, StateSource, StateTarget>, G: FsmGuardAsync<F, StateSource>>(PhantomData<F>, StateSource, E, StateTarget, A, G);
+pub struct TransitionSelfGuard<F: Fsm, State: FsmState<F> + Send + Sync, E: FsmEvent, A: FsmActionSelf<F, State>, G: FsmGuardAsync<F, State>>(PhantomData<F>, State, E, A, G);
+pub struct TransitionInternalGuard<F: Fsm, State: FsmState<F> + Send + Sync, E: FsmEvent, A: FsmActionSelf<F, State>, G: FsmGuardAsync<F, State>>(PhantomData<F>, State, E, A, G);
+
+/// Enters the submachine `Sub` from `StateSource` on the event, each of its regions in the state of the tuple `StateTargets`
+/// that belongs to it. Regions without a target start in their initial state.
+pub struct Fork<F: Fsm, StateSource: FsmState<F>, E: FsmEvent, Sub: FsmState<F> + Fsm, StateTargets>(PhantomData<F>, StateSource, E, PhantomData<Sub>, StateTargets);
+/// Leaves the submachine `Sub` for `StateTarget` on the event, but only when all the states of the tuple `StateSources`
+/// are active in its regions.
+pub struct Join<F: Fsm, Sub: FsmState<F> + Fsm, StateSources, E: FsmEvent, StateTarget: FsmState<F>>(PhantomData<F>, PhantomData<Sub>, StateSources, E, StateTarget);
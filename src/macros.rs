@@ -7,3 +7,16 @@ macro_rules! fsm_event_unit {
         impl FsmEvent for $event_name {}
     }
 }
+
+#[macro_export]
+macro_rules! fsm_duration {
+    ($duration_name:ident, $duration:expr) => {
+        #[derive(Copy, Clone, PartialEq, Default, Debug)]
+        pub struct $duration_name;
+        impl FsmDuration for $duration_name {
+            fn duration() -> std::time::Duration {
+                $duration
+            }
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::prelude::v1::*;
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::Mutex,
+	time::Duration
+};
+
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use crate::machine::*;
+
+/// The upper bounds of the dwell time histogram buckets, in seconds.
+pub const FSM_DWELL_TIME_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The metric families exported by the collectors, in the order they are written.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FsmMetricFamily {
+	Transitions,
+	RejectedEvents,
+	ErrorStateEntries,
+	StateDwellTime
+}
+
+impl FsmMetricFamily {
+	pub const ALL: [FsmMetricFamily; 4] = [
+		FsmMetricFamily::Transitions,
+		FsmMetricFamily::RejectedEvents,
+		FsmMetricFamily::ErrorStateEntries,
+		FsmMetricFamily::StateDwellTime
+	];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			FsmMetricFamily::Transitions => "fsm_transitions_total",
+			FsmMetricFamily::RejectedEvents => "fsm_rejected_events_total",
+			FsmMetricFamily::ErrorStateEntries => "fsm_error_state_entries_total",
+			FsmMetricFamily::StateDwellTime => "fsm_state_dwell_seconds"
+		}
+	}
+
+	fn help(&self) -> &'static str {
+		match self {
+			FsmMetricFamily::Transitions => "Transitions taken, by source state, event and target state.",
+			FsmMetricFamily::RejectedEvents => "Events that were not handled, by reason.",
+			FsmMetricFamily::ErrorStateEntries => "Entries into the error state.",
+			FsmMetricFamily::StateDwellTime => "Time spent in a state before leaving it."
+		}
+	}
+
+	fn kind(&self) -> &'static str {
+		match self {
+			FsmMetricFamily::StateDwellTime => "histogram",
+			_ => "counter"
+		}
+	}
+}
+
+/// A source of metric samples, so that the collectors of several machines can be exported together.
+pub trait FsmMetricsCollector {
+	/// Append the samples of a family in the Prometheus text format, without the HELP and TYPE lines.
+	fn write_samples(&self, family: FsmMetricFamily, out: &mut String);
+}
+
+/// Export the collectors in the Prometheus text exposition format.
+pub fn fsm_metrics_to_prometheus(collectors: &[&dyn FsmMetricsCollector]) -> String {
+	let mut out = String::new();
+
+	for family in FsmMetricFamily::ALL.iter() {
+		let _ = writeln!(out, "# HELP {} {}", family.name(), family.help());
+		let _ = writeln!(out, "# TYPE {} {}", family.name(), family.kind());
+
+		for collector in collectors {
+			collector.write_samples(*family, &mut out);
+		}
+	}
+
+	out
+}
+
+#[derive(Debug, Default)]
+struct FsmHistogram {
+	buckets: [u64; 11],
+	sum: f64,
+	count: u64
+}
+
+impl FsmHistogram {
+	fn observe(&mut self, value: f64) {
+		for (bucket, bound) in self.buckets.iter_mut().zip(FSM_DWELL_TIME_BUCKETS.iter()) {
+			if value <= *bound {
+				*bucket += 1;
+			}
+		}
+
+		self.sum += value;
+		self.count += 1;
+	}
+}
+
+#[derive(Debug, Default)]
+struct FsmMetricsData {
+	transitions: BTreeMap<(String, &'static str, String), u64>,
+	rejected_events: BTreeMap<(&'static str, &'static str), u64>,
+	error_state_entries: BTreeMap<String, u64>,
+	dwell_time: BTreeMap<String, FsmHistogram>,
+	/// By region and state, without the active states of a submachine state, which change while it is active.
+	entered: HashMap<(usize, String), Instant>
+}
+
+/// Collects transition counters and state dwell times of a machine, use it as
+/// `InspectionType<F, FsmInspectMetrics<F>>` and read it through the `get_inspection` method of the machine.
+///
+/// Events without a transition are counted once per region that had no transition for them,
+/// the anonymous transition attempts with a `NoEvent` are not counted.
+pub struct FsmInspectMetrics<F: Fsm> {
+	data: Mutex<FsmMetricsData>,
+	_fsm_ty: PhantomData<F>
+}
+
+impl<F: Fsm> FsmInspectMetrics<F> {
+	pub fn transitions(&self, source_state: &str, event: &str, target_state: &str) -> u64 {
+		self.data.lock().unwrap().transitions.iter()
+			.find(|((s, e, t), _)| s == source_state && *e == event && t == target_state)
+			.map_or(0, |(_, count)| *count)
+	}
+
+	pub fn rejected_events(&self, event: &str, error: &FsmError) -> u64 {
+		let reason = Self::rejection_reason(error);
+		self.data.lock().unwrap().rejected_events.iter()
+			.find(|((e, r), _)| *e == event && Some(*r) == reason)
+			.map_or(0, |(_, count)| *count)
+	}
+
+	pub fn error_state_entries(&self, state: &str) -> u64 {
+		self.data.lock().unwrap().error_state_entries.get(state).copied().unwrap_or(0)
+	}
+
+	/// The number of times the state was left and the total time spent in it.
+	pub fn dwell_time(&self, state: &str) -> (u64, Duration) {
+		self.data.lock().unwrap().dwell_time.get(state)
+			.map_or((0, Duration::from_secs(0)), |h| (h.count, Duration::from_secs_f64(h.sum)))
+	}
+
+	/// Export the metrics of this machine in the Prometheus text exposition format.
+	pub fn to_prometheus(&self) -> String {
+		fsm_metrics_to_prometheus(&[self])
+	}
+
+	fn rejection_reason(error: &FsmError) -> Option<&'static str> {
+		match error {
+			FsmError::NoTransition => Some("no_transition"),
+			FsmError::Interrupted => Some("interrupted"),
+			_ => None
+		}
+	}
+}
+
+impl<F: Fsm> FsmMetricsCollector for FsmInspectMetrics<F> {
+	fn write_samples(&self, family: FsmMetricFamily, out: &mut String) {
+		let data = self.data.lock().unwrap();
+		let machine = escape_label(fsm_machine_name::<F>());
+		let name = family.name();
+
+		match family {
+			FsmMetricFamily::Transitions => {
+				for ((source, event, target), count) in &data.transitions {
+					let _ = writeln!(out, "{}{{machine=\"{}\",source=\"{}\",event=\"{}\",target=\"{}\"}} {}",
+						name, machine, escape_label(source), event, escape_label(target), count);
+				}
+			},
+			FsmMetricFamily::RejectedEvents => {
+				for ((event, reason), count) in &data.rejected_events {
+					let _ = writeln!(out, "{}{{machine=\"{}\",event=\"{}\",reason=\"{}\"}} {}", name, machine, event, reason, count);
+				}
+			},
+			FsmMetricFamily::ErrorStateEntries => {
+				for (state, count) in &data.error_state_entries {
+					let _ = writeln!(out, "{}{{machine=\"{}\",state=\"{}\"}} {}", name, machine, escape_label(state), count);
+				}
+			},
+			FsmMetricFamily::StateDwellTime => {
+				for (state, histogram) in &data.dwell_time {
+					let state = escape_label(state);
+					for (count, bound) in histogram.buckets.iter().zip(FSM_DWELL_TIME_BUCKETS.iter()) {
+						let _ = writeln!(out, "{}_bucket{{machine=\"{}\",state=\"{}\",le=\"{}\"}} {}", name, machine, state, bound, count);
+					}
+					let _ = writeln!(out, "{}_bucket{{machine=\"{}\",state=\"{}\",le=\"+Inf\"}} {}", name, machine, state, histogram.count);
+					let _ = writeln!(out, "{}_sum{{machine=\"{}\",state=\"{}\"}} {}", name, machine, state, histogram.sum);
+					let _ = writeln!(out, "{}_count{{machine=\"{}\",state=\"{}\"}} {}", name, machine, state, histogram.count);
+				}
+			}
+		}
+	}
+}
+
+#[async_trait]
+impl<F> FsmInspect<F> for FsmInspectMetrics<F> where F: Fsm + Sync, F::S: FsmRetrieveStateName + Display + Sync {
+	fn new_from_context(_context: &FsmArc<F::C>) -> Self {
+		FsmInspectMetrics {
+			data: Mutex::new(FsmMetricsData::default()),
+			_fsm_ty: PhantomData
+		}
+	}
+
+	async fn on_state_entry(&self, state: &F::S, event_context: &EventContext<'_, F>) {
+		let name = state.name().await;
+		let mut data = self.data.lock().unwrap();
+
+		if event_context.event.name() == "FsmErrorEvent" {
+			*data.error_state_entries.entry(name).or_insert(0) += 1;
+		}
+		data.entered.insert((event_context.region, state.to_string()), Instant::now());
+	}
+
+	async fn on_state_exit(&self, state: &F::S, event_context: &EventContext<'_, F>) {
+		let state = state.to_string();
+		let mut data = self.data.lock().unwrap();
+
+		if let Some(entered) = data.entered.remove(&(event_context.region, state.clone())) {
+			let dwell_time = entered.elapsed().as_secs_f64();
+			data.dwell_time.entry(state).or_default().observe(dwell_time);
+		}
+	}
+
+	async fn on_transition(&self, source_state: &F::S, target_state: &F::S, event_context: &EventContext<'_, F>) {
+		let key = (source_state.name().await, event_context.event.name(), target_state.name().await);
+		*self.data.lock().unwrap().transitions.entry(key).or_insert(0) += 1;
+	}
+
+	async fn on_no_transition(&self, _current_state: &F::S, event_context: &EventContext<'_, F>) {
+		let event = event_context.event.name();
+		if event != "NoEvent" {
+			*self.data.lock().unwrap().rejected_events.entry((event, "no_transition")).or_insert(0) += 1;
+		}
+	}
+
+	async fn on_event_processed(&self, event_context: &EventContext<'_, F>, result: &Result<(), FsmError>) {
+		if let Err(FsmError::Interrupted) = result {
+			*self.data.lock().unwrap().rejected_events.entry((event_context.event.name(), "interrupted")).or_insert(0) += 1;
+		}
+	}
+}
+
+impl<F: Fsm> Debug for FsmInspectMetrics<F> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("FsmInspectMetrics").field("machine", &fsm_machine_name::<F>()).finish()
+	}
+}
+
+fn escape_label(value: &str) -> String {
+	value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
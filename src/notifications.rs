@@ -0,0 +1,68 @@
+use crate::prelude::v1::*;
+
+use std::sync::Mutex;
+
+use tokio::sync::broadcast;
+
+/// Sent to the subscribers of a machine after a transition that changed the active state of a region.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FsmTransitionNotification {
+	pub machine: &'static str,
+	pub region: usize,
+	pub source_state: String,
+	pub target_state: String,
+	pub event: &'static str
+}
+
+/// The subscribers of a machine. Subscribers that fall behind by more than the capacity of the
+/// channel skip the oldest notifications, see `tokio::sync::broadcast`.
+#[derive(Debug)]
+pub struct FsmNotifications {
+	transitions: broadcast::Sender<FsmTransitionNotification>,
+	nested_transitions: broadcast::Sender<FsmTransitionNotification>,
+	/// The channels of the parent machines, that also receive the transitions of this machine.
+	forwards: Mutex<Vec<broadcast::Sender<FsmTransitionNotification>>>
+}
+
+impl FsmNotifications {
+	pub fn new(capacity: usize) -> Self {
+		FsmNotifications {
+			transitions: broadcast::channel(capacity).0,
+			nested_transitions: broadcast::channel(capacity).0,
+			forwards: Mutex::new(Vec::new())
+		}
+	}
+
+	pub fn notify(&self, notification: FsmTransitionNotification) {
+		// sending only fails when there are no subscribers
+		let _ = self.transitions.send(notification.clone());
+		let _ = self.nested_transitions.send(notification.clone());
+
+		for forward in self.forwards.lock().unwrap().iter() {
+			let _ = forward.send(notification.clone());
+		}
+	}
+
+	pub fn subscribe(&self) -> broadcast::Receiver<FsmTransitionNotification> {
+		self.transitions.subscribe()
+	}
+
+	pub fn subscribe_with_submachines(&self) -> broadcast::Receiver<FsmTransitionNotification> {
+		self.nested_transitions.subscribe()
+	}
+
+	/// The channel that the submachines forward their transitions to.
+	pub fn nested_sender(&self) -> broadcast::Sender<FsmTransitionNotification> {
+		self.nested_transitions.clone()
+	}
+
+	pub fn forward_to(&self, sender: broadcast::Sender<FsmTransitionNotification>) {
+		self.forwards.lock().unwrap().push(sender);
+	}
+}
+
+impl Default for FsmNotifications {
+	fn default() -> Self {
+		FsmNotifications::new(64)
+	}
+}
@@ -0,0 +1,243 @@
+use crate::prelude::v1::*;
+
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+use crate::machine::*;
+
+/// A transition taken while processing a recorded event.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FsmRecordedTransition {
+	pub region: usize,
+	pub source: String,
+	pub target: String
+}
+
+/// A processed event, or the start of the machine when there is no event.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(bound = "E: Serialize + DeserializeOwned")]
+pub struct FsmRecordedStep<E> {
+	/// The nesting of the event, the events of the error state are processed within the failed event.
+	pub depth: usize,
+	pub event: Option<E>,
+	pub transitions: Vec<FsmRecordedTransition>,
+	pub error: Option<String>,
+	/// The active state afterwards.
+	pub state: String
+}
+
+impl<E: Serialize> FsmRecordedStep<E> {
+	fn matches(&self, other: &FsmRecordedStep<E>) -> bool {
+		self.depth == other.depth &&
+			self.transitions == other.transitions &&
+			self.error == other.error &&
+			self.state == other.state &&
+			serde_json::to_value(&self.event).ok() == serde_json::to_value(&other.event).ok()
+	}
+}
+
+/// The steps captured by `FsmInspectRecorder`, stored as one JSON object per line.
+#[derive(Clone, Debug)]
+pub struct FsmRecording<E> {
+	pub steps: Vec<FsmRecordedStep<E>>
+}
+
+impl<E> FsmRecording<E> where E: Clone + Serialize + DeserializeOwned {
+	pub fn to_json_lines(&self) -> serde_json::Result<String> {
+		let mut lines = String::new();
+		for step in &self.steps {
+			lines.push_str(&serde_json::to_string(step)?);
+			lines.push('\n');
+		}
+
+		Ok(lines)
+	}
+
+	pub fn from_json_lines(lines: &str) -> serde_json::Result<Self> {
+		let steps = lines.lines()
+			.filter(|line| !line.trim().is_empty())
+			.map(serde_json::from_str)
+			.collect::<Result<_, _>>()?;
+
+		Ok(FsmRecording { steps })
+	}
+
+	pub fn write_json_lines<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+		writer.write_all(self.to_json_lines()?.as_bytes())
+	}
+
+	pub fn read_json_lines<R: io::Read>(mut reader: R) -> io::Result<Self> {
+		let mut lines = String::new();
+		reader.read_to_string(&mut lines)?;
+
+		Ok(Self::from_json_lines(&lines)?)
+	}
+
+	/// Feed the recorded events into a fresh machine that records itself with `recorder`, usually
+	/// `fsm.get_inspection()`. The machine is started when the recording starts with its start.
+	///
+	/// Events that the machine queues itself are executed from its queue rather than fed again.
+	pub async fn replay<F>(&self, fsm: &F, recorder: &FsmInspectRecorder<F>) -> Result<(), FsmReplayDivergence<E>>
+		where F: Fsm<E = E> + Sync, F::S: FsmRetrieveStateName + Sync, F::CS: FsmRetrieveStateName + Sync
+	{
+		loop {
+			let observed = recorder.recording().steps;
+			let diverged = observed.iter().zip(self.steps.iter()).position(|(o, e)| !o.matches(e));
+
+			if let Some(step) = diverged {
+				return Err(self.divergence(step, &observed));
+			}
+			if observed.len() >= self.steps.len() {
+				return match observed.len() == self.steps.len() {
+					true => Ok(()),
+					false => Err(self.divergence(self.steps.len(), &observed))
+				};
+			}
+
+			let next = &self.steps[observed.len()];
+
+			// a failing queued event would panic in `execute_queued_events`
+			let queued = fsm.get_queue().write().await.dequeue_event();
+
+			if let Some(event) = queued {
+				let _ = fsm.process_event(event).await;
+			} else if next.depth > 0 {
+				// the nested event of the recording wasn't processed
+				return Err(self.divergence(observed.len(), &observed));
+			} else {
+				match next.event {
+					None => fsm.start().await,
+					Some(ref event) => { let _ = fsm.process_event(event.clone()).await; }
+				}
+			}
+
+			if recorder.recording().steps.len() == observed.len() {
+				return Err(self.divergence(observed.len(), &observed));
+			}
+		}
+	}
+
+	fn divergence(&self, step: usize, observed: &[FsmRecordedStep<E>]) -> FsmReplayDivergence<E> {
+		FsmReplayDivergence {
+			step,
+			expected: self.steps.get(step).cloned(),
+			observed: observed.get(step).cloned()
+		}
+	}
+}
+
+/// The first step where a replay differs from the recording. A missing step means that one
+/// side stopped before the other.
+#[derive(Clone, Debug)]
+pub struct FsmReplayDivergence<E> {
+	pub step: usize,
+	pub expected: Option<FsmRecordedStep<E>>,
+	pub observed: Option<FsmRecordedStep<E>>
+}
+
+impl<E: Debug> fmt::Display for FsmReplayDivergence<E> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "The replay diverged at step {}, expected {:?}, observed {:?}", self.step, self.expected, self.observed)
+	}
+}
+
+impl<E: Debug> std::error::Error for FsmReplayDivergence<E> {}
+
+struct FsmRecorderState<E> {
+	steps: Vec<FsmRecordedStep<E>>,
+	/// The steps of the events that are being processed.
+	open: Vec<usize>
+}
+
+/// Records the processed events of a machine with their transitions, use it as
+/// `InspectionType<F, FsmInspectRecorder<F>>` on a machine that declares `Snapshot`.
+pub struct FsmInspectRecorder<F: Fsm> {
+	state: Mutex<FsmRecorderState<F::E>>
+}
+
+impl<F: Fsm> FsmInspectRecorder<F> where F::E: Clone {
+	pub fn recording(&self) -> FsmRecording<F::E> {
+		FsmRecording {
+			steps: self.state.lock().unwrap().steps.clone()
+		}
+	}
+
+	pub fn clear(&self) {
+		self.state.lock().unwrap().steps.clear();
+	}
+}
+
+#[async_trait]
+impl<F> FsmInspect<F> for FsmInspectRecorder<F>
+	where F: Fsm + Sync, F::E: Clone, F::S: FsmRetrieveStateName + Sync, F::CS: FsmRetrieveStateName + Sync
+{
+	fn new_from_context(_context: &FsmArc<F::C>) -> Self {
+		FsmInspectRecorder {
+			state: Mutex::new(FsmRecorderState { steps: Vec::new(), open: Vec::new() })
+		}
+	}
+
+	async fn on_event(&self, event_context: &EventContext<'_, F>) {
+		let state = event_context.current_state.name().await;
+		let mut recorder = self.state.lock().unwrap();
+
+		let step = FsmRecordedStep {
+			depth: recorder.open.len(),
+			event: Some(event_context.event.clone()),
+			transitions: Vec::new(),
+			error: None,
+			state
+		};
+
+		recorder.steps.push(step);
+		let index = recorder.steps.len() - 1;
+		recorder.open.push(index);
+	}
+
+	async fn on_event_processed(&self, event_context: &EventContext<'_, F>, result: &Result<(), FsmError>) {
+		let state = event_context.current_state.name().await;
+		let mut recorder = self.state.lock().unwrap();
+
+		if let Some(index) = recorder.open.pop() {
+			let step = &mut recorder.steps[index];
+			step.error = result.as_ref().err().map(|e| format!("{:?}", e));
+			step.state = state;
+		}
+	}
+
+	async fn on_transition(&self, source_state: &F::S, target_state: &F::S, event_context: &EventContext<'_, F>) {
+		let transition = FsmRecordedTransition {
+			region: event_context.region,
+			source: source_state.name().await,
+			target: target_state.name().await
+		};
+
+		let mut recorder = self.state.lock().unwrap();
+		if let Some(&index) = recorder.open.last() {
+			recorder.steps[index].transitions.push(transition);
+		}
+	}
+
+	async fn on_state_entry(&self, _state: &F::S, event_context: &EventContext<'_, F>) {
+		let state = event_context.current_state.name().await;
+		let mut recorder = self.state.lock().unwrap();
+
+		// the initial states are entered outside of an event, once per region
+		if !recorder.open.is_empty() {
+			return;
+		}
+
+		match recorder.steps.last_mut() {
+			Some(step) if step.event.is_none() => step.state = state,
+			_ => recorder.steps.push(FsmRecordedStep { depth: 0, event: None, transitions: Vec::new(), error: None, state })
+		}
+	}
+}
+
+impl<F: Fsm> Debug for FsmInspectRecorder<F> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("FsmInspectRecorder").field("steps", &self.state.lock().unwrap().steps.len()).finish()
+	}
+}
@@ -0,0 +1,102 @@
+use crate::prelude::v1::*;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Serialize, Serializer, Deserialize, Deserializer, de::DeserializeOwned};
+
+use crate::machine::*;
+
+/// Persist a running machine and restore it later. Implemented by machines that declare `Snapshot`.
+#[async_trait]
+pub trait FsmSnapshot: Fsm {
+	type Snapshot: Serialize + DeserializeOwned + Debug + Send + Sync;
+
+	/// Capture the active states, the state data and the queued events, including the ones of the submachines.
+	async fn snapshot(&self) -> Self::Snapshot;
+
+	/// Restore a snapshot into a machine that wasn't started. `on_entry` isn't invoked for the restored states.
+	async fn restore(&self, snapshot: Self::Snapshot) -> Result<(), FsmSnapshotError>;
+
+	async fn from_snapshot(context: &FsmArc<Self::C>, snapshot: Self::Snapshot) -> Result<Self, FsmSnapshotError> where Self: Sized {
+		let fsm = Self::new(context);
+		fsm.restore(snapshot).await?;
+
+		Ok(fsm)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FsmSnapshotError {
+	/// The snapshot has a different number of regions than the machine.
+	RegionMismatch,
+	/// The snapshot refers to a state that the machine doesn't have.
+	UnknownState(String),
+	/// The machine's queue has no room for the queued events of the snapshot.
+	QueueFull
+}
+
+impl fmt::Display for FsmSnapshotError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:?}", self)
+	}
+}
+
+impl std::error::Error for FsmSnapshotError {}
+
+impl Serialize for NoEvent {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_unit_struct("NoEvent")
+	}
+}
+
+impl<'de> Deserialize<'de> for NoEvent {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		<()>::deserialize(deserializer)?;
+		Ok(NoEvent)
+	}
+}
+
+impl<F: Fsm> Serialize for Completed<F> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_unit_struct("Completed")
+	}
+}
+
+impl<'de, F: Fsm> Deserialize<'de> for Completed<F> {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		<()>::deserialize(deserializer)?;
+		Ok(Completed::new())
+	}
+}
+
+/// Errors are stored as their message.
+impl Serialize for FsmErrorEvent {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&self.0.to_string())
+	}
+}
+
+impl<'de> Deserialize<'de> for FsmErrorEvent {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let message = String::deserialize(deserializer)?;
+		Ok(FsmErrorEvent(FsmTransitionError(Arc::new(anyhow::anyhow!(message)))))
+	}
+}
+
+/// The timers are started again on restore, a queued timeout event is stale afterwards.
+impl Serialize for FsmTimeoutEvent {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		(self.timeout, self.entry).serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for FsmTimeoutEvent {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let (timeout, entry) = <(usize, u64)>::deserialize(deserializer)?;
+		Ok(FsmTimeoutEvent { timeout, entry })
+	}
+}
+
+/// Declares that the machine supports snapshots, see `FsmSnapshot`.
+pub struct Snapshot;
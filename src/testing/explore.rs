@@ -0,0 +1,257 @@
+use crate::prelude::v1::*;
+
+use std::collections::BTreeSet;
+
+use crate::machine::*;
+
+/// A small deterministic random number generator (SplitMix64), so that a seed reproduces a walk.
+#[derive(Clone, Debug)]
+pub struct FsmRandom(u64);
+
+impl FsmRandom {
+	pub fn new(seed: u64) -> Self {
+		FsmRandom(seed)
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		z ^ (z >> 31)
+	}
+
+	/// A number in `0..n`, `n` has to be positive.
+	pub fn below(&mut self, n: u64) -> u64 {
+		self.next_u64() % n
+	}
+
+	pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+		if items.is_empty() {
+			return None;
+		}
+
+		items.get(self.below(items.len() as u64) as usize)
+	}
+}
+
+type FsmInvariant<F> = Box<dyn Fn(&<F as Fsm>::C, &<F as Fsm>::CS) -> Result<(), String> + Send + Sync>;
+
+type FsmEventGenerator<F> = Box<dyn Fn(&'static str, &mut FsmRandom) -> Option<<F as Fsm>::E> + Send + Sync>;
+
+/// A step of a walk, the event is generated from its variant name and its own seed.
+type FsmWalkStep = (&'static str, u64);
+
+struct FsmViolation {
+	step: usize,
+	invariant: String,
+	message: String,
+	state: String,
+	events: Vec<String>
+}
+
+/// Drives fresh machines through random walks of events and checks the invariants after each step.
+///
+/// The events are picked uniformly from `FsmEvents::variants`, the generator builds the event of a
+/// variant or returns `None` to skip it. A failing walk is shrunk to a minimal sequence of events
+/// that still violates the same invariant.
+pub struct FsmExplorer<F: Fsm> {
+	factory: Box<dyn Fn() -> F + Send + Sync>,
+	generator: FsmEventGenerator<F>,
+	invariants: Vec<(String, FsmInvariant<F>)>,
+	walks: usize,
+	steps: usize,
+	seed: u64
+}
+
+impl<F> FsmExplorer<F> where F: Fsm + Sync, F::E: Debug, F::CS: FsmRetrieveStateName + Sync {
+	pub fn new<M, G>(factory: M, generator: G) -> Self
+		where M: Fn() -> F + Send + Sync + 'static, G: Fn(&'static str, &mut FsmRandom) -> Option<F::E> + Send + Sync + 'static
+	{
+		FsmExplorer {
+			factory: Box::new(factory),
+			generator: Box::new(generator),
+			invariants: Vec::new(),
+			walks: 100,
+			steps: 100,
+			seed: 0
+		}
+	}
+
+	/// Checked against the context and the current state after the start and after every event.
+	pub fn invariant<I>(mut self, name: &str, invariant: I) -> Self
+		where I: Fn(&F::C, &F::CS) -> Result<(), String> + Send + Sync + 'static
+	{
+		self.invariants.push((name.to_string(), Box::new(invariant)));
+		self
+	}
+
+	pub fn walks(mut self, walks: usize) -> Self {
+		self.walks = walks;
+		self
+	}
+
+	pub fn steps(mut self, steps: usize) -> Self {
+		self.steps = steps;
+		self
+	}
+
+	pub fn seed(mut self, seed: u64) -> Self {
+		self.seed = seed;
+		self
+	}
+
+	pub async fn run(&self) -> Result<FsmExplorationStats, FsmExplorationFailure> {
+		let mut random = FsmRandom::new(self.seed);
+		let mut stats = FsmExplorationStats::default();
+
+		for walk in 0..self.walks {
+			let plan: Vec<FsmWalkStep> = (0..self.steps)
+				.filter_map(|_| random.choose(F::E::variants()).map(|name| (*name, random.next_u64())))
+				.collect();
+
+			if let Some(violation) = self.walk(&plan, Some(&mut stats)).await {
+				let violation = self.shrink(plan, violation).await;
+
+				return Err(FsmExplorationFailure {
+					seed: self.seed,
+					walk,
+					invariant: violation.invariant,
+					message: violation.message,
+					state: violation.state,
+					events: violation.events
+				});
+			}
+		}
+
+		Ok(stats)
+	}
+
+	async fn walk(&self, plan: &[FsmWalkStep], mut stats: Option<&mut FsmExplorationStats>) -> Option<FsmViolation> {
+		let fsm = (self.factory)();
+		fsm.start().await;
+
+		let mut events = Vec::new();
+		if let Some(violation) = self.check(&fsm, 0, &events, &mut stats).await {
+			return Some(violation);
+		}
+
+		for (i, (name, seed)) in plan.iter().enumerate() {
+			let event = match (self.generator)(name, &mut FsmRandom::new(*seed)) {
+				Some(event) => event,
+				None => continue
+			};
+
+			events.push(format!("{:?}", event));
+			let result = fsm.process_event(event).await;
+
+			if let Some(ref mut stats) = stats {
+				stats.steps += 1;
+				if result.is_err() {
+					stats.rejected += 1;
+				}
+			}
+
+			if let Some(violation) = self.check(&fsm, i + 1, &events, &mut stats).await {
+				return Some(violation);
+			}
+		}
+
+		None
+	}
+
+	async fn check(&self, fsm: &F, step: usize, events: &[String], stats: &mut Option<&mut FsmExplorationStats>) -> Option<FsmViolation> {
+		let current_state = fsm.get_current_state().await;
+		let state = current_state.name().await;
+
+		if let Some(ref mut stats) = stats {
+			stats.visited_states.insert(state.clone());
+		}
+
+		let context = fsm.get_context().read().await;
+		for (name, invariant) in &self.invariants {
+			if let Err(message) = invariant(&context, &current_state) {
+				return Some(FsmViolation {
+					step,
+					invariant: name.clone(),
+					message,
+					state,
+					events: events.to_vec()
+				});
+			}
+		}
+
+		None
+	}
+
+	/// Remove chunks of the walk, halving their size, as long as the same invariant is violated.
+	async fn shrink(&self, mut plan: Vec<FsmWalkStep>, mut violation: FsmViolation) -> FsmViolation {
+		plan.truncate(violation.step);
+		let mut chunk = max(plan.len() / 2, 1);
+
+		while !plan.is_empty() {
+			let mut i = 0;
+			let mut removed = false;
+
+			while i < plan.len() {
+				let mut candidate = plan.clone();
+				candidate.drain(i..min(i + chunk, plan.len()));
+
+				match self.walk(&candidate, None).await {
+					Some(v) if v.invariant == violation.invariant => {
+						plan = candidate;
+						plan.truncate(v.step);
+						violation = v;
+						removed = true;
+					},
+					_ => i += chunk
+				}
+			}
+
+			if chunk == 1 && !removed {
+				break;
+			}
+			if !removed {
+				chunk = max(chunk / 2, 1);
+			}
+		}
+
+		violation
+	}
+}
+
+/// What the random walks covered.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FsmExplorationStats {
+	/// The processed events.
+	pub steps: usize,
+	/// The events that returned an error, e.g. `NoTransition`.
+	pub rejected: usize,
+	pub visited_states: BTreeSet<String>
+}
+
+/// A violated invariant, with the shrunk sequence of events that reproduces it from the start of the machine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FsmExplorationFailure {
+	pub seed: u64,
+	pub walk: usize,
+	pub invariant: String,
+	pub message: String,
+	/// The active state when the invariant was violated.
+	pub state: String,
+	pub events: Vec<String>
+}
+
+impl fmt::Display for FsmExplorationFailure {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "Invariant '{}' violated in state {}: {}", self.invariant, self.state, self.message)?;
+		writeln!(f, "Reproduced by the events (seed {}, walk {}):", self.seed, self.walk)?;
+		for event in &self.events {
+			writeln!(f, "  {}", event)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl std::error::Error for FsmExplorationFailure {}
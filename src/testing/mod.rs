@@ -0,0 +1,7 @@
+//! Helpers for testing machines, behind the `testing` feature.
+
+mod explore;
+mod scenario;
+
+pub use explore::*;
+pub use scenario::*;
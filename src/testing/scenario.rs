@@ -0,0 +1,275 @@
+use crate::prelude::v1::*;
+
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::machine::*;
+
+/// A state hook that was invoked on a machine, see `FsmInspectHooks`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FsmHook {
+	Entry(String),
+	Exit(String),
+	/// The action of a transition out of the state.
+	Action(String)
+}
+
+impl FsmHook {
+	pub fn entry(state: &str) -> Self {
+		FsmHook::Entry(state.to_string())
+	}
+
+	pub fn exit(state: &str) -> Self {
+		FsmHook::Exit(state.to_string())
+	}
+
+	pub fn action(state: &str) -> Self {
+		FsmHook::Action(state.to_string())
+	}
+}
+
+impl fmt::Display for FsmHook {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			FsmHook::Entry(state) => write!(f, "entry {}", state),
+			FsmHook::Exit(state) => write!(f, "exit {}", state),
+			FsmHook::Action(state) => write!(f, "action {}", state)
+		}
+	}
+}
+
+/// Logs the state hooks of a machine in order, use it as `InspectionType<F, FsmInspectHooks<F>>`
+/// for the machines driven by `FsmScenario`.
+pub struct FsmInspectHooks<F: Fsm> {
+	hooks: Mutex<Vec<FsmHook>>,
+	_fsm_ty: PhantomData<F>
+}
+
+impl<F: Fsm> FsmInspectHooks<F> {
+	/// The hooks invoked since the last call.
+	pub fn take(&self) -> Vec<FsmHook> {
+		mem::take(&mut *self.hooks.lock().unwrap())
+	}
+
+	fn push(&self, hook: FsmHook) {
+		self.hooks.lock().unwrap().push(hook);
+	}
+}
+
+#[async_trait]
+impl<F> FsmInspect<F> for FsmInspectHooks<F> where F: Fsm + Sync, F::S: FsmRetrieveStateName + Sync {
+	fn new_from_context(_context: &FsmArc<F::C>) -> Self {
+		FsmInspectHooks {
+			hooks: Mutex::new(Vec::new()),
+			_fsm_ty: PhantomData
+		}
+	}
+
+	async fn on_state_entry(&self, state: &F::S, _event_context: &EventContext<'_, F>) {
+		self.push(FsmHook::Entry(state.name().await));
+	}
+
+	async fn on_state_exit(&self, state: &F::S, _event_context: &EventContext<'_, F>) {
+		self.push(FsmHook::Exit(state.name().await));
+	}
+
+	async fn on_action(&self, state: &F::S, _event_context: &EventContext<'_, F>) {
+		self.push(FsmHook::Action(state.name().await));
+	}
+}
+
+impl<F: Fsm> Debug for FsmInspectHooks<F> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("FsmInspectHooks").field("hooks", &self.hooks.lock().unwrap()).finish()
+	}
+}
+
+enum FsmScenarioInput<F: Fsm> {
+	Start,
+	Event(F::E)
+}
+
+struct FsmScenarioStep<F: Fsm> {
+	input: FsmScenarioInput<F>,
+	state: Option<String>,
+	result: Result<(), FsmError>,
+	hooks: Option<Vec<FsmHook>>
+}
+
+/// A script of events to drive a machine through, with the expectations after each of them.
+///
+/// The expectations apply to the last added step. The states are compared by their names, a
+/// machine with orthogonal regions is named like `(StateA, StateB)`. Each step is expected to
+/// succeed unless stated otherwise, the hooks are only compared when they are given.
+pub struct FsmScenario<F: Fsm> {
+	steps: Vec<FsmScenarioStep<F>>
+}
+
+impl<F> FsmScenario<F> where F: Fsm + Sync, F::S: FsmRetrieveStateName + Sync, F::CS: FsmRetrieveStateName + Sync {
+	pub fn new() -> Self {
+		FsmScenario { steps: Vec::new() }
+	}
+
+	/// Start the machine.
+	pub fn start(self) -> Self {
+		self.push(FsmScenarioInput::Start)
+	}
+
+	pub fn event(self, event: F::E) -> Self {
+		self.push(FsmScenarioInput::Event(event))
+	}
+
+	pub fn expect_state(mut self, state: &str) -> Self {
+		self.last().state = Some(state.to_string());
+		self
+	}
+
+	pub fn expect_result(mut self, result: Result<(), FsmError>) -> Self {
+		self.last().result = result;
+		self
+	}
+
+	pub fn expect_no_transition(self) -> Self {
+		self.expect_result(Err(FsmError::NoTransition))
+	}
+
+	pub fn expect_interrupted(self) -> Self {
+		self.expect_result(Err(FsmError::Interrupted))
+	}
+
+	/// The hooks that the step invokes, in order.
+	pub fn expect_hooks(mut self, hooks: &[FsmHook]) -> Self {
+		self.last().hooks = Some(hooks.to_vec());
+		self
+	}
+
+	/// Run the scenario and panic with a diff of the first failing step.
+	pub async fn run(self, fsm: &F, hooks: &FsmInspectHooks<F>) {
+		if let Err(failure) = self.try_run(fsm, hooks).await {
+			panic!("{}", failure);
+		}
+	}
+
+	pub async fn try_run(self, fsm: &F, hooks: &FsmInspectHooks<F>) -> Result<(), FsmScenarioFailure> {
+		hooks.take();
+
+		for (i, step) in self.steps.into_iter().enumerate() {
+			let (input, result) = match step.input {
+				FsmScenarioInput::Start => {
+					fsm.start().await;
+					("start".to_string(), Ok(()))
+				},
+				FsmScenarioInput::Event(event) => {
+					let name = format!("event {}", event.name());
+					(name, fsm.process_event(event).await)
+				}
+			};
+
+			let mut failure = FsmScenarioFailure {
+				step: i,
+				input,
+				mismatches: Vec::new()
+			};
+
+			if result != step.result {
+				failure.mismatches.push(format!("result:\n  - {:?}\n  + {:?}", step.result, result));
+			}
+
+			if let Some(expected) = step.state {
+				let state = fsm.get_current_state().await.name().await;
+				if state != expected {
+					failure.mismatches.push(format!("state:\n  - {}\n  + {}", expected, state));
+				}
+			}
+
+			let invoked = hooks.take();
+			if let Some(expected) = step.hooks {
+				if invoked != expected {
+					failure.mismatches.push(format!("hooks:\n{}", diff_lines(&expected, &invoked)));
+				}
+			}
+
+			if !failure.mismatches.is_empty() {
+				return Err(failure);
+			}
+		}
+
+		Ok(())
+	}
+
+	fn push(mut self, input: FsmScenarioInput<F>) -> Self {
+		self.steps.push(FsmScenarioStep {
+			input,
+			state: None,
+			result: Ok(()),
+			hooks: None
+		});
+		self
+	}
+
+	fn last(&mut self) -> &mut FsmScenarioStep<F> {
+		self.steps.last_mut().expect("Add a step to the scenario before its expectations")
+	}
+}
+
+impl<F> Default for FsmScenario<F> where F: Fsm + Sync, F::S: FsmRetrieveStateName + Sync, F::CS: FsmRetrieveStateName + Sync {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// The first step of a scenario that didn't meet its expectations. Displayed as a diff, the
+/// expected lines are prefixed with `-` and the observed ones with `+`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FsmScenarioFailure {
+	pub step: usize,
+	pub input: String,
+	pub mismatches: Vec<String>
+}
+
+impl fmt::Display for FsmScenarioFailure {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "Scenario step {} ({}) failed", self.step, self.input)?;
+		for mismatch in &self.mismatches {
+			writeln!(f, "{}", mismatch)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl std::error::Error for FsmScenarioFailure {}
+
+/// A line diff based on the longest common subsequence.
+fn diff_lines<T: Display + PartialEq>(expected: &[T], observed: &[T]) -> String {
+	let (n, m) = (expected.len(), observed.len());
+	let mut lcs = vec![vec![0; m + 1]; n + 1];
+	for i in (0..n).rev() {
+		for j in (0..m).rev() {
+			lcs[i][j] = if expected[i] == observed[j] {
+				lcs[i + 1][j + 1] + 1
+			} else {
+				max(lcs[i + 1][j], lcs[i][j + 1])
+			};
+		}
+	}
+
+	let mut lines = Vec::new();
+	let (mut i, mut j) = (0, 0);
+	while i < n || j < m {
+		if i < n && j < m && expected[i] == observed[j] {
+			lines.push(format!("    {}", expected[i]));
+			i += 1;
+			j += 1;
+		} else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+			lines.push(format!("  - {}", expected[i]));
+			i += 1;
+		} else {
+			lines.push(format!("  + {}", observed[j]));
+			j += 1;
+		}
+	}
+
+	lines.join("\n")
+}